@@ -0,0 +1,158 @@
+//! MemTable 和 ShardedTable 的性能对比，以及创建快照的开销
+//!
+//! cargo bench --bench storage
+
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use kv::{KvPair, MemTable, ShardedTable, Storage};
+
+const KEYS: usize = 1000;
+const THREADS: usize = 4;
+
+fn keys() -> Vec<String> {
+    (0..KEYS).map(|i| format!("key{}", i)).collect()
+}
+
+fn fill(store: &impl Storage) {
+    for (i, key) in keys().into_iter().enumerate() {
+        store.set("t1", key, (i as i64).into()).unwrap();
+    }
+}
+
+// 单线程下逐个 set/get
+fn single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_thread");
+    let keys = keys();
+
+    let mem = MemTable::new();
+    let sharded = ShardedTable::default();
+    fill(&mem);
+    fill(&sharded);
+
+    group.bench_function(BenchmarkId::new("get", "MemTable"), |b| {
+        b.iter(|| keys.iter().for_each(|k| drop(black_box(mem.get("t1", k)))))
+    });
+    group.bench_function(BenchmarkId::new("get", "ShardedTable"), |b| {
+        b.iter(|| {
+            keys.iter()
+                .for_each(|k| drop(black_box(sharded.get("t1", k))))
+        })
+    });
+    group.bench_function(BenchmarkId::new("set", "MemTable"), |b| {
+        b.iter(|| {
+            keys.iter()
+                .for_each(|k| drop(black_box(mem.set("t1", k.clone(), 1.into()))))
+        })
+    });
+    group.bench_function(BenchmarkId::new("set", "ShardedTable"), |b| {
+        b.iter(|| {
+            keys.iter()
+                .for_each(|k| drop(black_box(sharded.set("t1", k.clone(), 1.into()))))
+        })
+    });
+    group.finish();
+}
+
+// 多 key 命令：ShardedTable 会把 key 分派到多个 shard 并行处理
+fn multi_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("multi_key");
+    let keys = keys();
+    let pairs: Vec<_> = keys.iter().map(|k| KvPair::new(k, 1.into())).collect();
+
+    let mem = MemTable::new();
+    let sharded = ShardedTable::default();
+
+    group.bench_function(BenchmarkId::new("set_many", "MemTable"), |b| {
+        b.iter(|| black_box(mem.set_many("t1", pairs.clone())))
+    });
+    group.bench_function(BenchmarkId::new("set_many", "ShardedTable"), |b| {
+        b.iter(|| black_box(sharded.set_many("t1", pairs.clone())))
+    });
+    group.bench_function(BenchmarkId::new("get_many", "MemTable"), |b| {
+        b.iter(|| black_box(mem.get_many("t1", keys.clone())))
+    });
+    group.bench_function(BenchmarkId::new("get_many", "ShardedTable"), |b| {
+        b.iter(|| black_box(sharded.get_many("t1", keys.clone())))
+    });
+    group.finish();
+}
+
+// 多线程并发读写同一个 table
+fn concurrent(c: &mut Criterion) {
+    fn run(store: &(impl Storage + Sync)) {
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                s.spawn(move || {
+                    for i in 0..KEYS {
+                        let key = format!("key{}", (i + t) % KEYS);
+                        if i % 4 == 0 {
+                            store.set("t1", key, (i as i64).into()).unwrap();
+                        } else {
+                            black_box(store.get("t1", &key).unwrap());
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    let mut group = c.benchmark_group("concurrent");
+
+    let mem = MemTable::new();
+    let sharded = ShardedTable::default();
+    fill(&mem);
+    fill(&sharded);
+
+    group.bench_function("MemTable", |b| b.iter(|| run(&mem)));
+    group.bench_function("ShardedTable", |b| b.iter(|| run(&sharded)));
+    group.finish();
+}
+
+// 快照复制所有数据，但只在开始和结束时短暂持有写锁，复制期间的写入不会被阻塞
+fn snapshot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot");
+    let keys = keys();
+
+    let mem = MemTable::new();
+    for i in 0..100 {
+        let pairs = keys
+            .iter()
+            .map(|k| KvPair::new(format!("{}-{}", k, i), 1.into()))
+            .collect();
+        mem.set_many("t1", pairs).unwrap();
+    }
+
+    group.bench_function("snapshot", |b| {
+        b.iter(|| black_box(mem.snapshot().unwrap()))
+    });
+    group.bench_function("set", |b| {
+        b.iter(|| {
+            keys.iter()
+                .for_each(|k| drop(black_box(mem.set("t2", k.clone(), 1.into()))))
+        })
+    });
+    group.bench_function("set_during_snapshot", |b| {
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    black_box(mem.snapshot().unwrap());
+                }
+            });
+            b.iter(|| {
+                keys.iter()
+                    .for_each(|k| drop(black_box(mem.set("t2", k.clone(), 1.into()))))
+            });
+            done.store(true, Ordering::Relaxed);
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, single_thread, multi_key, concurrent, snapshot);
+criterion_main!(benches);
@@ -1,7 +1,25 @@
 fn main() {
-    prost_build::Config::new()
+    let mut config = prost_build::Config::new();
+
+    // prost 生成的 enum 自带 PartialOrd，只给 message 和 oneof 加
+    config
         .bytes(["."])
-        .type_attribute(".", "#[derive(PartialOrd)]")
+        .message_attribute(".", "#[derive(PartialOrd)]")
+        .enum_attribute(".abi.CommandRequest.request_data", "#[derive(PartialOrd)]")
+        .enum_attribute(".abi.Value.value", "#[derive(PartialOrd)]")
+        .enum_attribute(".abi.DumpFrame.frame", "#[derive(PartialOrd)]");
+
+    // Value 使用自定义的 JSON 映射（见 src/json.rs），其它类型直接 derive
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        config
+            .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+            .message_attribute(
+                ".abi.Value",
+                r#"#[serde(into = "serde_json::Value", try_from = "serde_json::Value")]"#,
+            );
+    }
+
+    config
         .out_dir("src/pb")
         .compile_protos(&["abi.proto"], &["."])
         .unwrap();
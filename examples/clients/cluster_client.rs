@@ -0,0 +1,36 @@
+use kv::{ClusterClient, CommandRequest, KvPair, ProstClientStream, ShardBy};
+use tokio::net::TcpStream;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    // 每个地址对应一个 kv server，例如用不同端口启动多个 server 示例
+    let addrs = ["127.0.0.1:9527", "127.0.0.1:9528", "127.0.0.1:9529"];
+    let mut cluster = ClusterClient::new(ShardBy::Key);
+
+    for addr in addrs {
+        let stream = TcpStream::connect(addr).await?;
+        cluster.add_node(addr, ProstClientStream::new(stream));
+        info!("Connected to {}", addr);
+    }
+
+    // Hmset 会按 key 拆分到不同的节点
+    let pairs = (0..10)
+        .map(|i| KvPair::new(format!("k{}", i), (i as i64).into()))
+        .collect();
+    let res = cluster
+        .execute(CommandRequest::new_hmset("table1", pairs))
+        .await?;
+    info!("Got response {:?}", res);
+
+    // Hmget 的结果会按 key 的原始顺序合并
+    let keys = (0..10).map(|i| format!("k{}", i)).collect();
+    let res = cluster
+        .execute(CommandRequest::new_hmget("table1", keys))
+        .await?;
+    info!("Got response {:?}", res);
+
+    Ok(())
+}
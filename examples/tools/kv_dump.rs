@@ -0,0 +1,43 @@
+use std::{env, fs::File, io::BufWriter};
+
+use anyhow::bail;
+use kv::{ProstClientStream, SledDb, dump, dump_remote};
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// 把数据导出到 dump 文件
+///
+/// kv-dump <file> [--addr 127.0.0.1:9527]   通过服务器协议在线导出
+/// kv-dump <file> --sled <dir>              直接打开 SledDb 目录离线导出
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (file, source) = match args.as_slice() {
+        [file] => (file, None),
+        [file, flag, value] => (file, Some((flag.as_str(), value))),
+        _ => bail!("Usage: kv-dump <file> [--addr <host:port> | --sled <dir>]"),
+    };
+    let w = BufWriter::new(File::create(file)?);
+
+    let stats = match source {
+        None => dump_remote(&mut connect("127.0.0.1:9527").await?, w).await?,
+        Some(("--addr", addr)) => dump_remote(&mut connect(addr).await?, w).await?,
+        Some(("--sled", dir)) => dump(&SledDb::new(dir), w)?,
+        Some((flag, _)) => bail!("Unknown option {}", flag),
+    };
+    info!(
+        "Dumped {} tables, {} pairs to {}",
+        stats.tables, stats.pairs, file
+    );
+
+    Ok(())
+}
+
+async fn connect(addr: &str) -> anyhow::Result<ProstClientStream<TcpStream>> {
+    let stream = TcpStream::connect(addr).await?;
+    info!("Connected to {}", addr);
+
+    Ok(ProstClientStream::new(stream))
+}
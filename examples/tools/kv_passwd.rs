@@ -0,0 +1,18 @@
+use std::env;
+
+use anyhow::bail;
+use kv::hash_password;
+
+/// 生成 ACL 文件中的密码规则
+///
+/// kv-passwd <user> <password>
+fn main() -> anyhow::Result<()> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let [user, password] = args.as_slice() else {
+        bail!("Usage: kv-passwd <user> <password>");
+    };
+
+    println!("user {} password {}", user, hash_password(password)?);
+
+    Ok(())
+}
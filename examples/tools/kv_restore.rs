@@ -0,0 +1,43 @@
+use std::{env, fs::File, io::BufReader};
+
+use anyhow::bail;
+use kv::{ProstClientStream, SledDb, restore, restore_remote};
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// 把 dump 文件中的数据导入存储，已有的 key 会被覆盖
+///
+/// kv-restore <file> [--addr 127.0.0.1:9527]   通过服务器协议在线导入
+/// kv-restore <file> --sled <dir>              直接打开 SledDb 目录离线导入
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (file, target) = match args.as_slice() {
+        [file] => (file, None),
+        [file, flag, value] => (file, Some((flag.as_str(), value))),
+        _ => bail!("Usage: kv-restore <file> [--addr <host:port> | --sled <dir>]"),
+    };
+    let r = BufReader::new(File::open(file)?);
+
+    let stats = match target {
+        None => restore_remote(&mut connect("127.0.0.1:9527").await?, r).await?,
+        Some(("--addr", addr)) => restore_remote(&mut connect(addr).await?, r).await?,
+        Some(("--sled", dir)) => restore(&SledDb::new(dir), r)?,
+        Some((flag, _)) => bail!("Unknown option {}", flag),
+    };
+    info!(
+        "Restored {} tables, {} pairs from {}",
+        stats.tables, stats.pairs, file
+    );
+
+    Ok(())
+}
+
+async fn connect(addr: &str) -> anyhow::Result<ProstClientStream<TcpStream>> {
+    let stream = TcpStream::connect(addr).await?;
+    info!("Connected to {}", addr);
+
+    Ok(ProstClientStream::new(stream))
+}
@@ -0,0 +1,199 @@
+//! `#[derive(KvRecord)]`：把 struct 映射成 kv 中的一组 kv pair
+//!
+//! ```ignore
+//! #[derive(KvRecord)]
+//! #[kv(table = "users")]
+//! struct User {
+//!     #[kv(key)]
+//!     id: u64,
+//!     name: String,
+//!     email: Option<String>,
+//! }
+//! ```
+//!
+//! 不指定 table 时使用 snake_case 的 struct 名。字段通过 kv 中已有的 `From`/`TryFrom<Value>` 转换，
+//! `Option<T>` 的 None 对应空的 Value。
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Error, Fields, GenericArgument, LitStr, PathArguments, Result, Type,
+    parse_macro_input,
+};
+
+#[proc_macro_derive(KvRecord, attributes(kv))]
+pub fn derive_kv_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "KvRecord does not support generics",
+        ));
+    }
+
+    let mut table = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("kv")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `table = \"...\"`"))
+            }
+        })?;
+    }
+    let table = table.unwrap_or_else(|| snake_case(&name.to_string()));
+
+    let fields = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(f) => &f.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    name,
+                    "KvRecord requires a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                name,
+                "KvRecord can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut key = None;
+    for field in fields {
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("kv")) {
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("key") {
+                    return Err(meta.error("expected `key`"));
+                }
+                if key.is_some() {
+                    return Err(meta.error("only one field can be marked #[kv(key)]"));
+                }
+                key = Some(field);
+                Ok(())
+            })?;
+        }
+    }
+    let Some(key) = key else {
+        return Err(Error::new(
+            Span::call_site(),
+            "KvRecord requires one field marked #[kv(key)]",
+        ));
+    };
+    let key_ident = key.ident.as_ref().unwrap();
+    let key_ty = &key.ty;
+    let key_name = key_ident.to_string();
+    if is_option(key_ty) {
+        return Err(Error::new_spanned(
+            key_ty,
+            "#[kv(key)] field cannot be Option",
+        ));
+    }
+
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let names: Vec<_> = idents.iter().map(|i| i.to_string()).collect();
+
+    let to_values = fields.iter().map(|f| {
+        let ident = f.ident.as_ref().unwrap();
+        if is_option(&f.ty) {
+            quote! {
+                match &self.#ident {
+                    Some(v) => ::kv::Value::from(v.clone()),
+                    None => ::kv::Value::default(),
+                }
+            }
+        } else {
+            quote! { ::kv::Value::from(self.#ident.clone()) }
+        }
+    });
+
+    let from_values = fields.iter().map(|f| {
+        let ident = f.ident.as_ref().unwrap();
+        let ty = &f.ty;
+        if let Some(inner) = option_inner(ty) {
+            quote! {
+                let #ident = match values.next() {
+                    Some(v) if v.value.is_some() => Some(<#inner>::try_from(v)?),
+                    _ => None,
+                };
+            }
+        } else {
+            quote! {
+                let #ident = <#ty>::try_from(values.next().unwrap_or_default())?;
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl ::kv::KvRecord for #name {
+            type Key = #key_ty;
+
+            const TABLE: &'static str = #table;
+            const KEY_FIELD: &'static str = #key_name;
+            const FIELDS: &'static [&'static str] = &[#(#names),*];
+
+            fn key(&self) -> &Self::Key {
+                &self.#key_ident
+            }
+
+            fn to_values(&self) -> ::std::vec::Vec<::kv::Value> {
+                ::std::vec![#(#to_values),*]
+            }
+
+            fn from_values(
+                values: ::std::vec::Vec<::kv::Value>,
+            ) -> ::std::result::Result<Self, ::kv::KVError> {
+                let mut values = values.into_iter();
+                #(#from_values)*
+                Ok(Self { #(#idents),* })
+            }
+        }
+    })
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(p) = ty else {
+        return None;
+    };
+    let seg = p.path.segments.last()?;
+    if seg.ident != "Option" {
+        return None;
+    }
+    match &seg.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(t) => Some(t),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    option_inner(ty).is_some()
+}
+
+fn snake_case(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 4);
+    for (i, c) in s.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,472 @@
+//! 连接认证和按 table 授权
+//!
+//! ACL 文件每行一条规则，空行和 # 开头的行会被忽略：
+//!
+//! ```text
+//! user alice password $argon2id$v=19$m=19456,t=2,p=1$...
+//! user ci token 2f1c9a...
+//! grant alice write users*
+//! grant ci read *
+//! ```
+//!
+//! 密码用 `hash_password` 生成 argon2 哈希后写入文件。table pattern 中的 `*` 匹配任意字符串，
+//! admin 包含 write，write 包含 read。namespace 中的 table 按 `{namespace}/{table}` 匹配；
+//! HSNAPSHOT 这类不针对某个 table 的命令需要 pattern 为 `*` 的授权，namespace 中的 HTABLES 需要 `{namespace}/*`，
+//! ListNamespaces 和 DropNamespace 需要对 `*` 的 admin 权限。
+//!
+//! 同一个连接认证失败后需要等待一段时间（每次失败翻倍）才能再次认证，期间的 Auth 返回 429。
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+
+use crate::{Auth, CommandRequest, KVError, NAMESPACE_SEPARATOR, command_request::RequestData};
+
+/// 第一次认证失败后需要等待的时间，之后每次失败翻倍
+const AUTH_BACKOFF_BASE: Duration = Duration::from_millis(100);
+/// 认证失败后最长需要等待的时间
+const AUTH_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+/// 对 table 的权限，高级别的权限包含低级别的
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Read,
+    Write,
+    /// 可以修改 schema
+    Admin,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Grant {
+    pattern: String,
+    permission: Permission,
+}
+
+/// 认证后的用户和它的授权
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    name: String,
+    grants: Vec<Grant>,
+}
+
+/// 用户、凭证和授权规则
+#[derive(Debug, Default)]
+pub struct Acl {
+    /// 用户名 -> argon2 哈希
+    passwords: HashMap<String, String>,
+    /// token -> 用户名
+    tokens: HashMap<String, String>,
+    grants: HashMap<String, Vec<Grant>>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从 ACL 文件读取，格式见模块文档
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, KVError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// 用户使用密码认证，hash 是 `hash_password` 生成的 argon2 哈希
+    pub fn add_password(&mut self, user: impl Into<String>, hash: impl Into<String>) {
+        self.passwords.insert(user.into(), hash.into());
+    }
+
+    /// 用户使用静态 token 认证，一个用户可以有多个 token
+    pub fn add_token(&mut self, user: impl Into<String>, token: impl Into<String>) {
+        self.tokens.insert(token.into(), user.into());
+    }
+
+    /// 授予用户对匹配 pattern 的 table 的权限
+    pub fn grant(
+        &mut self,
+        user: impl Into<String>,
+        permission: Permission,
+        pattern: impl Into<String>,
+    ) {
+        self.grants.entry(user.into()).or_default().push(Grant {
+            pattern: pattern.into(),
+            permission,
+        });
+    }
+
+    /// 检查凭证，成功时返回用户的身份
+    pub fn authenticate(&self, auth: &Auth) -> Result<Identity, KVError> {
+        let name = if !auth.token.is_empty() {
+            self.tokens.get(&auth.token).cloned()
+        } else {
+            self.passwords
+                .get(&auth.username)
+                .filter(|hash| {
+                    argon2::verify_encoded(hash, auth.password.as_bytes()).unwrap_or(false)
+                })
+                .map(|_| auth.username.clone())
+        };
+
+        // 不区分用户不存在和凭证错误
+        let name = name.ok_or_else(|| KVError::Unauthenticated("Invalid credentials".into()))?;
+        let grants = self.grants.get(&name).cloned().unwrap_or_default();
+
+        Ok(Identity { name, grants })
+    }
+}
+
+impl FromStr for Acl {
+    type Err = KVError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut acl = Acl::new();
+
+        for (i, line) in s.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = || KVError::InvalidCommand(format!("Invalid ACL rule at line {}", i + 1));
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["user", user, "password", hash] => acl.add_password(*user, *hash),
+                ["user", user, "token", token] => acl.add_token(*user, *token),
+                ["grant", user, permission, pattern] => {
+                    let permission = match *permission {
+                        "read" => Permission::Read,
+                        "write" => Permission::Write,
+                        "admin" => Permission::Admin,
+                        _ => return Err(err()),
+                    };
+                    acl.grant(*user, permission, *pattern);
+                }
+                _ => return Err(err()),
+            }
+        }
+
+        Ok(acl)
+    }
+}
+
+impl Identity {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 是否有对 table 的权限，table 为 `*` 表示所有 table
+    pub fn allows(&self, permission: Permission, table: &str) -> bool {
+        self.grants
+            .iter()
+            .any(|g| g.permission >= permission && glob_match(&g.pattern, table))
+    }
+
+    /// 检查是否可以执行命令，batch 中只要有一个命令没有权限，整个 batch 都不执行
+    pub fn check(&self, cmd: &CommandRequest) -> Result<(), KVError> {
+        self.check_in("", cmd)
+    }
+
+    /// 和 check 一样，但命令来自绑定了 namespace 的连接（table 已经带上 namespace 前缀）
+    ///
+    /// 快照不属于某个 table，需要对整个 namespace（`{namespace}/*`）有读权限，默认 namespace 需要 `*`。
+    pub fn check_in(&self, namespace: &str, cmd: &CommandRequest) -> Result<(), KVError> {
+        let Some(data) = &cmd.request_data else {
+            return Ok(());
+        };
+
+        match data {
+            RequestData::Batch(batch) => {
+                return batch
+                    .requests
+                    .iter()
+                    .try_for_each(|cmd| self.check_in(namespace, cmd));
+            }
+            // 由连接处理，batch 中的会被 dispatch 拒绝
+            RequestData::Auth(_) | RequestData::UseNamespace(_) => return Ok(()),
+            _ => {}
+        }
+
+        let (permission, table) = required(data, namespace);
+        if self.allows(permission, &table) {
+            Ok(())
+        } else {
+            Err(KVError::PermissionDenied(self.name.clone(), table.into()))
+        }
+    }
+}
+
+/// 一个连接连续认证失败的次数，用来限制猜测密码的速度
+#[derive(Debug, Default)]
+pub(crate) struct AuthBackoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl AuthBackoff {
+    /// 还在等待期内时返回 RateLimited
+    pub(crate) fn check(&self, now: Instant) -> Result<(), KVError> {
+        match self.retry_at {
+            Some(at) if at > now => {
+                let ms = (at - now).as_nanos().div_ceil(1_000_000) as u64;
+                Err(KVError::RateLimited("auth".into(), ms))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 记录一次认证的结果，成功时清零
+    pub(crate) fn record(&mut self, ok: bool, now: Instant) {
+        if ok {
+            *self = Self::default();
+            return;
+        }
+
+        self.failures = self.failures.saturating_add(1);
+        let delay = AUTH_BACKOFF_BASE
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(AUTH_BACKOFF_MAX);
+        self.retry_at = Some(now + delay);
+    }
+}
+
+/// 生成可以写入 ACL 文件的 argon2 密码哈希
+pub fn hash_password(password: &str) -> Result<String, KVError> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    argon2::hash_encoded(password.as_bytes(), &salt, &argon2::Config::default())
+        .map_err(|e| KVError::InternalError(e.to_string()))
+}
+
+// 执行命令需要的权限和 table
+fn required<'a>(data: &'a RequestData, namespace: &str) -> (Permission, Cow<'a, str>) {
+    let (permission, table) = match data {
+        RequestData::Hget(v) => (Permission::Read, &v.table),
+        RequestData::Hgetall(v) => (Permission::Read, &v.table),
+        RequestData::Hmget(v) => (Permission::Read, &v.table),
+        RequestData::Hexist(v) => (Permission::Read, &v.table),
+        RequestData::Hmexist(v) => (Permission::Read, &v.table),
+        RequestData::Hrange(v) => (Permission::Read, &v.table),
+        RequestData::Hprefix(v) => (Permission::Read, &v.table),
+        RequestData::Hscan(v) => (Permission::Read, &v.table),
+        RequestData::HgetVersion(v) => (Permission::Read, &v.table),
+        RequestData::Hhistory(v) => (Permission::Read, &v.table),
+        RequestData::HgetSchema(v) => (Permission::Read, &v.table),
+        RequestData::Hset(v) => (Permission::Write, &v.table),
+        RequestData::Hmset(v) => (Permission::Write, &v.table),
+        RequestData::Hdel(v) => (Permission::Write, &v.table),
+        RequestData::Hmdel(v) => (Permission::Write, &v.table),
+        RequestData::HsetSchema(v) => (Permission::Admin, &v.table),
+        RequestData::Htables(v) => return (Permission::Read, format!("{}*", v.prefix).into()),
+        RequestData::Hsnapshot(_) | RequestData::Hrelease(_) => {
+            return match namespace {
+                "" => (Permission::Read, "*".into()),
+                ns => (
+                    Permission::Read,
+                    format!("{}{}*", ns, NAMESPACE_SEPARATOR).into(),
+                ),
+            };
+        }
+        RequestData::ListNamespaces(_) | RequestData::DropNamespace(_) => {
+            return (Permission::Admin, "*".into());
+        }
+        // 已经在 check 中处理
+        RequestData::Batch(_) | RequestData::Auth(_) | RequestData::UseNamespace(_) => {
+            unreachable!()
+        }
+    };
+
+    (permission, table.into())
+}
+
+// `*` 匹配任意字符串（包括空字符串）
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let (p, s) = (pattern.as_bytes(), s.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    // 最近一个 * 的位置，以及它当时匹配到的 s 的位置
+    let mut star: Option<(usize, usize)> = None;
+
+    while j < s.len() {
+        if i < p.len() && p[i] == b'*' {
+            star = Some((i, j));
+            i += 1;
+        } else if i < p.len() && p[i] == s[j] {
+            i += 1;
+            j += 1;
+        } else if let Some((si, sj)) = star {
+            // 让 * 多匹配一个字符
+            i = si + 1;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[i..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试中用很小的参数，避免 debug 模式下太慢
+    fn cheap_hash(password: &str) -> String {
+        let config = argon2::Config {
+            mem_cost: 64,
+            time_cost: 1,
+            ..Default::default()
+        };
+        argon2::hash_encoded(password.as_bytes(), b"saltsaltsalt", &config).unwrap()
+    }
+
+    fn test_acl() -> Acl {
+        let mut acl = Acl::new();
+        acl.add_password("alice", cheap_hash("secret"));
+        acl.add_token("ci", "t0ken");
+        acl.grant("alice", Permission::Write, "users*");
+        acl.grant("alice", Permission::Read, "orders");
+        acl.grant("ci", Permission::Admin, "*");
+        acl
+    }
+
+    fn password(user: &str, password: &str) -> Auth {
+        Auth {
+            username: user.into(),
+            password: password.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob_should_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("users*", "users"));
+        assert!(glob_match("users*", "users_2024"));
+        assert!(glob_match("*:log", "app:log"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(!glob_match("users", "users2"));
+    }
+
+    #[test]
+    fn acl_should_authenticate_password_and_token() {
+        let acl = test_acl();
+
+        let alice = acl.authenticate(&password("alice", "secret")).unwrap();
+        assert_eq!(alice.name(), "alice");
+        let ci = acl
+            .authenticate(&Auth {
+                token: "t0ken".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(ci.name(), "ci");
+
+        for auth in [password("alice", "wrong"), password("bob", "secret")] {
+            assert!(matches!(
+                acl.authenticate(&auth),
+                Err(KVError::Unauthenticated(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn auth_backoff_should_double_after_failures() {
+        let mut backoff = AuthBackoff::default();
+        let now = Instant::now();
+        assert!(backoff.check(now).is_ok());
+
+        backoff.record(false, now);
+        assert_eq!(
+            backoff.check(now),
+            Err(KVError::RateLimited("auth".into(), 100))
+        );
+        backoff.record(false, now);
+        assert_eq!(
+            backoff.check(now),
+            Err(KVError::RateLimited("auth".into(), 200))
+        );
+        assert!(backoff.check(now + Duration::from_millis(200)).is_ok());
+
+        for _ in 0..100 {
+            backoff.record(false, now);
+        }
+        assert_eq!(
+            backoff.check(now),
+            Err(KVError::RateLimited("auth".into(), 10_000))
+        );
+
+        backoff.record(true, now);
+        assert!(backoff.check(now).is_ok());
+    }
+
+    #[test]
+    fn identity_should_check_permissions() {
+        let alice = test_acl()
+            .authenticate(&password("alice", "secret"))
+            .unwrap();
+
+        assert!(
+            alice
+                .check(&CommandRequest::new_hset("users", "k", 1.into()))
+                .is_ok()
+        );
+        assert!(
+            alice
+                .check(&CommandRequest::new_hget("orders", "k"))
+                .is_ok()
+        );
+        assert_eq!(
+            alice.check(&CommandRequest::new_hset("orders", "k", 1.into())),
+            Err(KVError::PermissionDenied("alice".into(), "orders".into()))
+        );
+        assert!(
+            alice
+                .check(&CommandRequest::new_hset_schema("users", None))
+                .is_err()
+        );
+        assert!(alice.check(&CommandRequest::new_htables()).is_err());
+
+        let batch = CommandRequest::new_batch(
+            vec![
+                CommandRequest::new_hset("users", "k", 1.into()),
+                CommandRequest::new_hdel("orders", "k"),
+            ],
+            false,
+        );
+        assert!(alice.check(&batch).is_err());
+    }
+
+    #[test]
+    fn acl_file_should_parse() {
+        let hash = cheap_hash("pw");
+        let content = format!(
+            "# users\nuser alice password {}\nuser ci token abc\n\ngrant alice read *\ngrant ci admin t*\n",
+            hash
+        );
+        let acl: Acl = content.parse().unwrap();
+        let ci = acl
+            .authenticate(&Auth {
+                token: "abc".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(ci.allows(Permission::Write, "t1"));
+        assert!(!ci.allows(Permission::Read, "*"));
+
+        assert!("grant alice root *".parse::<Acl>().is_err());
+        assert!("user alice".parse::<Acl>().is_err());
+    }
+
+    #[test]
+    fn hash_password_should_verify() {
+        let hash = hash_password("pw").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(argon2::verify_encoded(&hash, b"pw").unwrap());
+        assert_ne!(hash, hash_password("pw").unwrap());
+    }
+}
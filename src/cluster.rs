@@ -0,0 +1,912 @@
+use std::{
+    collections::{BTreeMap, HashMap, btree_map::Entry},
+    future::{self, Future},
+};
+
+use futures::future::join_all;
+use http::StatusCode;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    CommandBatch, CommandBatchResponse, CommandRequest, CommandResponse, Hmdel, Hmexist, Hmget,
+    Hmset, KVError, KvPair, NamespaceInfo, ProstClientStream, Service, Storage, Value,
+    command_request::RequestData, service::Deadline,
+};
+
+/// 每个物理节点缺省的虚拟节点数
+const DEFAULT_VIRTUAL_NODES: usize = 160;
+
+/// 拆分后的子命令：(节点 id, 子命令)
+type SubCommands = Vec<(String, RequestData)>;
+/// 子命令中每个元素在原始命令中的位置：节点 id -> 位置
+type Positions = HashMap<String, Vec<usize>>;
+
+/// 集群中的一个节点，能执行 CommandRequest 并返回 CommandResponse
+pub trait KvNode {
+    fn execute(
+        &mut self,
+        cmd: CommandRequest,
+    ) -> impl Future<Output = Result<CommandResponse, KVError>> + Send;
+}
+
+impl<S> KvNode for ProstClientStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn execute(
+        &mut self,
+        cmd: CommandRequest,
+    ) -> impl Future<Output = Result<CommandResponse, KVError>> + Send {
+        ProstClientStream::execute(self, cmd)
+    }
+}
+
+/// 进程内的 Service 也可以作为节点使用，方便测试和嵌入式部署
+impl<Store: Storage> KvNode for Service<Store> {
+    fn execute(
+        &mut self,
+        cmd: CommandRequest,
+    ) -> impl Future<Output = Result<CommandResponse, KVError>> + Send {
+        future::ready(Ok(Service::execute(self, cmd)))
+    }
+}
+
+/// 数据在节点间的分布方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardBy {
+    /// 整个 table 落在同一个节点上，Hgetall 只需访问一个节点
+    #[default]
+    Table,
+    /// 按 (table, key) 分布，数据更均匀，但 Hgetall/Hrange/Hprefix 需要访问所有节点
+    Key,
+}
+
+/// 带虚拟节点的一致性哈希环
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    ring: BTreeMap<u64, String>,
+}
+
+impl Default for HashRing {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIRTUAL_NODES)
+    }
+}
+
+impl HashRing {
+    /// 创建一个哈希环，每个物理节点在环上放置 virtual_nodes 个虚拟节点
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            ring: BTreeMap::new(),
+        }
+    }
+
+    /// 把节点加入哈希环
+    ///
+    /// 虚拟节点的位置和其它节点冲突时继续向后探测，不覆盖其它节点的位置，移除节点时也不会影响其它节点。
+    pub fn add(&mut self, node: &str) {
+        for i in 0..self.virtual_nodes {
+            let mut name = format!("{}#{}", node, i);
+            loop {
+                match self.ring.entry(hash(name.as_bytes())) {
+                    Entry::Vacant(e) => {
+                        e.insert(node.into());
+                        break;
+                    }
+                    Entry::Occupied(e) if e.get() == node => break,
+                    Entry::Occupied(_) => name.push('#'),
+                }
+            }
+        }
+    }
+
+    /// 把节点从哈希环中移除
+    pub fn remove(&mut self, node: &str) {
+        self.ring.retain(|_, v| v != node);
+    }
+
+    /// 找到 key 所属的节点：顺时针方向的第一个虚拟节点
+    pub fn get(&self, key: &[u8]) -> Option<&str> {
+        let h = hash(key);
+
+        self.ring
+            .range(h..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
+
+/// 客户端分片路由：根据一致性哈希把命令分发到不同的 kv server 上
+///
+/// 多 key 的命令（Hmget/Hmset/Hmdel/Hmexist）会按节点拆分后并发执行，再按原始顺序合并结果。
+/// 拆分后的写入不是原子的：某个节点失败时，其它节点上的写入已经生效，返回的错误信息中列出失败节点上的 key。
+/// 增删节点只改变路由，不会迁移已有数据。
+pub struct ClusterClient<N> {
+    shard_by: ShardBy,
+    ring: HashRing,
+    nodes: HashMap<String, N>,
+    /// 正在执行的命令的截止时间，发给每个节点的子命令都带上它
+    deadline: u64,
+}
+
+impl<N: KvNode> ClusterClient<N> {
+    pub fn new(shard_by: ShardBy) -> Self {
+        Self::with_ring(shard_by, HashRing::default())
+    }
+
+    pub fn with_ring(shard_by: ShardBy, ring: HashRing) -> Self {
+        Self {
+            shard_by,
+            ring,
+            nodes: HashMap::new(),
+            deadline: 0,
+        }
+    }
+
+    /// 加入一个节点，如果 id 已存在则替换，返回旧的节点
+    pub fn add_node(&mut self, id: impl Into<String>, node: N) -> Option<N> {
+        let id = id.into();
+        let old = self.nodes.insert(id.clone(), node);
+
+        if old.is_none() {
+            self.ring.add(&id);
+        }
+
+        old
+    }
+
+    /// 移除一个节点，返回被移除的节点
+    pub fn remove_node(&mut self, id: &str) -> Option<N> {
+        self.ring.remove(id);
+        self.nodes.remove(id)
+    }
+
+    /// 返回当前所有节点的 id
+    pub fn node_ids(&self) -> impl Iterator<Item = &str> {
+        self.nodes.keys().map(|k| k.as_str())
+    }
+
+    /// 返回 (table, key) 所属的节点 id
+    pub fn locate(&self, table: &str, key: &str) -> Option<&str> {
+        match self.shard_by {
+            ShardBy::Table => self.ring.get(table.as_bytes()),
+            ShardBy::Key => self.ring.get(format!("{}:{}", table, key).as_bytes()),
+        }
+    }
+
+    /// 执行命令，必要时拆分到多个节点并合并结果
+    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KVError> {
+        let Some(data) = cmd.request_data else {
+            return Err(KVError::InvalidCommand("Request has no data".into()));
+        };
+        self.deadline = cmd.deadline;
+
+        // 快照 id 只在创建它的节点上有效
+        if data.snapshot() != 0
+            || matches!(data, RequestData::Hsnapshot(_) | RequestData::Hrelease(_))
+        {
+            return Err(KVError::InvalidCommand(
+                "Snapshots are not supported by cluster".into(),
+            ));
+        }
+
+        if let Some((table, key)) = route_key(&data, self.shard_by) {
+            let id = self.locate(table, key).ok_or_else(no_node)?.to_owned();
+            let res = self.fan_out(vec![(id, data)]).await?;
+
+            return Ok(res
+                .into_iter()
+                .next()
+                .map(|(_, res)| res)
+                .unwrap_or_default());
+        }
+
+        match data {
+            // 每个节点都要检查写入的数据，schema 需要同步到所有节点
+            RequestData::Hgetall(_) | RequestData::HsetSchema(_) => self.broadcast(data).await,
+            RequestData::Hprefix(_) => {
+                let mut res = self.broadcast(data).await?;
+                res.pairs.sort_by(|a, b| a.key.cmp(&b.key));
+
+                Ok(res)
+            }
+            RequestData::Hrange(ref v) => {
+                let (limit, reverse) = (v.limit as usize, v.reverse);
+                let res = self.broadcast(data).await?;
+
+                Ok(merge_ordered(res, limit, reverse))
+            }
+            RequestData::Hscan(ref v) => {
+                let limit = v.limit as usize;
+                let res = self.broadcast(data).await?;
+
+                Ok(merge_ordered(res, limit, false))
+            }
+            RequestData::Htables(_) => {
+                let res = self.broadcast(data).await?;
+                if !is_success(&res) {
+                    return Ok(res);
+                }
+
+                // 同一个 table 可能分布在多个节点上，合并后去重
+                let mut names = res
+                    .values
+                    .into_iter()
+                    .map(String::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                names.sort();
+                names.dedup();
+
+                Ok(names
+                    .into_iter()
+                    .map(Value::from)
+                    .collect::<Vec<_>>()
+                    .into())
+            }
+            RequestData::Hmget(v) => {
+                self.split_keys(&v.table, v.keys, |table, keys| {
+                    RequestData::Hmget(Hmget { table, keys })
+                })
+                .await
+            }
+            RequestData::Hmdel(v) => {
+                self.split_keys(&v.table, v.keys, |table, keys| {
+                    RequestData::Hmdel(Hmdel { table, keys })
+                })
+                .await
+            }
+            RequestData::Hmexist(v) => {
+                self.split_keys(&v.table, v.keys, |table, keys| {
+                    RequestData::Hmexist(Hmexist { table, keys })
+                })
+                .await
+            }
+            RequestData::Hmset(v) => {
+                let table = v.table;
+                let keys: Vec<_> = v.pairs.iter().map(|p| p.key.clone()).collect();
+                let groups = self.group_by_node(&table, v.pairs, |p: &KvPair| &p.key)?;
+                let (parts, indices) = split_groups(groups, |pairs| {
+                    RequestData::Hmset(Hmset {
+                        table: table.clone(),
+                        pairs,
+                    })
+                });
+
+                self.merge_values(parts, indices, &keys).await
+            }
+            RequestData::Batch(batch) => self.execute_batch(batch, cmd.deadline).await,
+            RequestData::Auth(_) | RequestData::UseNamespace(_) => {
+                // 每个节点都返回同样的名字，只保留一个
+                let mut res = self.broadcast(data).await?;
+                res.values.truncate(1);
+
+                Ok(res)
+            }
+            RequestData::DropNamespace(_) => {
+                let res = self.broadcast(data).await?;
+                if !is_success(&res) {
+                    return Ok(res);
+                }
+
+                let deleted = res
+                    .values
+                    .into_iter()
+                    .map(i64::try_from)
+                    .sum::<Result<i64, _>>()?;
+
+                Ok(Value::from(deleted).into())
+            }
+            RequestData::ListNamespaces(_) => {
+                let res = self.broadcast(data).await?;
+                if !is_success(&res) {
+                    return Ok(res);
+                }
+
+                // 同一个 namespace 在每个节点上分别统计，用量相加，配额以单个节点为准
+                let mut merged: BTreeMap<String, NamespaceInfo> = BTreeMap::new();
+                for info in res.namespaces {
+                    match merged.get_mut(&info.name) {
+                        Some(m) => {
+                            m.keys += info.keys;
+                            m.bytes += info.bytes;
+                        }
+                        None => {
+                            merged.insert(info.name.clone(), info);
+                        }
+                    }
+                }
+
+                Ok(merged.into_values().collect::<Vec<_>>().into())
+            }
+            // 单 key 命令已经在 route_key 中处理，其余命令集群不支持
+            _ => Err(KVError::InvalidCommand(
+                "Command is not supported by cluster".into(),
+            )),
+        }
+    }
+
+    // batch 中的命令可能属于不同节点，按顺序逐个执行以保证 stop_on_error 的语义
+    async fn execute_batch(
+        &mut self,
+        batch: CommandBatch,
+        deadline: u64,
+    ) -> Result<CommandResponse, KVError> {
+        let mut responses = Vec::with_capacity(batch.requests.len());
+        let deadline = Deadline::new(deadline, None);
+
+        for mut cmd in batch.requests {
+            if let Err(e) = deadline.check() {
+                responses.push(e.into());
+                break;
+            }
+
+            cmd.deadline = deadline.min(cmd.deadline).at();
+            let res = match cmd.request_data {
+                Some(RequestData::Batch(_)) => {
+                    KVError::InvalidCommand("CommandBatch cannot be nested".into()).into()
+                }
+                _ => Box::pin(self.execute(cmd))
+                    .await
+                    .unwrap_or_else(CommandResponse::from),
+            };
+
+            let failed = !is_success(&res);
+            responses.push(res);
+            if failed && batch.stop_on_error {
+                break;
+            }
+        }
+
+        Ok(CommandBatchResponse { responses }.into())
+    }
+
+    // 把同一个命令发到所有节点，合并返回的 values 和 kv pairs
+    async fn broadcast(&mut self, data: RequestData) -> Result<CommandResponse, KVError> {
+        if self.nodes.is_empty() {
+            return Err(no_node());
+        }
+
+        let parts = self
+            .nodes
+            .keys()
+            .map(|id| (id.clone(), data.clone()))
+            .collect();
+        let results = self.fan_out(parts).await?;
+
+        if let Some(res) = first_error(&results) {
+            return Ok(res);
+        }
+
+        let mut merged = CommandResponse::from(Vec::<KvPair>::new());
+        for (_, res) in results {
+            merged.values.extend(res.values);
+            merged.pairs.extend(res.pairs);
+            merged.namespaces.extend(res.namespaces);
+        }
+
+        Ok(merged)
+    }
+
+    // 按 key 拆分多 key 命令，再把结果按原始顺序合并
+    async fn split_keys(
+        &mut self,
+        table: &str,
+        keys: Vec<String>,
+        f: impl Fn(String, Vec<String>) -> RequestData,
+    ) -> Result<CommandResponse, KVError> {
+        let groups = self.group_by_node(table, keys.clone(), |k: &String| k)?;
+        let (parts, indices) = split_groups(groups, |keys| f(table.to_owned(), keys));
+
+        self.merge_values(parts, indices, &keys).await
+    }
+
+    // 把一组元素按所属节点分组，同时记录每个元素的原始位置
+    fn group_by_node<T>(
+        &self,
+        table: &str,
+        items: Vec<T>,
+        key_of: impl Fn(&T) -> &String,
+    ) -> Result<HashMap<String, Vec<(usize, T)>>, KVError> {
+        let mut groups: HashMap<String, Vec<(usize, T)>> = HashMap::new();
+
+        for (i, item) in items.into_iter().enumerate() {
+            let id = self.locate(table, key_of(&item)).ok_or_else(no_node)?;
+            groups.entry(id.to_owned()).or_default().push((i, item));
+        }
+
+        Ok(groups)
+    }
+
+    // 并发执行各节点的子命令，并把返回的 values 放回原始位置；keys 是原始命令中的 key
+    async fn merge_values(
+        &mut self,
+        parts: SubCommands,
+        indices: Positions,
+        keys: &[String],
+    ) -> Result<CommandResponse, KVError> {
+        let total = indices.values().map(|v| v.len()).sum();
+        let mut values = vec![Value::default(); total];
+        let results = self.fan_out(parts).await?;
+
+        // 其它节点上的子命令已经执行，在错误信息中列出失败节点上的 key
+        if let Some(mut res) = first_error(&results) {
+            let mut failed: Vec<_> = results
+                .iter()
+                .filter(|(_, res)| !is_success(res))
+                .flat_map(|(id, _)| &indices[id])
+                .collect();
+            failed.sort();
+            let failed: Vec<_> = failed.into_iter().map(|&i| keys[i].as_str()).collect();
+            res.message = format!("{} (failed keys: {})", res.message, failed.join(", "));
+
+            return Ok(res);
+        }
+
+        for (id, res) in results {
+            let positions = &indices[&id];
+
+            if res.values.len() != positions.len() {
+                return Err(KVError::InternalError(format!(
+                    "Node {} returned {} values, expected {}",
+                    id,
+                    res.values.len(),
+                    positions.len()
+                )));
+            }
+
+            for (pos, v) in positions.iter().zip(res.values) {
+                values[*pos] = v;
+            }
+        }
+
+        Ok(values.into())
+    }
+
+    // 并发地把子命令发到各自的节点
+    async fn fan_out(
+        &mut self,
+        mut parts: SubCommands,
+    ) -> Result<Vec<(String, CommandResponse)>, KVError> {
+        let futures = self.nodes.iter_mut().filter_map(|(id, node)| {
+            let i = parts.iter().position(|(p, _)| p == id)?;
+            let (id, data) = parts.swap_remove(i);
+            let cmd = CommandRequest {
+                request_data: Some(data),
+                deadline: self.deadline,
+            };
+
+            Some(async move { node.execute(cmd).await.map(|res| (id, res)) })
+        });
+
+        join_all(futures).await.into_iter().collect()
+    }
+}
+
+// 单 key 命令（以及 ShardBy::Table 时的所有命令）只需发送到一个节点，返回用于路由的 (table, key)
+fn route_key(data: &RequestData, shard_by: ShardBy) -> Option<(&str, &str)> {
+    let (table, key) = match data {
+        // 没有 table，需要询问所有节点
+        RequestData::Htables(_) | RequestData::Hsnapshot(_) | RequestData::Hrelease(_) => {
+            return None;
+        }
+        // batch 中的命令分别路由，每个节点的连接都需要认证和绑定 namespace
+        RequestData::Batch(_) | RequestData::Auth(_) | RequestData::UseNamespace(_) => {
+            return None;
+        }
+        // namespace 中的 table 分布在所有节点上
+        RequestData::ListNamespaces(_) | RequestData::DropNamespace(_) => return None,
+        RequestData::Hget(v) => (&v.table, v.key.as_str()),
+        RequestData::Hset(v) => (&v.table, v.pair.as_ref().map_or("", |p| p.key.as_str())),
+        RequestData::Hdel(v) => (&v.table, v.key.as_str()),
+        RequestData::Hexist(v) => (&v.table, v.key.as_str()),
+        RequestData::HgetVersion(v) => (&v.table, v.key.as_str()),
+        RequestData::Hhistory(v) => (&v.table, v.key.as_str()),
+        RequestData::HsetSchema(v) => (&v.table, ""),
+        RequestData::HgetSchema(v) => (&v.table, ""),
+        RequestData::Hgetall(v) => (&v.table, ""),
+        RequestData::Hmget(v) => (&v.table, ""),
+        RequestData::Hmset(v) => (&v.table, ""),
+        RequestData::Hmdel(v) => (&v.table, ""),
+        RequestData::Hmexist(v) => (&v.table, ""),
+        RequestData::Hrange(v) => (&v.table, ""),
+        RequestData::Hprefix(v) => (&v.table, ""),
+        RequestData::Hscan(v) => (&v.table, ""),
+    };
+
+    match (shard_by, data) {
+        (ShardBy::Table, _)
+        | (
+            ShardBy::Key,
+            RequestData::Hget(_)
+            | RequestData::Hset(_)
+            | RequestData::Hdel(_)
+            | RequestData::Hexist(_)
+            | RequestData::HgetVersion(_)
+            | RequestData::Hhistory(_)
+            | RequestData::HgetSchema(_),
+        ) => Some((table.as_str(), key)),
+        _ => None,
+    }
+}
+
+// 每个节点都已按方向截取了前 limit 个，合并排序后再截取一次即可
+fn merge_ordered(mut res: CommandResponse, limit: usize, reverse: bool) -> CommandResponse {
+    res.pairs.sort_by(|a, b| a.key.cmp(&b.key));
+    if reverse {
+        res.pairs.reverse();
+    }
+    if limit > 0 {
+        res.pairs.truncate(limit);
+    }
+
+    res
+}
+
+// 如果有节点返回了错误，整个命令就返回该错误
+fn first_error(results: &[(String, CommandResponse)]) -> Option<CommandResponse> {
+    results
+        .iter()
+        .find(|(_, res)| !is_success(res))
+        .map(|(_, res)| res.clone())
+}
+
+// 把分组结果拆成 (节点, 子命令) 和 (节点, 原始位置) 两部分
+fn split_groups<T>(
+    groups: HashMap<String, Vec<(usize, T)>>,
+    f: impl Fn(Vec<T>) -> RequestData,
+) -> (SubCommands, Positions) {
+    let mut parts = Vec::with_capacity(groups.len());
+    let mut indices = HashMap::with_capacity(groups.len());
+
+    for (id, items) in groups {
+        let (pos, items): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+        parts.push((id.clone(), f(items)));
+        indices.insert(id, pos);
+    }
+
+    (parts, indices)
+}
+
+fn is_success(res: &CommandResponse) -> bool {
+    StatusCode::from_u16(res.status as _).is_ok_and(|s| s.is_success())
+}
+
+fn no_node() -> KVError {
+    KVError::InternalError("No node available in cluster".into())
+}
+
+// FNV-1a 加上 murmur3 的 fmix64，保证不同进程、不同平台上哈希结果一致
+fn hash(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, ServiceInner, assert_res_ok};
+
+    #[test]
+    fn hash_ring_should_distribute_keys() {
+        let mut ring = HashRing::default();
+        ["n1", "n2", "n3"].iter().for_each(|n| ring.add(n));
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for i in 0..3000 {
+            *counts
+                .entry(ring.get(format!("key{}", i).as_bytes()).unwrap())
+                .or_default() += 1;
+        }
+
+        assert_eq!(counts.len(), 3);
+        assert!(counts.values().all(|&c| c > 600), "{:?}", counts);
+    }
+
+    #[test]
+    fn hash_ring_should_only_move_keys_of_changed_node() {
+        let mut ring = HashRing::default();
+        ["n1", "n2", "n3"].iter().for_each(|n| ring.add(n));
+
+        let keys: Vec<_> = (0..1000).map(|i| format!("key{}", i)).collect();
+        let before: Vec<_> = keys
+            .iter()
+            .map(|k| ring.get(k.as_bytes()).unwrap().to_owned())
+            .collect();
+
+        ring.add("n4");
+
+        for (k, old) in keys.iter().zip(&before) {
+            let new = ring.get(k.as_bytes()).unwrap();
+            assert!(new == old || new == "n4");
+        }
+
+        ring.remove("n4");
+
+        for (k, old) in keys.iter().zip(&before) {
+            assert_eq!(ring.get(k.as_bytes()).unwrap(), old);
+        }
+    }
+
+    #[test]
+    fn hash_ring_should_not_overwrite_colliding_points() {
+        let mut ring = HashRing::new(4);
+        // 假设 n1 的某个虚拟节点和 n2 的第一个虚拟节点位置相同
+        let point = hash(b"n2#0");
+        ring.ring.insert(point, "n1".into());
+
+        ring.add("n2");
+        assert_eq!(ring.ring[&point], "n1");
+        assert_eq!(ring.ring.values().filter(|n| *n == "n2").count(), 4);
+
+        ring.add("n2");
+        assert_eq!(ring.ring.len(), 5);
+
+        ring.remove("n2");
+        assert_eq!(ring.ring.len(), 1);
+        assert_eq!(ring.ring[&point], "n1");
+    }
+
+    #[tokio::test]
+    async fn cluster_should_split_and_merge_multi_key_commands() {
+        let mut cluster = new_cluster(ShardBy::Key, 3);
+        let pairs: Vec<_> = (0..20)
+            .map(|i| KvPair::new(format!("k{}", i), (i as i64).into()))
+            .collect();
+
+        let res = cluster
+            .execute(CommandRequest::new_hmset("t1", pairs.clone()))
+            .await
+            .unwrap();
+        assert_res_ok(res, &vec![Value::default(); 20], &[]);
+
+        // 数据确实分布到了多个节点上
+        let used: std::collections::HashSet<_> = (0..20)
+            .map(|i| cluster.locate("t1", &format!("k{}", i)).unwrap().to_owned())
+            .collect();
+        assert!(used.len() > 1);
+
+        let keys = vec!["k3".into(), "missing".into(), "k17".into(), "k0".into()];
+        let res = cluster
+            .execute(CommandRequest::new_hmget("t1", keys))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[3.into(), Value::default(), 17.into(), 0.into()], &[]);
+
+        let res = cluster
+            .execute(CommandRequest::new_hgetall("t1"))
+            .await
+            .unwrap();
+        let mut expected = pairs;
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_res_ok(res, &[], &expected);
+    }
+
+    #[tokio::test]
+    async fn cluster_should_merge_range_results_in_order() {
+        let mut cluster = new_cluster(ShardBy::Key, 3);
+        let pairs: Vec<_> = (0..20)
+            .map(|i| KvPair::new(format!("k{:02}", i), (i as i64).into()))
+            .collect();
+        cluster
+            .execute(CommandRequest::new_hmset("t1", pairs))
+            .await
+            .unwrap();
+
+        let keys = |res: CommandResponse| res.pairs.into_iter().map(|p| p.key).collect::<Vec<_>>();
+
+        let res = cluster
+            .execute(CommandRequest::new_hrange("t1", "k05", "k15", 3, false))
+            .await
+            .unwrap();
+        assert_eq!(keys(res), ["k05", "k06", "k07"]);
+
+        let res = cluster
+            .execute(CommandRequest::new_hrange("t1", "k05", "k15", 3, true))
+            .await
+            .unwrap();
+        assert_eq!(keys(res), ["k14", "k13", "k12"]);
+
+        let res = cluster
+            .execute(CommandRequest::new_hprefix("t1", "k1"))
+            .await
+            .unwrap();
+        let expected: Vec<_> = (10..20).map(|i| format!("k{}", i)).collect();
+        assert_eq!(keys(res), expected);
+
+        let res = cluster
+            .execute(CommandRequest::new_htables())
+            .await
+            .unwrap();
+        assert_res_ok(res, &["t1".into()], &[]);
+
+        let res = cluster
+            .execute(CommandRequest::new_hscan("t1", "k18", "", 0, 0))
+            .await
+            .unwrap();
+        assert_eq!(keys(res), ["k18", "k19"]);
+        assert!(
+            cluster
+                .execute(CommandRequest::new_hsnapshot())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn cluster_shard_by_table_should_keep_table_on_one_node() {
+        let mut cluster = new_cluster(ShardBy::Table, 3);
+
+        cluster
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await
+            .unwrap();
+        cluster
+            .execute(CommandRequest::new_hset("t1", "k2", "v2".into()))
+            .await
+            .unwrap();
+
+        let owner = cluster.locate("t1", "k1").unwrap().to_owned();
+        assert_eq!(cluster.locate("t1", "k2"), Some(owner.as_str()));
+
+        let node = cluster.nodes.get(&owner).unwrap().clone();
+        let res = node.execute(CommandRequest::new_hgetall("t1"));
+        assert_eq!(res.pairs.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn cluster_should_support_adding_and_removing_nodes() {
+        let mut cluster = new_cluster(ShardBy::Key, 2);
+
+        cluster.add_node("n9", ServiceInner::new(MemTable::new()).into());
+        assert_eq!(cluster.node_ids().count(), 3);
+
+        cluster
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await
+            .unwrap();
+        let owner = cluster.locate("t1", "k1").unwrap().to_owned();
+
+        assert!(cluster.remove_node(&owner).is_some());
+        assert_ne!(cluster.locate("t1", "k1"), Some(owner.as_str()));
+
+        // 被移除节点上的数据不会迁移
+        let res = cluster
+            .execute(CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 404);
+    }
+
+    #[tokio::test]
+    async fn cluster_should_route_each_command_of_batch() {
+        let mut cluster = new_cluster(ShardBy::Key, 3);
+        let mut cmds: Vec<_> = (0..10)
+            .map(|i| CommandRequest::new_hset("t1", format!("k{}", i), i.into()))
+            .collect();
+        cmds.push(CommandRequest::new_hsnapshot());
+        cmds.push(CommandRequest::new_hget("t1", "k3"));
+
+        let res = cluster
+            .execute(CommandRequest::new_batch(cmds, true))
+            .await
+            .unwrap();
+        let responses = res.batch.unwrap().responses;
+        assert_eq!(responses.len(), 11);
+        assert_eq!(responses[10].status, 400);
+
+        let res = cluster
+            .execute(CommandRequest::new_hget("t1", "k9"))
+            .await
+            .unwrap();
+        assert_eq!(res.values, vec![9.into()]);
+    }
+
+    #[tokio::test]
+    async fn cluster_should_pass_deadline_to_nodes() {
+        use std::time::{Duration, SystemTime};
+
+        let mut cluster = new_cluster(ShardBy::Key, 3);
+        let expired = SystemTime::now() - Duration::from_secs(1);
+        let keys = (0..10).map(|i| format!("k{}", i)).collect();
+
+        let res = cluster
+            .execute(CommandRequest::new_hmget("t1", keys).with_deadline(expired))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 504);
+
+        let res = cluster
+            .execute(
+                CommandRequest::new_batch(vec![CommandRequest::new_hget("t1", "k1")], true)
+                    .with_deadline(expired),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.batch.unwrap().responses[0].status, 504);
+    }
+
+    #[tokio::test]
+    async fn cluster_without_nodes_should_fail() {
+        let mut cluster: ClusterClient<Service> = ClusterClient::new(ShardBy::Key);
+        let res = cluster.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert!(res.is_err());
+
+        // 需要询问所有节点的命令也不能返回空的结果
+        let res = cluster.execute(CommandRequest::new_htables()).await;
+        assert_eq!(res.unwrap_err(), no_node());
+        let res = cluster.execute(CommandRequest::new_hgetall("t1")).await;
+        assert_eq!(res.unwrap_err(), no_node());
+    }
+
+    #[tokio::test]
+    async fn cluster_should_report_keys_of_failed_nodes() {
+        use crate::{TableSchema, ValueType};
+
+        let mut cluster = new_cluster(ShardBy::Key, 3);
+        // 只有一个节点上有 schema，写到这个节点上的字符串会被拒绝
+        let schema = TableSchema {
+            value_type: ValueType::Integer as i32,
+            ..Default::default()
+        };
+        let strict = cluster.locate("t1", "k0").unwrap().to_owned();
+        let node = cluster.nodes.get(&strict).unwrap().clone();
+        node.execute(CommandRequest::new_hset_schema("t1", Some(schema)));
+
+        let pairs: Vec<_> = (0..20)
+            .map(|i| KvPair::new(format!("k{}", i), "v".into()))
+            .collect();
+        let res = cluster
+            .execute(CommandRequest::new_hmset("t1", pairs))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 400);
+
+        let failed: Vec<_> = (0..20)
+            .map(|i| format!("k{}", i))
+            .filter(|k| cluster.locate("t1", k) == Some(strict.as_str()))
+            .collect();
+        assert!(
+            res.message
+                .ends_with(&format!("(failed keys: {})", failed.join(", "))),
+            "{}",
+            res.message
+        );
+
+        // 其它节点上的写入已经生效
+        for i in 0..20 {
+            let key = format!("k{}", i);
+            let res = cluster
+                .execute(CommandRequest::new_hget("t1", &key))
+                .await
+                .unwrap();
+            assert_eq!(res.status == 200, !failed.contains(&key), "{}", key);
+        }
+    }
+
+    fn new_cluster(shard_by: ShardBy, n: usize) -> ClusterClient<Service> {
+        let mut cluster = ClusterClient::new(shard_by);
+
+        for i in 0..n {
+            cluster.add_node(format!("n{}", i), ServiceInner::new(MemTable::new()).into());
+        }
+
+        cluster
+    }
+}
@@ -0,0 +1,468 @@
+//! 可移植的备份格式，用于在不同的 Storage 之间导出 / 导入数据
+//!
+//! 文件以 8 字节的 magic 开头，之后是一串 DumpFrame：一个 header、若干 chunk、一个 footer。
+//! 每个 frame 是以 varint 长度为前缀的 protobuf 消息，后面跟着消息内容的 crc32（小端）。
+//! footer 记录了 table 和 kv pair 的数量，缺少 footer 或者数量对不上都说明文件不完整。
+
+use std::{
+    io::{self, Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+
+use crate::{
+    CommandRequest, DumpChunk, DumpFooter, DumpFrame, DumpHeader, KVError, KvNode, KvPair, Storage,
+    dump_frame::Frame,
+};
+
+/// dump 文件的 magic
+const DUMP_MAGIC: &[u8; 8] = b"KVDUMP\r\n";
+/// 当前的 dump 格式版本
+pub const DUMP_VERSION: u32 = 1;
+/// 每个 chunk 最多包含的 kv pair 数量，也是在线导出时每次请求的数量
+const CHUNK_SIZE: usize = 1024;
+
+/// 导出或导入的数据量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DumpStats {
+    pub tables: u64,
+    pub pairs: u64,
+}
+
+/// 把数据按 dump 格式写入 W
+pub struct DumpWriter<W> {
+    inner: W,
+    stats: DumpStats,
+    // 上一个 chunk 所属的 table，用于统计 table 数量
+    last_table: Option<String>,
+}
+
+/// 从 R 中读取 dump 格式的数据
+pub struct DumpReader<R> {
+    inner: R,
+    header: DumpHeader,
+    stats: DumpStats,
+    last_table: Option<String>,
+    finished: bool,
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// 写入 magic 和 header
+    pub fn new(mut inner: W) -> Result<Self, KVError> {
+        inner.write_all(DUMP_MAGIC)?;
+
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut writer = Self {
+            inner,
+            stats: DumpStats::default(),
+            last_table: None,
+        };
+        writer.write_frame(Frame::Header(DumpHeader {
+            version: DUMP_VERSION,
+            created_at,
+        }))?;
+
+        Ok(writer)
+    }
+
+    /// 写入一个 table 的所有数据，按 CHUNK_SIZE 拆成多个 chunk
+    pub fn write_table(
+        &mut self,
+        table: &str,
+        pairs: impl IntoIterator<Item = KvPair>,
+    ) -> Result<(), KVError> {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+
+        for pair in pairs {
+            chunk.push(pair);
+            if chunk.len() == CHUNK_SIZE {
+                self.write_chunk(table, std::mem::take(&mut chunk))?;
+            }
+        }
+
+        self.write_chunk(table, chunk)
+    }
+
+    /// 写入一个 chunk，同一个 table 的 chunk 需要连续写入
+    pub fn write_chunk(&mut self, table: &str, pairs: Vec<KvPair>) -> Result<(), KVError> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+
+        if self.last_table.as_deref() != Some(table) {
+            self.stats.tables += 1;
+            self.last_table = Some(table.to_owned());
+        }
+        self.stats.pairs += pairs.len() as u64;
+
+        self.write_frame(Frame::Chunk(DumpChunk {
+            table: table.to_owned(),
+            pairs,
+        }))
+    }
+
+    /// 写入 footer 并 flush，返回写入的数据量
+    pub fn finish(mut self) -> Result<DumpStats, KVError> {
+        let DumpStats { tables, pairs } = self.stats;
+        self.write_frame(Frame::Footer(DumpFooter { tables, pairs }))?;
+        self.inner.flush()?;
+
+        Ok(self.stats)
+    }
+
+    fn write_frame(&mut self, frame: Frame) -> Result<(), KVError> {
+        let body = DumpFrame { frame: Some(frame) }.encode_to_vec();
+        let mut buf = Vec::with_capacity(body.len() + 14);
+
+        prost::encode_length_delimiter(body.len(), &mut buf)?;
+        buf.extend_from_slice(&body);
+        buf.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+
+        Ok(self.inner.write_all(&buf)?)
+    }
+}
+
+impl<R: Read> DumpReader<R> {
+    /// 检查 magic 并读取 header
+    pub fn new(mut inner: R) -> Result<Self, KVError> {
+        let mut magic = [0; DUMP_MAGIC.len()];
+        inner
+            .read_exact(&mut magic)
+            .map_err(|_| dump_error("missing magic"))?;
+        if &magic != DUMP_MAGIC {
+            return Err(dump_error("bad magic"));
+        }
+
+        let mut reader = Self {
+            inner,
+            header: DumpHeader::default(),
+            stats: DumpStats::default(),
+            last_table: None,
+            finished: false,
+        };
+
+        match reader.read_frame()? {
+            Some(Frame::Header(header)) if header.version == DUMP_VERSION => {
+                reader.header = header;
+                Ok(reader)
+            }
+            Some(Frame::Header(header)) => Err(dump_error(&format!(
+                "unsupported version {}",
+                header.version
+            ))),
+            _ => Err(dump_error("missing header")),
+        }
+    }
+
+    /// dump 文件的 header
+    pub fn header(&self) -> &DumpHeader {
+        &self.header
+    }
+
+    /// 读取下一个 chunk，读到 footer 时返回 None
+    pub fn next_chunk(&mut self) -> Result<Option<DumpChunk>, KVError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        match self.read_frame()? {
+            Some(Frame::Chunk(chunk)) => {
+                if self.last_table.as_ref() != Some(&chunk.table) {
+                    self.stats.tables += 1;
+                    self.last_table = Some(chunk.table.clone());
+                }
+                self.stats.pairs += chunk.pairs.len() as u64;
+
+                Ok(Some(chunk))
+            }
+            Some(Frame::Footer(footer)) => {
+                let expected = DumpStats {
+                    tables: footer.tables,
+                    pairs: footer.pairs,
+                };
+                if expected != self.stats {
+                    return Err(dump_error(&format!(
+                        "footer expects {:?}, got {:?}",
+                        expected, self.stats
+                    )));
+                }
+
+                self.finished = true;
+                Ok(None)
+            }
+            Some(Frame::Header(_)) => Err(dump_error("unexpected header")),
+            None => Err(dump_error("truncated file, missing footer")),
+        }
+    }
+
+    /// 已经读取的数据量
+    pub fn stats(&self) -> DumpStats {
+        self.stats
+    }
+
+    // 读取一个 frame，文件正好结束时返回 None
+    fn read_frame(&mut self) -> Result<Option<Frame>, KVError> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+
+        // 不按 len 预先分配内存，长度被破坏时不至于分配过多的内存
+        let mut body = Vec::new();
+        let mut crc = [0; 4];
+        (&mut self.inner).take(len as u64).read_to_end(&mut body)?;
+        if body.len() != len || self.inner.read_exact(&mut crc).is_err() {
+            return Err(dump_error("truncated frame"));
+        }
+
+        if crc32fast::hash(&body) != u32::from_le_bytes(crc) {
+            return Err(dump_error("checksum mismatch"));
+        }
+
+        let frame = DumpFrame::decode(body.as_slice())?;
+        frame
+            .frame
+            .map(Some)
+            .ok_or_else(|| dump_error("empty frame"))
+    }
+
+    // 读取 varint 长度，最多 10 个字节
+    fn read_len(&mut self) -> Result<Option<usize>, KVError> {
+        let mut buf = Vec::with_capacity(10);
+
+        loop {
+            let mut byte = [0];
+            match self.inner.read(&mut byte) {
+                Ok(0) if buf.is_empty() => return Ok(None),
+                Ok(0) => return Err(dump_error("truncated frame length")),
+                Ok(_) => buf.push(byte[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            if byte[0] & 0x80 == 0 || buf.len() == 10 {
+                break;
+            }
+        }
+
+        Ok(Some(prost::decode_length_delimiter(buf.as_slice())?))
+    }
+}
+
+/// 把 store 中所有 table 的数据导出到 w，导出的是调用时刻的快照
+pub fn dump(store: &impl Storage, w: impl Write) -> Result<DumpStats, KVError> {
+    let snapshot = store.snapshot()?;
+    let mut writer = DumpWriter::new(w)?;
+
+    for table in snapshot.tables()? {
+        let mut start = String::new();
+
+        loop {
+            let pairs = snapshot.scan(&table, &start, None, CHUNK_SIZE)?;
+            let more = next_page(&mut start, &pairs);
+            writer.write_chunk(&table, pairs)?;
+
+            if !more {
+                break;
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+/// 把 r 中的数据导入 store，每个 chunk 用一个 write_batch 写入
+pub fn restore(store: &impl Storage, r: impl Read) -> Result<DumpStats, KVError> {
+    let mut reader = DumpReader::new(r)?;
+
+    while let Some(chunk) = reader.next_chunk()? {
+        store.set_many(&chunk.table, chunk.pairs)?;
+    }
+
+    Ok(reader.stats())
+}
+
+/// 通过服务器协议导出数据：在服务器上创建快照，用 Htables 获取所有 table，再用 Hscan 分页读取
+pub async fn dump_remote(node: &mut impl KvNode, w: impl Write) -> Result<DumpStats, KVError> {
+    let res = node
+        .execute(CommandRequest::new_hsnapshot())
+        .await?
+        .into_result()?;
+    let snapshot = match res.values.into_iter().next().map(i64::try_from) {
+        Some(Ok(id)) => id as u64,
+        _ => {
+            return Err(KVError::InternalError(
+                "Server returned no snapshot id".into(),
+            ));
+        }
+    };
+
+    let result = dump_snapshot(node, snapshot, w).await;
+    // 无论导出是否成功都释放快照
+    let released = node.execute(CommandRequest::new_hrelease(snapshot)).await;
+
+    let stats = result?;
+    released?.into_result()?;
+
+    Ok(stats)
+}
+
+async fn dump_snapshot(
+    node: &mut impl KvNode,
+    snapshot: u64,
+    w: impl Write,
+) -> Result<DumpStats, KVError> {
+    let mut writer = DumpWriter::new(w)?;
+    let res = node
+        .execute(CommandRequest::new_htables_at(snapshot))
+        .await?
+        .into_result()?;
+
+    for value in res.values {
+        let table = String::try_from(value)?;
+        let mut start = String::new();
+
+        loop {
+            let cmd = CommandRequest::new_hscan(&table, &start, "", CHUNK_SIZE as u32, snapshot);
+            let pairs = node.execute(cmd).await?.into_result()?.pairs;
+            let more = next_page(&mut start, &pairs);
+            writer.write_chunk(&table, pairs)?;
+
+            if !more {
+                break;
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+/// 通过服务器协议导入数据，每个 chunk 用一个 Hmset 写入
+pub async fn restore_remote(node: &mut impl KvNode, r: impl Read) -> Result<DumpStats, KVError> {
+    let mut reader = DumpReader::new(r)?;
+
+    while let Some(chunk) = reader.next_chunk()? {
+        let cmd = CommandRequest::new_hmset(chunk.table, chunk.pairs);
+        node.execute(cmd).await?.into_result()?;
+    }
+
+    Ok(reader.stats())
+}
+
+// 根据这一页的数据更新下一页的起点，返回是否还有下一页
+fn next_page(start: &mut String, pairs: &[KvPair]) -> bool {
+    // 下一页从比最后一个 key 大的最小字符串开始
+    if let Some(last) = pairs.last() {
+        *start = format!("{}\0", last.key);
+    }
+
+    pairs.len() == CHUNK_SIZE
+}
+
+fn dump_error(msg: &str) -> KVError {
+    KVError::DumpError(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, Service, ServiceInner, SledDb, Value};
+
+    #[test]
+    fn memtable_dump_should_restore_into_sled_db() {
+        let store = MemTable::new();
+        let pairs: Vec<_> = (0..3000)
+            .map(|i| KvPair::new(format!("k{}", i), (i as i64).into()))
+            .collect();
+        store.set_many("t1", pairs).unwrap();
+        store.set("t2", "hello".into(), "world".into()).unwrap();
+        store.set("t2", "bin".into(), Value::from(1.5)).unwrap();
+        // 空的 table 不会被导出
+        store.get("t3", "nope").unwrap();
+
+        let mut buf = Vec::new();
+        let stats = dump(&store, &mut buf).unwrap();
+        assert_eq!(
+            stats,
+            DumpStats {
+                tables: 2,
+                pairs: 3002
+            }
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let sled = SledDb::new(dir.path());
+        assert_eq!(restore(&sled, buf.as_slice()).unwrap(), stats);
+
+        assert_eq!(sled.tables().unwrap(), ["t1", "t2"]);
+        for table in ["t1", "t2"] {
+            let mut expected = store.get_all(table).unwrap();
+            let mut restored = sled.get_all(table).unwrap();
+            expected.sort_by(|a, b| a.key.cmp(&b.key));
+            restored.sort_by(|a, b| a.key.cmp(&b.key));
+            assert_eq!(restored, expected);
+        }
+    }
+
+    #[test]
+    fn corrupted_dump_should_be_rejected() {
+        let store = MemTable::new();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        let mut buf = Vec::new();
+        dump(&store, &mut buf).unwrap();
+
+        // 缺少 footer
+        let truncated = &buf[..buf.len() - 3];
+        assert!(matches!(
+            restore(&MemTable::new(), truncated),
+            Err(KVError::DumpError(_))
+        ));
+
+        // 数据被修改
+        let mut corrupted = buf.clone();
+        let i = corrupted.len() / 2;
+        corrupted[i] ^= 0xff;
+        assert!(restore(&MemTable::new(), corrupted.as_slice()).is_err());
+
+        assert!(matches!(
+            restore(&MemTable::new(), &b"not a dump"[..]),
+            Err(KVError::DumpError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn dump_remote_should_page_through_tables() {
+        let mut src: Service = ServiceInner::new(MemTable::new()).into();
+        let pairs: Vec<_> = (0..2500)
+            .map(|i| KvPair::new(format!("k{:04}", i), (i as i64).into()))
+            .collect();
+        src.execute(CommandRequest::new_hmset("t1", pairs));
+        src.execute(CommandRequest::new_hset("t2", "k\0", "v".into()));
+        src.execute(CommandRequest::new_hset("t2", "k", "v".into()));
+
+        let mut buf = Vec::new();
+        let stats = dump_remote(&mut src, &mut buf).await.unwrap();
+        assert_eq!(
+            stats,
+            DumpStats {
+                tables: 2,
+                pairs: 2502
+            }
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut dst: Service<SledDb> = ServiceInner::new(SledDb::new(dir.path())).into();
+        assert_eq!(
+            restore_remote(&mut dst, buf.as_slice()).await.unwrap(),
+            stats
+        );
+
+        let res = dst.execute(CommandRequest::new_hget("t1", "k2499"));
+        assert_eq!(res.values, [Value::from(2499)]);
+        let res = dst.execute(CommandRequest::new_hgetall("t2"));
+        assert_eq!(res.pairs.len(), 2);
+    }
+}
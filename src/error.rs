@@ -1,7 +1,7 @@
-use crate::Value;
+use crate::{ErrorCategory, ErrorCode, ErrorInfo, Value};
 use thiserror::Error;
 
-#[derive(Debug, Error, PartialEq)]
+#[derive(Debug, Clone, Error, PartialEq)]
 pub enum KVError {
     #[error("Not found for table: {0}, key: {1}")]
     NotFound(String, String),
@@ -13,17 +13,270 @@ pub enum KVError {
     ConvertError(Value, &'static str),
 
     #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {3}")]
-    StorageError(&'static str, String, String, String),
+    StorageError(String, String, String, String),
 
     #[error("Sled database error: {0}")]
-    SledDbError(#[from] sled::Error),
+    SledDbError(String),
 
-    #[error("Failed to encode protobuf message")]
-    EncodeError(#[from] prost::EncodeError),
+    #[error("Redb database error: {0}")]
+    RedbError(String),
 
-    #[error("Failed to decode protobuf message")]
-    DecodeError(#[from] prost::DecodeError),
+    #[error("Failed to encode protobuf message: {0}")]
+    EncodeError(String),
+
+    #[error("Failed to decode protobuf message: {0}")]
+    DecodeError(String),
+
+    #[error("Version mismatch for table: {0}, key: {1}, expected: {2}, actual: {3}")]
+    VersionMismatch(String, String, u64, u64),
+
+    #[error("Failed to compress or decompress value: {0}")]
+    CompressionError(String),
+
+    #[error("Encryption error: {0}")]
+    CryptoError(String),
+
+    #[error("Failed to convert value with serde: {0}")]
+    SerdeError(String),
+
+    #[error("Invalid dump file: {0}")]
+    DumpError(String),
+
+    #[error("I/O error: {0}")]
+    IoError(String),
 
     #[error("Internal error: {0}")]
     InternalError(String),
+
+    #[error("Deadline exceeded: {0}")]
+    DeadlineExceeded(u64),
+
+    #[error("Request cancelled")]
+    Cancelled,
+
+    #[error("Unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    #[error("Permission denied: user {0} on table {1}")]
+    PermissionDenied(String, String),
+
+    #[error("Quota exceeded for namespace {0}: {1}")]
+    QuotaExceeded(String, String),
+
+    #[error("Rate limit exceeded for {0} commands, retry after {1}ms")]
+    RateLimited(String, u64),
+}
+
+macro_rules! impl_from_redb_error {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for KVError {
+                fn from(e: $t) -> Self {
+                    Self::RedbError(e.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_redb_error!(
+    redb::Error,
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError
+);
+
+impl From<sled::Error> for KVError {
+    fn from(e: sled::Error) -> Self {
+        Self::SledDbError(e.to_string())
+    }
+}
+
+impl From<prost::EncodeError> for KVError {
+    fn from(e: prost::EncodeError) -> Self {
+        Self::EncodeError(e.to_string())
+    }
+}
+
+impl From<prost::DecodeError> for KVError {
+    fn from(e: prost::DecodeError) -> Self {
+        Self::DecodeError(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for KVError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IoError(e.to_string())
+    }
+}
+
+impl KVError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::NotFound(..) => ErrorCode::NotFound,
+            Self::InvalidCommand(_) => ErrorCode::InvalidCommand,
+            Self::ConvertError(..) => ErrorCode::Convert,
+            Self::StorageError(..) => ErrorCode::Storage,
+            Self::SledDbError(_) => ErrorCode::Sled,
+            Self::RedbError(_) => ErrorCode::Redb,
+            Self::EncodeError(_) => ErrorCode::Encode,
+            Self::DecodeError(_) => ErrorCode::Decode,
+            Self::VersionMismatch(..) => ErrorCode::VersionMismatch,
+            Self::CompressionError(_) => ErrorCode::Compression,
+            Self::CryptoError(_) => ErrorCode::Crypto,
+            Self::SerdeError(_) => ErrorCode::Serde,
+            Self::DumpError(_) => ErrorCode::Dump,
+            Self::IoError(_) => ErrorCode::Io,
+            Self::InternalError(_) => ErrorCode::Internal,
+            Self::DeadlineExceeded(_) => ErrorCode::DeadlineExceeded,
+            Self::Cancelled => ErrorCode::Cancelled,
+            Self::Unauthenticated(_) => ErrorCode::Unauthenticated,
+            Self::PermissionDenied(..) => ErrorCode::PermissionDenied,
+            Self::QuotaExceeded(..) => ErrorCode::QuotaExceeded,
+            Self::RateLimited(..) => ErrorCode::RateLimited,
+        }
+    }
+
+    /// 存储和网络的错误可能是暂时的，版本冲突重新读取后、超时放宽截止时间后可以重试，其它的重试也没用
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::StorageError(..)
+            | Self::SledDbError(_)
+            | Self::RedbError(_)
+            | Self::VersionMismatch(..)
+            | Self::IoError(_)
+            | Self::DeadlineExceeded(_)
+            | Self::Cancelled
+            | Self::RateLimited(..) => ErrorCategory::Retryable,
+            _ => ErrorCategory::Fatal,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.category() == ErrorCategory::Retryable
+    }
+}
+
+impl From<&KVError> for ErrorInfo {
+    fn from(e: &KVError) -> Self {
+        let mut info = ErrorInfo {
+            code: e.code() as _,
+            category: e.category() as _,
+            ..Default::default()
+        };
+
+        match e.clone() {
+            KVError::NotFound(table, key) => {
+                info.table = table;
+                info.key = key;
+            }
+            KVError::ConvertError(value, expected) => {
+                info.value = Some(value);
+                info.expected_type = expected.into();
+            }
+            KVError::StorageError(command, table, key, detail) => {
+                info.command = command;
+                info.table = table;
+                info.key = key;
+                info.detail = detail;
+            }
+            KVError::VersionMismatch(table, key, expected, actual) => {
+                info.table = table;
+                info.key = key;
+                info.expected_version = expected;
+                info.actual_version = actual;
+            }
+            KVError::DeadlineExceeded(deadline) => info.deadline = deadline,
+            KVError::Cancelled => {}
+            KVError::PermissionDenied(user, table) => {
+                info.table = table;
+                info.detail = user;
+            }
+            KVError::QuotaExceeded(namespace, detail) => {
+                info.namespace = namespace;
+                info.detail = detail;
+            }
+            KVError::RateLimited(class, retry_after) => {
+                info.detail = class;
+                info.retry_after = retry_after;
+            }
+            KVError::InvalidCommand(detail)
+            | KVError::SledDbError(detail)
+            | KVError::RedbError(detail)
+            | KVError::EncodeError(detail)
+            | KVError::DecodeError(detail)
+            | KVError::CompressionError(detail)
+            | KVError::CryptoError(detail)
+            | KVError::SerdeError(detail)
+            | KVError::DumpError(detail)
+            | KVError::IoError(detail)
+            | KVError::InternalError(detail)
+            | KVError::Unauthenticated(detail) => info.detail = detail,
+        }
+
+        info
+    }
+}
+
+/// 从服务器返回的 ErrorInfo 还原出 KVError
+impl From<ErrorInfo> for KVError {
+    fn from(info: ErrorInfo) -> Self {
+        let code = info.code();
+        let detail = info.detail;
+        match code {
+            ErrorCode::NotFound => Self::NotFound(info.table, info.key),
+            ErrorCode::InvalidCommand => Self::InvalidCommand(detail),
+            ErrorCode::Convert => Self::ConvertError(
+                info.value.unwrap_or_default(),
+                type_name(&info.expected_type),
+            ),
+            ErrorCode::Storage => Self::StorageError(info.command, info.table, info.key, detail),
+            ErrorCode::Sled => Self::SledDbError(detail),
+            ErrorCode::Redb => Self::RedbError(detail),
+            ErrorCode::Encode => Self::EncodeError(detail),
+            ErrorCode::Decode => Self::DecodeError(detail),
+            ErrorCode::VersionMismatch => Self::VersionMismatch(
+                info.table,
+                info.key,
+                info.expected_version,
+                info.actual_version,
+            ),
+            ErrorCode::Compression => Self::CompressionError(detail),
+            ErrorCode::Crypto => Self::CryptoError(detail),
+            ErrorCode::Serde => Self::SerdeError(detail),
+            ErrorCode::Dump => Self::DumpError(detail),
+            ErrorCode::Io => Self::IoError(detail),
+            ErrorCode::DeadlineExceeded => Self::DeadlineExceeded(info.deadline),
+            ErrorCode::Cancelled => Self::Cancelled,
+            ErrorCode::Unauthenticated => Self::Unauthenticated(detail),
+            ErrorCode::PermissionDenied => Self::PermissionDenied(detail, info.table),
+            ErrorCode::QuotaExceeded => Self::QuotaExceeded(info.namespace, detail),
+            ErrorCode::RateLimited => Self::RateLimited(detail, info.retry_after),
+            ErrorCode::Internal | ErrorCode::Unknown => Self::InternalError(detail),
+        }
+    }
+}
+
+// ConvertError 中的类型名都是 TryFrom<Value> 里的字面量
+fn type_name(name: &str) -> &'static str {
+    const NAMES: &[&str] = &[
+        "String",
+        "Binary",
+        "Integer",
+        "Float",
+        "Bool",
+        "Unsigned",
+        "List",
+        "Map",
+        "Timestamp",
+        "Decimal",
+    ];
+
+    NAMES
+        .iter()
+        .find(|n| **n == name)
+        .copied()
+        .unwrap_or("Unknown")
 }
@@ -0,0 +1,282 @@
+//! Value 和 JSON 之间的映射
+//!
+//! | Value             | JSON                                         |
+//! |-------------------|----------------------------------------------|
+//! | 空                | `null`                                       |
+//! | String/Bool       | 字符串/布尔值                                |
+//! | Integer/Unsigned  | 数字，超过 i64 范围的非负整数读回为 Unsigned |
+//! | Float             | 带小数点的数字，NaN/±inf 为 `{"$float": "NaN" \| "inf" \| "-inf"}` |
+//! | Binary            | `{"$binary": "<base64>"}`                    |
+//! | List/Map          | 数组/对象                                    |
+//! | Timestamp         | `{"$timestamp": {"seconds": s, "nanos": n}}` |
+//! | Decimal           | `{"$decimal": "-12.50"}`                     |
+//!
+//! 只有一个 key 且 key 为上面某个 `$` 开头的名字的对象会被当成对应的类型，不是 Map。
+
+use bytes::Bytes;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map as JsonMap, Number, Value as Json, json};
+
+use crate::{Decimal, KVError, KvPair, List, Map, Timestamp, Value, value};
+
+const FLOAT: &str = "$float";
+const BINARY: &str = "$binary";
+const TIMESTAMP: &str = "$timestamp";
+const DECIMAL: &str = "$decimal";
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl Value {
+    /// 把任意可以序列化的数据转成 Value，struct 对应 Map，序列对应 List
+    ///
+    /// 数据先经过 serde_json 转换，所以 NaN/inf 会变成空的 Value，`Vec<u8>` 会变成整数的 List。
+    pub fn from_serde<T: Serialize>(data: &T) -> Result<Self, KVError> {
+        serde_json::to_value(data)
+            .map_err(|e| KVError::SerdeError(e.to_string()))?
+            .try_into()
+    }
+
+    /// 把 from_serde 得到的 Value 转回原来的类型
+    pub fn to_serde<T: DeserializeOwned>(&self) -> Result<T, KVError> {
+        serde_json::from_value(self.clone().into()).map_err(|e| KVError::SerdeError(e.to_string()))
+    }
+}
+
+impl From<Value> for Json {
+    fn from(v: Value) -> Self {
+        let Some(v) = v.value else {
+            return Json::Null;
+        };
+
+        match v {
+            value::Value::String(s) => Json::String(s),
+            value::Value::Binary(b) => json!({ BINARY: encode_base64(&b) }),
+            value::Value::Integer(i) => i.into(),
+            value::Value::Float(f) => match Number::from_f64(f) {
+                Some(n) => Json::Number(n),
+                None if f.is_nan() => json!({ FLOAT: "NaN" }),
+                None if f > 0.0 => json!({ FLOAT: "inf" }),
+                None => json!({ FLOAT: "-inf" }),
+            },
+            value::Value::Bool(b) => Json::Bool(b),
+            value::Value::List(l) => Json::Array(l.values.into_iter().map(Json::from).collect()),
+            value::Value::Map(m) => Json::Object(
+                m.entries
+                    .into_iter()
+                    .map(|p| (p.key, p.value.unwrap_or_default().into()))
+                    .collect(),
+            ),
+            value::Value::Timestamp(t) => {
+                json!({ TIMESTAMP: { "seconds": t.seconds, "nanos": t.nanos } })
+            }
+            value::Value::Unsigned(u) => u.into(),
+            value::Value::Decimal(d) => json!({ DECIMAL: d.to_string() }),
+        }
+    }
+}
+
+impl TryFrom<Json> for Value {
+    type Error = KVError;
+
+    fn try_from(v: Json) -> Result<Self, Self::Error> {
+        let v = match v {
+            Json::Null => return Ok(Value::default()),
+            Json::Bool(b) => value::Value::Bool(b),
+            Json::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+                (Some(i), _, _) => value::Value::Integer(i),
+                (None, Some(u), _) => value::Value::Unsigned(u),
+                (_, _, Some(f)) => value::Value::Float(f),
+                _ => return Err(invalid(format!("Unsupported number {}", n))),
+            },
+            Json::String(s) => value::Value::String(s),
+            Json::Array(a) => value::Value::List(List {
+                values: a
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<_, _>>()?,
+            }),
+            Json::Object(o) => from_object(o)?,
+        };
+
+        Ok(Value { value: Some(v) })
+    }
+}
+
+fn from_object(o: JsonMap<String, Json>) -> Result<value::Value, KVError> {
+    if o.len() == 1 {
+        let (k, v) = o.iter().next().unwrap();
+        match (k.as_str(), v) {
+            (FLOAT, Json::String(s)) => {
+                let f = match s.as_str() {
+                    "NaN" => f64::NAN,
+                    "inf" => f64::INFINITY,
+                    "-inf" => f64::NEG_INFINITY,
+                    _ => return Err(invalid(format!("Invalid float {}", s))),
+                };
+                return Ok(value::Value::Float(f));
+            }
+            (BINARY, Json::String(s)) => {
+                return Ok(value::Value::Binary(Bytes::from(decode_base64(s)?)));
+            }
+            (DECIMAL, Json::String(s)) => return Ok(value::Value::Decimal(s.parse::<Decimal>()?)),
+            (TIMESTAMP, Json::Object(t)) => {
+                let field = |name| t.get(name).and_then(Json::as_i64);
+                let (Some(seconds), Some(nanos)) = (field("seconds"), field("nanos")) else {
+                    return Err(invalid(format!("Invalid timestamp {}", v)));
+                };
+                let nanos = i32::try_from(nanos)
+                    .map_err(|_| invalid(format!("Invalid nanos {}", nanos)))?;
+                return Ok(value::Value::Timestamp(Timestamp { seconds, nanos }));
+            }
+            _ => {}
+        }
+    }
+
+    // serde_json 的对象按 key 排序，Map 的 entries 也是有序的
+    let entries = o
+        .into_iter()
+        .map(|(k, v)| Ok(KvPair::new(k, v.try_into()?)))
+        .collect::<Result<_, KVError>>()?;
+
+    Ok(value::Value::Map(Map { entries }))
+}
+
+fn invalid(msg: String) -> KVError {
+    KVError::SerdeError(msg)
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().fold(0u32, |n, &b| n << 8 | b as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+fn decode_base64(s: &str) -> Result<Vec<u8>, KVError> {
+    let err = || invalid(format!("Invalid base64 {}", s));
+    if !s.len().is_multiple_of(4) {
+        return Err(err());
+    }
+
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    for chunk in s.as_bytes().chunks(4) {
+        let pad = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if pad > 2 {
+            return Err(err());
+        }
+
+        let mut n = 0u32;
+        for &c in &chunk[..4 - pad] {
+            let v = BASE64.iter().position(|&b| b == c).ok_or_else(err)?;
+            n = n << 6 | v as u32;
+        }
+        n <<= 6 * pad;
+
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - pad]);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        score: f64,
+        tags: Vec<String>,
+        manager: Option<String>,
+    }
+
+    #[test]
+    fn struct_should_roundtrip_through_value() {
+        let user = User {
+            name: "tyr".into(),
+            age: 30,
+            score: 9.5,
+            tags: vec!["a".into(), "b".into()],
+            manager: None,
+        };
+
+        let v = Value::from_serde(&user).unwrap();
+        let map = BTreeMap::<String, Value>::try_from(v.clone()).unwrap();
+        assert_eq!(map["name"], "tyr".into());
+        assert_eq!(map["age"], 30.into());
+        assert_eq!(map["manager"], Value::default());
+
+        assert_eq!(v.to_serde::<User>(), Ok(user));
+        assert!(v.to_serde::<Vec<String>>().is_err());
+    }
+
+    #[test]
+    fn value_json_mapping_should_roundtrip() {
+        let values: Vec<Value> = vec![
+            Value::default(),
+            "hello".into(),
+            Bytes::from_static(b"\x00\xffbinary").into(),
+            (-42).into(),
+            u64::MAX.into(),
+            1.0.into(),
+            f64::INFINITY.into(),
+            f64::NEG_INFINITY.into(),
+            true.into(),
+            vec![1.into(), "a".into()].into(),
+            BTreeMap::from([("k".to_string(), Value::from(1))]).into(),
+            Timestamp {
+                seconds: -2,
+                nanos: 500,
+            }
+            .into(),
+            "-0.05".parse::<Decimal>().unwrap().into(),
+        ];
+
+        for v in values {
+            let s = serde_json::to_string(&v).unwrap();
+            assert_eq!(serde_json::from_str::<Value>(&s).unwrap(), v, "{}", s);
+        }
+
+        let s = serde_json::to_string(&Value::from(f64::NAN)).unwrap();
+        assert_eq!(s, r#"{"$float":"NaN"}"#);
+        let v: Value = serde_json::from_str(&s).unwrap();
+        assert!(f64::try_from(v).unwrap().is_nan());
+
+        let s = serde_json::to_string(&Value::from(Bytes::from_static(b"kv"))).unwrap();
+        assert_eq!(s, r#"{"$binary":"a3Y="}"#);
+    }
+
+    #[test]
+    fn command_response_should_serialize() {
+        let res = crate::CommandResponse::from(vec![KvPair::new("k1", 1.into())]);
+        let s = serde_json::to_string(&res).unwrap();
+        assert!(s.contains(r#""pairs":[{"key":"k1","value":1}]"#), "{}", s);
+        assert_eq!(
+            serde_json::from_str::<crate::CommandResponse>(&s).unwrap(),
+            res
+        );
+    }
+
+    #[test]
+    fn base64_should_roundtrip() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(decode_base64(&encode_base64(data)).unwrap(), data);
+        }
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+        assert!(decode_base64("Zm9=v").is_err());
+        assert!(decode_base64("Zm9v!A==").is_err());
+    }
+}
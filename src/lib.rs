@@ -1,9 +1,26 @@
+// 让 #[derive(KvRecord)] 生成的 ::kv 路径在本 crate 中也能使用
+extern crate self as kv;
+
+mod auth;
+mod cluster;
+mod dump;
 mod error;
+#[cfg(feature = "serde")]
+mod json;
+mod network;
 mod pb;
+mod rate_limit;
+mod record;
 mod service;
 mod storage;
 
+pub use auth::*;
+pub use cluster::*;
+pub use dump::*;
 pub use error::KVError;
+pub use network::*;
 pub use pb::abi::*;
+pub use rate_limit::*;
+pub use record::*;
 pub use service::*;
 pub use storage::*;
@@ -0,0 +1,854 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, Stream, StreamExt, stream};
+use prost::Message;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tracing::info;
+
+use crate::{
+    Auth, CommandRequest, CommandResponse, Identity, KVError, KvPair, NamespaceInfo, Service,
+    Storage, Value,
+    auth::AuthBackoff,
+    command_request::RequestData,
+    rate_limit::Connection,
+    service::{qualify, snapshot_not_found, unqualify, validate_namespace},
+};
+
+/// 服务器分块发送时，最多缓存多少个还没有写入 socket 的 chunk
+const CHUNK_BUFFER: usize = 4;
+/// 执行 batch 时最多预先读取多少个后续的请求
+const MAX_PENDING: usize = 16;
+
+/// 处理服务器端 accept 下来的某个 socket 的读写
+pub struct ProstServerStream<S, Store> {
+    inner: Framed<S, LengthDelimitedCodec>,
+    service: Service<Store>,
+    /// 执行 batch 期间读到的请求
+    pending: VecDeque<BytesMut>,
+    /// 通过 Auth 认证的用户
+    identity: Option<Identity>,
+    /// 认证失败后的等待时间
+    auth_backoff: AuthBackoff,
+    /// 通过 UseNamespace 绑定的 namespace，空字符串表示默认 namespace
+    namespace: String,
+    /// 这个连接的限流令牌桶
+    connection: Connection,
+    /// 这个连接创建的快照，连接只能使用自己的快照，断开时释放
+    snapshots: HashSet<u64>,
+}
+
+/// 处理客户端 socket 的读写
+pub struct ProstClientStream<S> {
+    inner: Framed<S, LengthDelimitedCodec>,
+}
+
+impl<S, Store> ProstServerStream<S, Store>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage + Send + Sync + 'static,
+{
+    pub fn new(stream: S, service: Service<Store>) -> Self {
+        Self {
+            inner: Framed::new(stream, LengthDelimitedCodec::new()),
+            service,
+            pending: VecDeque::new(),
+            identity: None,
+            auth_backoff: AuthBackoff::default(),
+            namespace: String::new(),
+            connection: Connection::new(),
+            snapshots: HashSet::new(),
+        }
+    }
+
+    /// 不断读取 CommandRequest，交给 Service 处理后写回 CommandResponse，直到对端断开
+    pub async fn process(mut self) -> Result<(), KVError> {
+        let result = self.serve().await;
+
+        for id in self.snapshots.drain() {
+            self.service.execute(CommandRequest::new_hrelease(id));
+        }
+        if let Some(limiter) = self.service.rate_limiter() {
+            limiter.disconnect(&self.connection);
+        }
+
+        result
+    }
+
+    async fn serve(&mut self) -> Result<(), KVError> {
+        loop {
+            let data = match self.pending.pop_front() {
+                Some(data) => data,
+                None => match self.inner.next().await {
+                    Some(data) => data?,
+                    None => break,
+                },
+            };
+            let mut cmd = CommandRequest::decode(data)?;
+
+            // Auth 中有密码，不能打印到日志里
+            if let Some(RequestData::Auth(auth)) = cmd.request_data {
+                info!("Got an auth command");
+                let res = self.authenticate(auth).await;
+                self.inner.send(Bytes::from(res.encode_to_vec())).await?;
+                continue;
+            }
+            // batch 中的 Auth 不会执行，但也有密码，要在打印日志之前拒绝
+            if contains_auth(&cmd) {
+                info!("Got a batch with an auth command");
+                let res = CommandResponse::from(KVError::InvalidCommand(
+                    "Auth cannot be used in a batch".into(),
+                ));
+                self.inner.send(Bytes::from(res.encode_to_vec())).await?;
+                continue;
+            }
+            info!("Got a new command: {:?}", cmd);
+
+            // 按带 namespace 的 table 名授权，出错时返回的也是这个名字
+            if !self.namespace.is_empty() {
+                qualify(&self.namespace, &mut cmd);
+            }
+            let checked = self
+                .authorize(&cmd)
+                .and_then(|_| self.check_snapshots(&cmd))
+                .and_then(|_| self.throttle(&cmd));
+            if let Err(e) = checked {
+                let res = CommandResponse::from(e);
+                self.inner.send(Bytes::from(res.encode_to_vec())).await?;
+                continue;
+            }
+
+            // 执行之后要根据结果记录创建和释放的快照
+            let tracked = commands(&cmd)
+                .iter()
+                .any(|c| {
+                    matches!(
+                        c.request_data,
+                        Some(RequestData::Hsnapshot(_) | RequestData::Hrelease(_))
+                    )
+                })
+                .then(|| cmd.clone());
+
+            let mut res = match &cmd.request_data {
+                Some(RequestData::UseNamespace(v)) => self.use_namespace(v.namespace.clone()),
+                Some(RequestData::Hgetall(v)) if v.chunk_size > 0 => {
+                    if !self.send_chunks(cmd).await? {
+                        // 客户端已经断开
+                        break;
+                    }
+                    continue;
+                }
+                Some(
+                    RequestData::Batch(_)
+                    | RequestData::Hgetall(_)
+                    | RequestData::Hrange(_)
+                    | RequestData::Hprefix(_)
+                    | RequestData::Hscan(_),
+                ) => match self.execute_cancellable(cmd).await? {
+                    Some(res) => res,
+                    // 客户端已经断开
+                    None => break,
+                },
+                _ => self.service.execute(cmd),
+            };
+            if let Some(cmd) = tracked {
+                self.track_snapshots(&cmd, &res);
+            }
+            if !self.namespace.is_empty() {
+                unqualify(&self.namespace, &mut res);
+            }
+            self.inner.send(Bytes::from(res.encode_to_vec())).await?;
+        }
+
+        Ok(())
+    }
+
+    // 快照 id 是全局的，只允许使用这个连接自己创建的快照，其它的当作不存在
+    fn check_snapshots(&self, cmd: &CommandRequest) -> Result<(), KVError> {
+        for cmd in commands(cmd) {
+            let id = match &cmd.request_data {
+                Some(RequestData::Hrelease(v)) => v.snapshot,
+                Some(data) => data.snapshot(),
+                None => 0,
+            };
+            if id != 0 && !self.snapshots.contains(&id) {
+                return Err(snapshot_not_found(id));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn track_snapshots(&mut self, cmd: &CommandRequest, res: &CommandResponse) {
+        let responses = match &res.batch {
+            Some(batch) => &batch.responses[..],
+            None => std::slice::from_ref(res),
+        };
+
+        for (cmd, res) in commands(cmd).iter().zip(responses) {
+            match &cmd.request_data {
+                Some(RequestData::Hsnapshot(_)) if res.status == 200 => {
+                    if let Some(Ok(id)) = res.values.first().cloned().map(i64::try_from) {
+                        self.snapshots.insert(id as u64);
+                    }
+                }
+                Some(RequestData::Hrelease(v)) => {
+                    self.snapshots.remove(&v.snapshot);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn throttle(&mut self, cmd: &CommandRequest) -> Result<(), KVError> {
+        match self.service.rate_limiter() {
+            Some(limiter) => {
+                let identity = self.identity.as_ref().map(|i| i.name());
+                limiter.acquire(&mut self.connection, identity, cmd)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // 认证失败时连接回到未认证的状态，并且在一段时间内拒绝这个连接的 Auth
+    async fn authenticate(&mut self, auth: Auth) -> CommandResponse {
+        if self.service.acl().is_none() {
+            return auth_disabled().into();
+        }
+        if let Err(e) = self.auth_backoff.check(Instant::now()) {
+            return e.into();
+        }
+
+        // argon2 校验很慢，在 blocking 线程中执行，不阻塞同一个 worker 上的其它连接
+        let service = self.service.clone();
+        let result = tokio::task::spawn_blocking(move || match service.acl() {
+            Some(acl) => acl.authenticate(&auth),
+            None => Err(auth_disabled()),
+        })
+        .await
+        .unwrap_or_else(|e| Err(KVError::InternalError(e.to_string())));
+        self.auth_backoff.record(result.is_ok(), Instant::now());
+
+        match result {
+            Ok(identity) => {
+                let name = identity.name().to_owned();
+                self.identity = Some(identity);
+                Value::from(name).into()
+            }
+            Err(e) => {
+                self.identity = None;
+                e.into()
+            }
+        }
+    }
+
+    fn use_namespace(&mut self, namespace: String) -> CommandResponse {
+        if !namespace.is_empty()
+            && let Err(e) = validate_namespace(&namespace)
+        {
+            return e.into();
+        }
+
+        self.namespace = namespace.clone();
+        Value::from(namespace).into()
+    }
+
+    fn authorize(&self, cmd: &CommandRequest) -> Result<(), KVError> {
+        if self.service.acl().is_none() {
+            return Ok(());
+        }
+
+        match &self.identity {
+            Some(identity) => identity.check_in(&self.namespace, cmd),
+            None => Err(KVError::Unauthenticated("Authentication required".into())),
+        }
+    }
+
+    // batch 和扫描可能执行很久，在 blocking 线程中执行，同时检查客户端是否断开，断开时取消还在执行的命令
+    async fn execute_cancellable(
+        &mut self,
+        cmd: CommandRequest,
+    ) -> Result<Option<CommandResponse>, KVError> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let service = self.service.clone();
+        let flag = cancelled.clone();
+        let mut task = tokio::task::spawn_blocking(move || service.execute_cancellable(cmd, &flag));
+
+        loop {
+            tokio::select! {
+                res = &mut task => {
+                    return res.map(Some).map_err(|e| KVError::InternalError(e.to_string()));
+                }
+                // 客户端可能在等待结果之前就发送了后面的请求，先保存起来
+                data = self.inner.next(), if self.pending.len() < MAX_PENDING => match data {
+                    Some(Ok(data)) => self.pending.push_back(data),
+                    _ => {
+                        cancelled.store(true, Ordering::Relaxed);
+                        task.await.ok();
+                        return Ok(None);
+                    }
+                },
+            }
+        }
+    }
+
+    // 在 blocking 线程中遍历存储，通过有界 channel 把每个 chunk 发给客户端；
+    // 客户端读得慢时 send 会等待，channel 满了之后遍历也会暂停。
+    // 和 execute_cancellable 一样同时检查客户端是否断开，断开时取消遍历并返回 false
+    async fn send_chunks(&mut self, cmd: CommandRequest) -> Result<bool, KVError> {
+        let (tx, mut rx) = mpsc::channel(CHUNK_BUFFER);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let service = self.service.clone();
+        let flag = cancelled.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            service.execute_chunked_cancellable(cmd, &flag, |res| tx.blocking_send(res).is_ok());
+        });
+
+        let result = loop {
+            tokio::select! {
+                res = rx.recv() => {
+                    let Some(mut res) = res else {
+                        break Ok(true);
+                    };
+                    if !self.namespace.is_empty() {
+                        unqualify(&self.namespace, &mut res);
+                    }
+                    if let Err(e) = self.inner.send(Bytes::from(res.encode_to_vec())).await {
+                        break Err(e.into());
+                    }
+                }
+                data = self.inner.next(), if self.pending.len() < MAX_PENDING => match data {
+                    Some(Ok(data)) => self.pending.push_back(data),
+                    _ => break Ok(false),
+                },
+            }
+        };
+
+        // 出错或者客户端断开时，blocking 线程中的遍历在下一个 chunk 之前停止
+        if !matches!(result, Ok(true)) {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+        drop(rx);
+        task.await
+            .map_err(|e| KVError::InternalError(e.to_string()))?;
+
+        result
+    }
+}
+
+fn auth_disabled() -> KVError {
+    KVError::InvalidCommand("Authentication is not enabled".into())
+}
+
+// 命令或者（嵌套的）batch 中是否有 Auth
+fn contains_auth(cmd: &CommandRequest) -> bool {
+    match &cmd.request_data {
+        Some(RequestData::Auth(_)) => true,
+        Some(RequestData::Batch(batch)) => batch.requests.iter().any(contains_auth),
+        _ => false,
+    }
+}
+
+// 命令本身，或者 batch 中的所有命令
+fn commands(cmd: &CommandRequest) -> &[CommandRequest] {
+    match &cmd.request_data {
+        Some(RequestData::Batch(batch)) => &batch.requests,
+        _ => std::slice::from_ref(cmd),
+    }
+}
+
+impl<S> ProstClientStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: Framed::new(stream, LengthDelimitedCodec::new()),
+        }
+    }
+
+    /// 发送一个 CommandRequest 并等待服务器的 CommandResponse
+    ///
+    /// 分块返回的结果会被合并成一个 CommandResponse，需要逐个处理时使用 hgetall_stream。
+    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KVError> {
+        self.inner.send(Bytes::from(cmd.encode_to_vec())).await?;
+
+        let mut res = self.recv().await?;
+        while res.has_more {
+            let next = self.recv().await?;
+            res.pairs.extend(next.pairs);
+            res.has_more = next.has_more;
+        }
+
+        Ok(res)
+    }
+
+    /// 分块读取 table 中的所有 kv pair，只有在返回的 Stream 被 poll 时才读取下一个 chunk
+    ///
+    /// Stream 在第一个错误之后结束。没有读完就 drop 时，剩下的 chunk 还在连接中，这个连接不能再使用。
+    pub async fn hgetall_stream(
+        &mut self,
+        table: impl Into<String>,
+        chunk_size: u32,
+    ) -> Result<impl Stream<Item = Result<KvPair, KVError>> + '_, KVError> {
+        let cmd = CommandRequest::new_hgetall_chunked(table, chunk_size.max(1));
+        self.inner.send(Bytes::from(cmd.encode_to_vec())).await?;
+
+        let state = (self, VecDeque::new(), true);
+        Ok(stream::try_unfold(
+            state,
+            |(client, mut pairs, mut has_more)| async move {
+                loop {
+                    if let Some(pair) = pairs.pop_front() {
+                        return Ok(Some((pair, (client, pairs, has_more))));
+                    }
+                    if !has_more {
+                        return Ok(None);
+                    }
+
+                    let res = client.recv().await?.into_result()?;
+                    has_more = res.has_more;
+                    pairs.extend(res.pairs);
+                }
+            },
+        ))
+    }
+
+    async fn recv(&mut self) -> Result<CommandResponse, KVError> {
+        match self.inner.next().await {
+            Some(data) => Ok(CommandResponse::decode(data?)?),
+            None => Err(KVError::IoError("Connection closed by server".into())),
+        }
+    }
+
+    /// 用 token 认证这个连接，返回用户名
+    pub async fn auth_token(&mut self, token: impl Into<String>) -> Result<String, KVError> {
+        self.auth(CommandRequest::new_auth_token(token)).await
+    }
+
+    /// 用用户名和密码认证这个连接
+    pub async fn auth_password(
+        &mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<String, KVError> {
+        self.auth(CommandRequest::new_auth_password(username, password))
+            .await
+    }
+
+    async fn auth(&mut self, cmd: CommandRequest) -> Result<String, KVError> {
+        let res = self.execute(cmd).await?.into_result()?;
+        let name = res.values.into_iter().next().unwrap_or_default();
+
+        name.try_into()
+    }
+
+    /// 把这个连接绑定到 namespace，空字符串表示默认 namespace
+    pub async fn use_namespace(&mut self, namespace: impl Into<String>) -> Result<(), KVError> {
+        self.execute(CommandRequest::new_use_namespace(namespace))
+            .await?
+            .into_result()
+            .map(|_| ())
+    }
+
+    /// 列出所有 namespace 和它们的用量
+    pub async fn list_namespaces(&mut self) -> Result<Vec<NamespaceInfo>, KVError> {
+        let res = self
+            .execute(CommandRequest::new_list_namespaces())
+            .await?
+            .into_result()?;
+
+        Ok(res.namespaces)
+    }
+
+    /// 删除 namespace 中的所有数据，返回删除的 key 的数量
+    pub async fn drop_namespace(&mut self, namespace: impl Into<String>) -> Result<u64, KVError> {
+        let res = self
+            .execute(CommandRequest::new_drop_namespace(namespace))
+            .await?
+            .into_result()?;
+        let deleted = res.values.into_iter().next().unwrap_or_default();
+
+        Ok(i64::try_from(deleted)? as u64)
+    }
+
+    /// 用一个 CommandBatch 发送多个命令，返回每个已执行命令的结果
+    pub async fn execute_batch(
+        &mut self,
+        cmds: Vec<CommandRequest>,
+        stop_on_error: bool,
+    ) -> Result<Vec<CommandResponse>, KVError> {
+        let res = self
+            .execute(CommandRequest::new_batch(cmds, stop_on_error))
+            .await?
+            .into_result()?;
+
+        Ok(res.batch.unwrap_or_default().responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{
+        Acl, ClassLimits, MemTable, NamespaceConfig, Permission, Quota, Rate, RateLimitConfig,
+        ServiceInner, assert_res_error, assert_res_ok,
+    };
+
+    #[tokio::test]
+    async fn client_server_basic_communication_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // 发送 HSET，等待回应
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(cmd).await?;
+
+        // 第一次 HSET 服务器应该返回 None（Value::default()）
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        // 再发一个 HGET
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client.execute(cmd).await?;
+
+        // 服务器应该返回上一次的结果
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_batch_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+
+        let cmds = vec![
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hget("t1", "k1"),
+            CommandRequest::new_hget("t1", "k2"),
+        ];
+        let responses = client.execute_batch(cmds, false).await?;
+
+        assert_eq!(responses.len(), 3);
+        assert_res_ok(responses[1].clone(), &["v1".into()], &[]);
+        assert_eq!(responses[2].status, 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_stream_hgetall_in_chunks() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+
+        let pairs: Vec<_> = (0..1000)
+            .map(|i| KvPair::new(format!("k{:04}", i), i.into()))
+            .collect();
+        client
+            .execute(CommandRequest::new_hmset("t1", pairs.clone()))
+            .await?;
+
+        let stream = client.hgetall_stream("t1", 64).await?;
+        let mut streamed: Vec<_> = stream
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        streamed.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(streamed, pairs);
+
+        // execute 会合并所有 chunk，连接可以继续使用
+        let mut res = client
+            .execute(CommandRequest::new_hgetall_chunked("t1", 100))
+            .await?;
+        assert!(!res.has_more);
+        res.pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(res.pairs, pairs);
+
+        let stream = client.hgetall_stream("empty", 64).await?;
+        assert_eq!(stream.count().await, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_sent_during_batch_should_be_kept() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+
+        let cmds = (0..1000)
+            .map(|i| CommandRequest::new_hset("t1", format!("k{}", i), i.into()))
+            .collect();
+        let batch = CommandRequest::new_batch(cmds, true);
+        let get = CommandRequest::new_hget("t1", "k999");
+
+        // 不等待 batch 的结果，直接发送下一个请求
+        for cmd in [batch, get] {
+            client.inner.send(Bytes::from(cmd.encode_to_vec())).await?;
+        }
+
+        let res = client.recv().await?;
+        assert_eq!(res.batch.unwrap().responses.len(), 1000);
+        assert_res_ok(client.recv().await?, &[999.into()], &[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connection_should_require_auth_when_acl_is_set() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut acl = Acl::new();
+        acl.add_token("ci", "t0ken");
+        acl.grant("ci", Permission::Write, "t1");
+        let service: Service = ServiceInner::new(MemTable::new()).acl(acl).into();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            ProstServerStream::new(stream, service).process().await.ok();
+        });
+
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_error(res, 401, "Authentication required");
+
+        assert_eq!(
+            client.auth_token("wrong").await,
+            Err(KVError::Unauthenticated("Invalid credentials".into()))
+        );
+        // 认证失败后立即重试会被拒绝，等待之后才能再次认证
+        let Err(KVError::RateLimited(class, ms)) = client.auth_token("t0ken").await else {
+            panic!("auth should be throttled after a failure");
+        };
+        assert_eq!(class, "auth");
+        let wait = Duration::from_millis(ms);
+        tokio::task::spawn_blocking(move || std::thread::sleep(wait)).await?;
+        assert_eq!(client.auth_token("t0ken").await?, "ci");
+
+        let auth = CommandRequest::new_auth_password("ci", "secret");
+        let batch = CommandRequest::new_batch(vec![auth], false);
+        let res = client.execute(batch).await?;
+        assert_res_error(res, 400, "Auth cannot be used in a batch");
+
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = client.execute(CommandRequest::new_hget("t2", "k1")).await?;
+        assert_res_error(res, 403, "Permission denied");
+        assert_eq!(
+            client
+                .execute_batch(vec![CommandRequest::new_htables()], false)
+                .await,
+            Err(KVError::PermissionDenied("ci".into(), "*".into()))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn snapshots_should_belong_to_connection() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mut acl = Acl::new();
+        acl.add_token("tenant", "t0ken");
+        acl.grant("tenant", Permission::Write, "team_a/*");
+        acl.add_token("admin", "adm1n");
+        acl.grant("admin", Permission::Admin, "*");
+        let service: Service = ServiceInner::new(MemTable::new()).acl(acl).into();
+        let server = service.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(ProstServerStream::new(stream, server.clone()).process());
+            }
+        });
+
+        // namespace 中的用户可以创建快照，但只能读取自己 namespace 中的 table
+        let mut a = ProstClientStream::new(TcpStream::connect(addr).await?);
+        a.auth_token("t0ken").await?;
+        a.use_namespace("team_a").await?;
+        a.execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        let res = a.execute(CommandRequest::new_hsnapshot()).await?;
+        let id = i64::try_from(res.values[0].clone())? as u64;
+        let res = a
+            .execute(CommandRequest::new_hget_at("t1", "k1", id))
+            .await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        // 其它连接即使有权限也不能使用这个快照
+        let mut b = ProstClientStream::new(TcpStream::connect(addr).await?);
+        b.auth_token("adm1n").await?;
+        let res = b
+            .execute(CommandRequest::new_hget_at("team_a/t1", "k1", id))
+            .await?;
+        assert_res_error(res, 400, "not found");
+        let res = b.execute(CommandRequest::new_hrelease(id)).await?;
+        assert_res_error(res, 400, "not found");
+
+        // 连接断开后快照被释放
+        drop(a);
+        for _ in 0..100 {
+            let res = service.execute(CommandRequest::new_hget_at("team_a/t1", "k1", id));
+            if res.status == 400 {
+                return Ok(());
+            }
+            tokio::task::spawn_blocking(|| std::thread::sleep(Duration::from_millis(10))).await?;
+        }
+        panic!("snapshot {} was not released", id);
+    }
+
+    #[tokio::test]
+    async fn auth_should_fail_without_acl() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+
+        assert!(matches!(
+            client.auth_password("alice", "secret").await,
+            Err(KVError::InvalidCommand(_))
+        ));
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_eq!(res.status, 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn namespaces_should_isolate_tables() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let config = NamespaceConfig {
+            quota: Quota {
+                max_keys: 1,
+                max_bytes: 0,
+            },
+            ..Default::default()
+        };
+        let service: Service = ServiceInner::new(MemTable::new()).namespaces(config).into();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(ProstServerStream::new(stream, service.clone()).process());
+            }
+        });
+
+        let mut a = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let mut b = ProstClientStream::new(TcpStream::connect(addr).await?);
+        a.use_namespace("team_a").await?;
+        b.use_namespace("team_b").await?;
+
+        a.execute(CommandRequest::new_hset("users", "k1", "a".into()))
+            .await?;
+        let res = b.execute(CommandRequest::new_hget("users", "k1")).await?;
+        assert_eq!(
+            res.into_result(),
+            Err(KVError::NotFound("users".into(), "k1".into()))
+        );
+        let res = a.execute(CommandRequest::new_htables()).await?;
+        assert_res_ok(res, &["users".into()], &[]);
+        let res = a
+            .execute(CommandRequest::new_hgetall_chunked("users", 10))
+            .await?;
+        assert_res_ok(res, &[], &[KvPair::new("k1", "a".into())]);
+        let res = b
+            .execute(CommandRequest::new_hgetall_chunked("users", 10))
+            .await?;
+        assert_res_ok(res, &[], &[]);
+
+        let res = a
+            .execute(CommandRequest::new_hset("users", "k2", "a".into()))
+            .await?;
+        assert_res_error(res, 507, "Quota exceeded for namespace team_a");
+        assert!(a.use_namespace("a/b").await.is_err());
+
+        // 默认 namespace 可以看到所有 namespace 中的 table
+        a.use_namespace("").await?;
+        let res = a.execute(CommandRequest::new_htables()).await?;
+        assert_res_ok(res, &["team_a/users".into()], &[]);
+
+        let namespaces = a.list_namespaces().await?;
+        assert_eq!(namespaces.len(), 1);
+        assert_eq!((namespaces[0].keys, namespaces[0].max_keys), (1, 1));
+        assert_eq!(a.drop_namespace("team_a").await?, 1);
+        assert!(a.list_namespaces().await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_over_rate_limit_should_get_429() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let config = RateLimitConfig {
+            connection: ClassLimits {
+                write: Some(Rate::new(1.0, 1.0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let service: Service = ServiceInner::new(MemTable::new()).rate_limit(config).into();
+        let server = service.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            ProstServerStream::new(stream, server).process().await.ok();
+        });
+
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        let set = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_eq!(client.execute(set.clone()).await?.status, 200);
+
+        let res = client.execute(set).await?;
+        assert_res_error(res.clone(), 429, "Rate limit exceeded for write commands");
+        assert!(matches!(res.into_result(), Err(KVError::RateLimited(_, ms)) if ms > 0));
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        let limiter = service.rate_limiter().unwrap();
+        let stats = limiter.stats()[""];
+        assert_eq!((stats.allowed, stats.throttled), (2, 1));
+        let stats: Vec<_> = limiter.connection_stats().into_values().collect();
+        assert_eq!(stats, vec![stats[0]]);
+        assert_eq!((stats[0].allowed, stats[0].throttled), (2, 1));
+
+        // 连接断开后不再保留它的统计
+        drop(client);
+        while !limiter.connection_stats().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    async fn start_server() -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service: Service = ServiceInner::new(MemTable::new()).into();
+                let server = ProstServerStream::new(stream, service);
+                tokio::spawn(server.process());
+            }
+        });
+
+        Ok(addr)
+    }
+}
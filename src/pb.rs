@@ -4,6 +4,12 @@ use abi::{command_request::RequestData, *};
 use bytes::Bytes;
 use http::StatusCode;
 use prost::Message;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::KVError;
 
@@ -15,16 +21,65 @@ impl CommandRequest {
                 table: table.into(),
                 pair: Some(KvPair::new(key, value)),
             })),
+            ..Default::default()
         }
     }
 
     /// 创建 Hget 命令
     pub fn new_hget(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self::new_hget_at(table, key, 0)
+    }
+
+    /// 创建从快照中读取的 Hget 命令
+    pub fn new_hget_at(table: impl Into<String>, key: impl Into<String>, snapshot: u64) -> Self {
         Self {
             request_data: Some(RequestData::Hget(Hget {
                 table: table.into(),
                 key: key.into(),
+                snapshot,
+                as_of: 0,
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建读取某个时间点（unix 时间戳，毫秒）的 value 的 Hget 命令
+    pub fn new_hget_as_of(table: impl Into<String>, key: impl Into<String>, as_of: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hget(Hget {
+                table: table.into(),
+                key: key.into(),
+                snapshot: 0,
+                as_of,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HgetVersion 命令
+    pub fn new_hget_version(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        version: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HgetVersion(HgetVersion {
+                table: table.into(),
+                key: key.into(),
+                version,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hhistory 命令
+    pub fn new_hhistory(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hhistory(Hhistory {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
         }
     }
 
@@ -33,7 +88,42 @@ impl CommandRequest {
         Self {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
+                chunk_size: 0,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建分块返回的 Hgetall 命令
+    pub fn new_hgetall_chunked(table: impl Into<String>, chunk_size: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetall(Hgetall {
+                table: table.into(),
+                chunk_size,
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hmget 命令
+    pub fn new_hmget(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmget(Hmget {
+                table: table.into(),
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hmset 命令
+    pub fn new_hmset(table: impl Into<String>, pairs: Vec<KvPair>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmset(Hmset {
+                table: table.into(),
+                pairs,
+            })),
+            ..Default::default()
         }
     }
 
@@ -44,6 +134,261 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hmdel 命令
+    pub fn new_hmdel(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmdel(Hmdel {
+                table: table.into(),
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hexist 命令
+    pub fn new_hexist(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexist(Hexist {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hmexist 命令
+    pub fn new_hmexist(table: impl Into<String>, keys: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmexist(Hmexist {
+                table: table.into(),
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hrange 命令，end 为空表示没有上界，limit 为 0 表示不限数量
+    pub fn new_hrange(
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        limit: u32,
+        reverse: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hrange(Hrange {
+                table: table.into(),
+                start: start.into(),
+                end: end.into(),
+                limit,
+                reverse,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hprefix 命令
+    pub fn new_hprefix(table: impl Into<String>, prefix: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hprefix(Hprefix {
+                table: table.into(),
+                prefix: prefix.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Htables 命令
+    pub fn new_htables() -> Self {
+        Self::new_htables_at(0)
+    }
+
+    /// 创建返回快照中 table 的 Htables 命令
+    pub fn new_htables_at(snapshot: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Htables(Htables {
+                snapshot,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hscan 命令，end 为空表示没有上界，snapshot 为 0 表示读取当前数据
+    pub fn new_hscan(
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        limit: u32,
+        snapshot: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                start: start.into(),
+                end: end.into(),
+                limit,
+                snapshot,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hsnapshot 命令
+    pub fn new_hsnapshot() -> Self {
+        Self {
+            request_data: Some(RequestData::Hsnapshot(Hsnapshot {})),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 Hrelease 命令
+    pub fn new_hrelease(snapshot: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hrelease(Hrelease { snapshot })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HsetSchema 命令，schema 为 None 表示删除 table 的 schema
+    pub fn new_hset_schema(table: impl Into<String>, schema: Option<TableSchema>) -> Self {
+        Self {
+            request_data: Some(RequestData::HsetSchema(HsetSchema {
+                table: table.into(),
+                schema,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 HgetSchema 命令
+    pub fn new_hget_schema(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::HgetSchema(HgetSchema {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 设置截止时间，服务器在这之后不再执行这个命令
+    pub fn with_deadline(mut self, deadline: SystemTime) -> Self {
+        self.deadline = deadline
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| (d.as_millis() as u64).max(1));
+        self
+    }
+
+    /// 设置从现在开始的超时时间
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(SystemTime::now() + timeout)
+    }
+
+    /// 创建 CommandBatch 命令
+    pub fn new_batch(requests: Vec<CommandRequest>, stop_on_error: bool) -> Self {
+        Self {
+            request_data: Some(RequestData::Batch(CommandBatch {
+                requests,
+                stop_on_error,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建用 token 认证的 Auth 命令
+    pub fn new_auth_token(token: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Auth(Auth {
+                token: token.into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建绑定 namespace 的 UseNamespace 命令，空字符串表示默认 namespace
+    pub fn new_use_namespace(namespace: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::UseNamespace(UseNamespace {
+                namespace: namespace.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 ListNamespaces 命令
+    pub fn new_list_namespaces() -> Self {
+        Self {
+            request_data: Some(RequestData::ListNamespaces(ListNamespaces {})),
+            ..Default::default()
+        }
+    }
+
+    /// 创建 DropNamespace 命令
+    pub fn new_drop_namespace(namespace: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::DropNamespace(DropNamespace {
+                namespace: namespace.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// 创建用用户名和密码认证的 Auth 命令
+    pub fn new_auth_password(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Auth(Auth {
+                username: username.into(),
+                password: password.into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+}
+
+impl RequestData {
+    /// 命令读取的快照 id，0 表示读取当前数据
+    pub fn snapshot(&self) -> u64 {
+        match self {
+            RequestData::Hget(v) => v.snapshot,
+            RequestData::Htables(v) => v.snapshot,
+            RequestData::Hscan(v) => v.snapshot,
+            _ => 0,
+        }
+    }
+
+    /// 命令操作的 table，没有 table 的命令返回 None
+    pub fn table_mut(&mut self) -> Option<&mut String> {
+        match self {
+            RequestData::Hget(v) => Some(&mut v.table),
+            RequestData::Hgetall(v) => Some(&mut v.table),
+            RequestData::Hmget(v) => Some(&mut v.table),
+            RequestData::Hset(v) => Some(&mut v.table),
+            RequestData::Hmset(v) => Some(&mut v.table),
+            RequestData::Hdel(v) => Some(&mut v.table),
+            RequestData::Hmdel(v) => Some(&mut v.table),
+            RequestData::Hexist(v) => Some(&mut v.table),
+            RequestData::Hmexist(v) => Some(&mut v.table),
+            RequestData::Hrange(v) => Some(&mut v.table),
+            RequestData::Hprefix(v) => Some(&mut v.table),
+            RequestData::Hscan(v) => Some(&mut v.table),
+            RequestData::HgetVersion(v) => Some(&mut v.table),
+            RequestData::Hhistory(v) => Some(&mut v.table),
+            RequestData::HsetSchema(v) => Some(&mut v.table),
+            RequestData::HgetSchema(v) => Some(&mut v.table),
+            RequestData::Htables(_)
+            | RequestData::Hsnapshot(_)
+            | RequestData::Hrelease(_)
+            | RequestData::Batch(_)
+            | RequestData::Auth(_)
+            | RequestData::UseNamespace(_)
+            | RequestData::ListNamespaces(_)
+            | RequestData::DropNamespace(_) => None,
         }
     }
 }
@@ -103,6 +448,71 @@ impl From<bool> for Value {
     }
 }
 
+/// 从 i32 转成 Value，有了 u64 的转换后，整数字面量默认按 i32 推导
+impl From<i32> for Value {
+    fn from(i: i32) -> Self {
+        Self {
+            value: Some(value::Value::Integer(i as i64)),
+        }
+    }
+}
+
+/// 从 u64 转成 Value
+impl From<u64> for Value {
+    fn from(u: u64) -> Self {
+        Self {
+            value: Some(value::Value::Unsigned(u)),
+        }
+    }
+}
+
+/// 从 Vec<Value> 转成 List 类型的 Value
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Self {
+            value: Some(value::Value::List(List { values })),
+        }
+    }
+}
+
+/// 从 BTreeMap 转成 Map 类型的 Value
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(map: BTreeMap<String, Value>) -> Self {
+        let entries = map.into_iter().map(|(k, v)| KvPair::new(k, v)).collect();
+
+        Self {
+            value: Some(value::Value::Map(Map { entries })),
+        }
+    }
+}
+
+/// 从 SystemTime 转成 Value
+impl From<SystemTime> for Value {
+    fn from(t: SystemTime) -> Self {
+        Self {
+            value: Some(value::Value::Timestamp(t.into())),
+        }
+    }
+}
+
+/// 从 Timestamp 转成 Value
+impl From<Timestamp> for Value {
+    fn from(t: Timestamp) -> Self {
+        Self {
+            value: Some(value::Value::Timestamp(t)),
+        }
+    }
+}
+
+/// 从 Decimal 转成 Value
+impl From<Decimal> for Value {
+    fn from(d: Decimal) -> Self {
+        Self {
+            value: Some(value::Value::Decimal(d)),
+        }
+    }
+}
+
 /// 从 Value 转成 CommandResponse
 impl From<Value> for CommandResponse {
     fn from(v: Value) -> Self {
@@ -136,19 +546,75 @@ impl From<Vec<KvPair>> for CommandResponse {
     }
 }
 
+/// 从 Vec<Version> 转成 CommandResponse
+impl From<Vec<Version>> for CommandResponse {
+    fn from(v: Vec<Version>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            versions: v,
+            ..Default::default()
+        }
+    }
+}
+
+/// 从 CommandBatchResponse 转成 CommandResponse
+impl From<CommandBatchResponse> for CommandResponse {
+    fn from(v: CommandBatchResponse) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            batch: Some(v),
+            ..Default::default()
+        }
+    }
+}
+
+/// 从 ListNamespaces 的结果转成 CommandResponse
+impl From<Vec<NamespaceInfo>> for CommandResponse {
+    fn from(v: Vec<NamespaceInfo>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            namespaces: v,
+            ..Default::default()
+        }
+    }
+}
+
+/// 从 TableSchema 转成 CommandResponse
+impl From<Option<TableSchema>> for CommandResponse {
+    fn from(v: Option<TableSchema>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            schema: v,
+            ..Default::default()
+        }
+    }
+}
+
 /// 从 KVError 转成 CommandResponse
 impl From<KVError> for CommandResponse {
     fn from(e: KVError) -> Self {
         let mut result = Self {
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
             message: e.to_string(),
-            values: vec![],
-            pairs: vec![],
+            error: Some(ErrorInfo::from(&e)),
+            ..Default::default()
         };
 
         match e {
             KVError::NotFound(_, _) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
             KVError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KVError::VersionMismatch(..) => result.status = StatusCode::CONFLICT.as_u16() as _,
+            KVError::DeadlineExceeded(_) => {
+                result.status = StatusCode::GATEWAY_TIMEOUT.as_u16() as _
+            }
+            // 和 nginx 一样，客户端断开的请求用 499
+            KVError::Cancelled => result.status = 499,
+            KVError::Unauthenticated(_) => result.status = StatusCode::UNAUTHORIZED.as_u16() as _,
+            KVError::PermissionDenied(..) => result.status = StatusCode::FORBIDDEN.as_u16() as _,
+            KVError::RateLimited(..) => result.status = StatusCode::TOO_MANY_REQUESTS.as_u16() as _,
+            KVError::QuotaExceeded(..) => {
+                result.status = StatusCode::INSUFFICIENT_STORAGE.as_u16() as _
+            }
             _ => {}
         }
 
@@ -156,6 +622,48 @@ impl From<KVError> for CommandResponse {
     }
 }
 
+impl CommandResponse {
+    /// 2xx 的 response 原样返回，否则还原成服务器上的 KVError
+    ///
+    /// 没有 ErrorInfo 的旧版本服务器按状态码转换，无法区分的都是 InternalError。
+    pub fn into_result(self) -> Result<Self, KVError> {
+        if StatusCode::from_u16(self.status as _).is_ok_and(|s| s.is_success()) {
+            return Ok(self);
+        }
+
+        Err(match self.error {
+            Some(info) => info.into(),
+            None if self.status == StatusCode::BAD_REQUEST.as_u16() as u32 => {
+                KVError::InvalidCommand(self.message)
+            }
+            None => {
+                KVError::InternalError(format!("Server returned {}: {}", self.status, self.message))
+            }
+        })
+    }
+}
+
+/// 从 Bytes 转成 Value
+impl From<Bytes> for Value {
+    fn from(b: Bytes) -> Self {
+        Self {
+            value: Some(value::Value::Binary(b)),
+        }
+    }
+}
+
+/// 尝试从 Value 转成 String
+impl TryFrom<Value> for String {
+    type Error = KVError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::String(s)) => Ok(s),
+            _ => Err(KVError::ConvertError(v, "String")),
+        }
+    }
+}
+
 /// 尝试从 Value 转成 Bytes
 impl TryFrom<Value> for Bytes {
     type Error = KVError;
@@ -204,6 +712,70 @@ impl TryFrom<Value> for bool {
     }
 }
 
+/// 尝试从 Value 转成 u64
+impl TryFrom<Value> for u64 {
+    type Error = KVError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Unsigned(u)) => Ok(u),
+            _ => Err(KVError::ConvertError(v, "Unsigned")),
+        }
+    }
+}
+
+/// 尝试从 List 类型的 Value 转成 Vec<Value>
+impl TryFrom<Value> for Vec<Value> {
+    type Error = KVError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::List(l)) => Ok(l.values),
+            _ => Err(KVError::ConvertError(v, "List")),
+        }
+    }
+}
+
+/// 尝试从 Map 类型的 Value 转成 BTreeMap
+impl TryFrom<Value> for BTreeMap<String, Value> {
+    type Error = KVError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Map(m)) => Ok(m
+                .entries
+                .into_iter()
+                .map(|p| (p.key, p.value.unwrap_or_default()))
+                .collect()),
+            _ => Err(KVError::ConvertError(v, "Map")),
+        }
+    }
+}
+
+/// 尝试从 Value 转成 SystemTime
+impl TryFrom<Value> for SystemTime {
+    type Error = KVError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Timestamp(t)) => t.try_into(),
+            _ => Err(KVError::ConvertError(v, "Timestamp")),
+        }
+    }
+}
+
+/// 尝试从 Value 转成 Decimal
+impl TryFrom<Value> for Decimal {
+    type Error = KVError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Decimal(d)) => Ok(d),
+            _ => Err(KVError::ConvertError(v, "Decimal")),
+        }
+    }
+}
+
 /// 尝试从 Value 转成 Vec<u8>
 impl TryFrom<Value> for Vec<u8> {
     type Error = KVError;
@@ -225,3 +797,203 @@ impl TryFrom<&[u8]> for Value {
         Ok(Value::decode(data)?)
     }
 }
+
+/// 从 SystemTime 转成 Timestamp，epoch 之前的时间秒数为负，纳秒数仍然非负
+impl From<SystemTime> for Timestamp {
+    fn from(t: SystemTime) -> Self {
+        let (seconds, nanos) = match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos() as i32),
+            Err(e) => {
+                let d = e.duration();
+                match d.subsec_nanos() {
+                    0 => (-(d.as_secs() as i64), 0),
+                    n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n as i32),
+                }
+            }
+        };
+
+        Self { seconds, nanos }
+    }
+}
+
+/// 尝试从 Timestamp 转成 SystemTime
+impl TryFrom<Timestamp> for SystemTime {
+    type Error = KVError;
+
+    fn try_from(t: Timestamp) -> Result<Self, Self::Error> {
+        let invalid = || KVError::InvalidCommand(format!("Invalid timestamp {:?}", t));
+        if !(0..1_000_000_000).contains(&t.nanos) {
+            return Err(invalid());
+        }
+
+        let time = if t.seconds >= 0 {
+            UNIX_EPOCH.checked_add(Duration::new(t.seconds as u64, t.nanos as u32))
+        } else {
+            UNIX_EPOCH
+                .checked_sub(Duration::from_secs(t.seconds.unsigned_abs()))
+                .and_then(|s| s.checked_add(Duration::from_nanos(t.nanos as u64)))
+        };
+
+        time.ok_or_else(invalid)
+    }
+}
+
+impl Decimal {
+    /// 创建值为 mantissa * 10^-scale 的 Decimal
+    pub fn new(mantissa: i64, scale: u32) -> Self {
+        Self { mantissa, scale }
+    }
+}
+
+/// 按十进制输出，比如 Decimal::new(-1050, 2) 输出 -10.50
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let scale = self.scale as usize;
+
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, int, frac)
+    }
+}
+
+/// 从 "-10.50" 这样的字符串解析，保留小数部分的位数作为 scale
+impl FromStr for Decimal {
+    type Err = KVError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || KVError::InvalidCommand(format!("Invalid decimal {}", s));
+
+        let (negative, body) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (int, frac) = body.split_once('.').unwrap_or((body, ""));
+        if int.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let mantissa: i64 = format!("{}{}{}", if negative { "-" } else { "" }, int, frac)
+            .parse()
+            .map_err(|_| invalid())?;
+
+        Ok(Self::new(mantissa, frac.len() as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rich_values_should_convert() {
+        let list = Value::from(vec![1.into(), "a".into()]);
+        assert_eq!(Vec::<Value>::try_from(list), Ok(vec![1.into(), "a".into()]));
+
+        let map = BTreeMap::from([("b".to_string(), 2.into()), ("a".to_string(), 1.into())]);
+        let v = Value::from(map.clone());
+        assert_eq!(BTreeMap::try_from(v.clone()), Ok(map));
+        assert!(i64::try_from(v).is_err());
+
+        assert_eq!(u64::try_from(Value::from(u64::MAX)), Ok(u64::MAX));
+
+        for t in [
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123),
+            UNIX_EPOCH - Duration::new(10, 250_000_000),
+            UNIX_EPOCH,
+        ] {
+            assert_eq!(SystemTime::try_from(Value::from(t)), Ok(t));
+        }
+        let t = Timestamp::from(UNIX_EPOCH - Duration::from_millis(1500));
+        assert_eq!((t.seconds, t.nanos), (-2, 500_000_000));
+    }
+
+    #[test]
+    fn decimal_should_parse_and_display() {
+        for (s, mantissa, scale) in [
+            ("10.50", 1050, 2),
+            ("-0.05", -5, 2),
+            ("42", 42, 0),
+            ("0.000", 0, 3),
+        ] {
+            let d: Decimal = s.parse().unwrap();
+            assert_eq!(d, Decimal::new(mantissa, scale));
+            assert_eq!(d.to_string(), s);
+        }
+
+        for s in ["", "-", ".5", "1.2.3", "1e5", "99999999999999999999"] {
+            assert!(s.parse::<Decimal>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn errors_should_roundtrip_through_response() {
+        let errors = vec![
+            KVError::NotFound("t1".into(), "k1".into()),
+            KVError::InvalidCommand("bad".into()),
+            KVError::ConvertError(1.into(), "String"),
+            KVError::StorageError("hset".into(), "t1".into(), "k1".into(), "full".into()),
+            KVError::SledDbError("io".into()),
+            KVError::RedbError("io".into()),
+            KVError::EncodeError("short".into()),
+            KVError::DecodeError("eof".into()),
+            KVError::VersionMismatch("t1".into(), "k1".into(), 2, 3),
+            KVError::CompressionError("lz4".into()),
+            KVError::CryptoError("tag".into()),
+            KVError::SerdeError("json".into()),
+            KVError::DumpError("crc".into()),
+            KVError::IoError("reset".into()),
+            KVError::InternalError("oops".into()),
+            KVError::DeadlineExceeded(1_700_000_000_000),
+            KVError::Cancelled,
+            KVError::Unauthenticated("bad token".into()),
+            KVError::PermissionDenied("alice".into(), "t1".into()),
+            KVError::QuotaExceeded("team_a".into(), "100 keys".into()),
+            KVError::RateLimited("write".into(), 250),
+        ];
+
+        for e in errors {
+            let res = CommandResponse::from(e.clone());
+            assert_eq!(
+                res.error.as_ref().unwrap().category(),
+                e.category(),
+                "{:?}",
+                e
+            );
+
+            let res = CommandResponse::decode(res.encode_to_vec().as_slice()).unwrap();
+            assert_eq!(res.into_result(), Err(e));
+        }
+
+        assert!(KVError::VersionMismatch("t".into(), "k".into(), 1, 2).is_retryable());
+        assert!(!KVError::ConvertError(1.into(), "Bool").is_retryable());
+    }
+
+    #[test]
+    fn response_without_error_info_should_convert_by_status() {
+        let ok = CommandResponse::from(Value::from(1));
+        assert_eq!(ok.clone().into_result(), Ok(ok));
+
+        let res = CommandResponse {
+            status: 400,
+            message: "bad".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            res.into_result(),
+            Err(KVError::InvalidCommand("bad".into()))
+        );
+
+        let res = CommandResponse {
+            status: 503,
+            message: "busy".into(),
+            ..Default::default()
+        };
+        assert!(matches!(res.into_result(), Err(KVError::InternalError(_))));
+    }
+}
@@ -0,0 +1,467 @@
+//! 按命令类别的令牌桶限流
+//!
+//! 每个连接有自己的一组令牌桶，认证后的用户的所有连接再共享一组。每个命令消耗所属类别的一个令牌，
+//! batch 中的每个命令分别计算，需要的令牌超过 burst 的 batch 永远无法执行，直接拒绝。
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::{CommandRequest, KVError, command_request::RequestData};
+
+/// 限流时区分的命令类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandClass {
+    /// 读取单个或多个指定的 key
+    Read,
+    /// 写入或删除
+    Write,
+    /// 遍历 table 或者列出 table、namespace
+    Scan,
+}
+
+const CLASSES: [CommandClass; 3] = [CommandClass::Read, CommandClass::Write, CommandClass::Scan];
+
+impl CommandClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandClass::Read => "read",
+            CommandClass::Write => "write",
+            CommandClass::Scan => "scan",
+        }
+    }
+
+    /// 命令所属的类别，Auth、UseNamespace 和 batch 本身不限流
+    pub fn of(data: &RequestData) -> Option<Self> {
+        match data {
+            RequestData::Hget(_)
+            | RequestData::Hmget(_)
+            | RequestData::Hexist(_)
+            | RequestData::Hmexist(_)
+            | RequestData::HgetVersion(_)
+            | RequestData::HgetSchema(_)
+            | RequestData::Hrelease(_) => Some(CommandClass::Read),
+            RequestData::Hset(_)
+            | RequestData::Hmset(_)
+            | RequestData::Hdel(_)
+            | RequestData::Hmdel(_)
+            | RequestData::HsetSchema(_)
+            | RequestData::DropNamespace(_) => Some(CommandClass::Write),
+            RequestData::Hgetall(_)
+            | RequestData::Hrange(_)
+            | RequestData::Hprefix(_)
+            | RequestData::Hscan(_)
+            | RequestData::Htables(_)
+            | RequestData::Hhistory(_)
+            | RequestData::Hsnapshot(_)
+            | RequestData::ListNamespaces(_) => Some(CommandClass::Scan),
+            RequestData::Batch(_) | RequestData::Auth(_) | RequestData::UseNamespace(_) => None,
+        }
+    }
+}
+
+/// 令牌桶的速率
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    /// 每秒补充的令牌数
+    pub per_second: f64,
+    /// 桶的容量，也就是允许的突发请求数
+    pub burst: f64,
+}
+
+impl Rate {
+    pub fn new(per_second: f64, burst: f64) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+/// 每个命令类别的速率，None 表示不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClassLimits {
+    pub read: Option<Rate>,
+    pub write: Option<Rate>,
+    pub scan: Option<Rate>,
+}
+
+impl ClassLimits {
+    fn get(&self, class: CommandClass) -> Option<Rate> {
+        match class {
+            CommandClass::Read => self.read,
+            CommandClass::Write => self.write,
+            CommandClass::Scan => self.scan,
+        }
+    }
+}
+
+/// 限流配置
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimitConfig {
+    /// 每个连接的速率
+    pub connection: ClassLimits,
+    /// 每个认证用户的所有连接共享的速率
+    pub identity: ClassLimits,
+}
+
+/// 一个客户端的限流统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThrottleStats {
+    /// 通过的请求数
+    pub allowed: u64,
+    /// 返回 429 的请求数
+    pub throttled: u64,
+    /// 按类别统计的返回 429 的请求数
+    pub read: u64,
+    pub write: u64,
+    pub scan: u64,
+}
+
+impl ThrottleStats {
+    fn record_throttled(&mut self, class: CommandClass) {
+        self.throttled += 1;
+        match class {
+            CommandClass::Read => self.read += 1,
+            CommandClass::Write => self.write += 1,
+            CommandClass::Scan => self.scan += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst);
+        self.updated = now;
+    }
+
+    // 还需要等待多少毫秒才有足够的令牌，0 表示现在就够
+    fn wait_ms(&self, rate: Rate, cost: f64) -> u64 {
+        if self.tokens >= cost {
+            return 0;
+        }
+
+        ((cost - self.tokens) / rate.per_second * 1000.0)
+            .ceil()
+            .max(1.0) as u64
+    }
+}
+
+/// 一个连接或者一个用户的令牌桶，第一次使用时是满的
+#[derive(Debug, Default)]
+struct Buckets(HashMap<CommandClass, Bucket>);
+
+impl Buckets {
+    // 检查令牌是否足够，返回第一个不够的类别和对应的错误
+    fn check(
+        &mut self,
+        limits: &ClassLimits,
+        costs: &[(CommandClass, f64)],
+        now: Instant,
+    ) -> Result<(), (CommandClass, KVError)> {
+        for &(class, cost) in costs {
+            let Some(rate) = limits.get(class) else {
+                continue;
+            };
+            if cost > rate.burst {
+                let err = KVError::InvalidCommand(format!(
+                    "Command needs {} {} tokens, more than the burst of {}",
+                    cost,
+                    class.name(),
+                    rate.burst
+                ));
+                return Err((class, err));
+            }
+
+            let bucket = self.0.entry(class).or_insert(Bucket {
+                tokens: rate.burst,
+                updated: now,
+            });
+            bucket.refill(rate, now);
+            match bucket.wait_ms(rate, cost) {
+                0 => {}
+                ms => return Err((class, KVError::RateLimited(class.name().into(), ms))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn consume(&mut self, limits: &ClassLimits, costs: &[(CommandClass, f64)]) {
+        for &(class, cost) in costs {
+            if limits.get(class).is_some()
+                && let Some(bucket) = self.0.get_mut(&class)
+            {
+                bucket.tokens -= cost;
+            }
+        }
+    }
+}
+
+/// 一个连接的令牌桶，id 在进程内唯一，用来按连接统计
+#[derive(Debug)]
+pub(crate) struct Connection {
+    id: u64,
+    buckets: Buckets,
+}
+
+impl Connection {
+    pub(crate) fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            buckets: Buckets::default(),
+        }
+    }
+}
+
+/// Service 持有的限流状态：配置、每个用户的令牌桶和限流统计
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    identities: Mutex<HashMap<String, Buckets>>,
+    /// 以用户名为 key，没有认证的连接统计在空字符串下
+    stats: Mutex<HashMap<String, ThrottleStats>>,
+    /// 以连接 id 为 key，只保留还没有断开的连接
+    connections: Mutex<HashMap<u64, ThrottleStats>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// 每个用户通过和被拒绝的请求数
+    pub fn stats(&self) -> HashMap<String, ThrottleStats> {
+        lock(&self.stats).clone()
+    }
+
+    /// 每个连接通过和被拒绝的请求数，可以区分同一个用户或者没有认证的不同连接
+    pub fn connection_stats(&self) -> HashMap<u64, ThrottleStats> {
+        lock(&self.connections).clone()
+    }
+
+    /// 连接断开时删除它的统计
+    pub(crate) fn disconnect(&self, connection: &Connection) {
+        lock(&self.connections).remove(&connection.id);
+    }
+
+    /// 同时从连接和用户的令牌桶中取出命令需要的令牌，任何一个不够时都不消耗令牌，返回 RateLimited；
+    /// 需要的令牌超过 burst 时永远无法执行，返回 InvalidCommand
+    pub(crate) fn acquire(
+        &self,
+        connection: &mut Connection,
+        identity: Option<&str>,
+        cmd: &CommandRequest,
+    ) -> Result<(), KVError> {
+        self.acquire_at(connection, identity, cmd, Instant::now())
+    }
+
+    fn acquire_at(
+        &self,
+        connection: &mut Connection,
+        identity: Option<&str>,
+        cmd: &CommandRequest,
+        now: Instant,
+    ) -> Result<(), KVError> {
+        let costs = costs(cmd);
+        if costs.is_empty() {
+            return Ok(());
+        }
+
+        let mut identities = lock(&self.identities);
+        let mut shared = identity.map(|name| identities.entry(name.into()).or_default());
+        let result = connection
+            .buckets
+            .check(&self.config.connection, &costs, now)
+            .and_then(|_| match shared.as_mut() {
+                Some(buckets) => buckets.check(&self.config.identity, &costs, now),
+                None => Ok(()),
+            });
+
+        let (mut stats, mut connections) = (lock(&self.stats), lock(&self.connections));
+        let counters = [
+            stats
+                .entry(identity.unwrap_or_default().into())
+                .or_default(),
+            connections.entry(connection.id).or_default(),
+        ];
+        match result {
+            Ok(()) => {
+                connection.buckets.consume(&self.config.connection, &costs);
+                if let Some(buckets) = shared {
+                    buckets.consume(&self.config.identity, &costs);
+                }
+                counters.into_iter().for_each(|s| s.allowed += 1);
+
+                Ok(())
+            }
+            Err((class, e)) => {
+                counters.into_iter().for_each(|s| s.record_throttled(class));
+                Err(e)
+            }
+        }
+    }
+}
+
+// 命令（batch 中的每个命令）在每个类别中需要的令牌数
+fn costs(cmd: &CommandRequest) -> Vec<(CommandClass, f64)> {
+    let mut counts = [0u32; CLASSES.len()];
+    let mut add = |cmd: &CommandRequest| {
+        if let Some(class) = cmd.request_data.as_ref().and_then(CommandClass::of) {
+            counts[class as usize] += 1;
+        }
+    };
+
+    match &cmd.request_data {
+        Some(RequestData::Batch(batch)) => batch.requests.iter().for_each(&mut add),
+        _ => add(cmd),
+    }
+
+    CLASSES
+        .into_iter()
+        .zip(counts)
+        .filter(|(_, n)| *n > 0)
+        .map(|(class, n)| (class, n as f64))
+        .collect()
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            connection: ClassLimits {
+                write: Some(Rate::new(10.0, 2.0)),
+                ..Default::default()
+            },
+            identity: ClassLimits {
+                read: Some(Rate::new(1.0, 3.0)),
+                ..Default::default()
+            },
+        })
+    }
+
+    #[test]
+    fn connection_bucket_should_refill() {
+        let limiter = limiter();
+        let mut conn = Connection::new();
+        let set = CommandRequest::new_hset("t1", "k1", 1.into());
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            assert!(limiter.acquire_at(&mut conn, None, &set, now).is_ok());
+        }
+        assert_eq!(
+            limiter.acquire_at(&mut conn, None, &set, now),
+            Err(KVError::RateLimited("write".into(), 100))
+        );
+        // 读命令和其它连接不受影响
+        let get = CommandRequest::new_hget("t1", "k1");
+        assert!(limiter.acquire_at(&mut conn, None, &get, now).is_ok());
+        assert!(
+            limiter
+                .acquire_at(&mut Connection::new(), None, &set, now)
+                .is_ok()
+        );
+
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.acquire_at(&mut conn, None, &set, later).is_ok());
+
+        let stats = limiter.stats()[""];
+        assert_eq!((stats.allowed, stats.throttled, stats.write), (5, 1, 1));
+
+        // 没有认证的连接按连接分开统计，断开后删除
+        let stats = limiter.connection_stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[&conn.id].allowed, stats[&conn.id].throttled), (4, 1));
+        limiter.disconnect(&conn);
+        assert!(!limiter.connection_stats().contains_key(&conn.id));
+    }
+
+    #[test]
+    fn identity_bucket_should_be_shared() {
+        let limiter = limiter();
+        let (mut c1, mut c2) = (Connection::new(), Connection::new());
+        let get = CommandRequest::new_hget("t1", "k1");
+        let now = Instant::now();
+
+        assert!(
+            limiter
+                .acquire_at(&mut c1, Some("alice"), &get, now)
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .acquire_at(&mut c2, Some("alice"), &get, now)
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .acquire_at(&mut c1, Some("alice"), &get, now)
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .acquire_at(&mut c2, Some("alice"), &get, now)
+                .is_err()
+        );
+        assert!(limiter.acquire_at(&mut c2, Some("bob"), &get, now).is_ok());
+
+        assert_eq!(limiter.stats()["alice"].read, 1);
+        assert_eq!(limiter.stats()["bob"].throttled, 0);
+    }
+
+    #[test]
+    fn batch_should_consume_all_or_nothing() {
+        let limiter = limiter();
+        let mut conn = Connection::new();
+        let now = Instant::now();
+        let batch = |n| {
+            let cmds = (0..n)
+                .map(|i| CommandRequest::new_hset("t1", format!("k{}", i), i.into()))
+                .collect();
+            CommandRequest::new_batch(cmds, false)
+        };
+
+        assert_eq!(costs(&batch(3)), vec![(CommandClass::Write, 3.0)]);
+        // 需要的令牌超过 burst 的 batch 直接拒绝，不消耗令牌
+        assert!(matches!(
+            limiter.acquire_at(&mut conn, None, &batch(3), now),
+            Err(KVError::InvalidCommand(_))
+        ));
+        assert_eq!(limiter.stats()[""].write, 1);
+        assert!(limiter.acquire_at(&mut conn, None, &batch(2), now).is_ok());
+        assert!(limiter.acquire_at(&mut conn, None, &batch(1), now).is_err());
+        assert!(costs(&CommandRequest::new_auth_token("t")).is_empty());
+        assert_eq!(
+            CommandClass::of(
+                CommandRequest::new_hsnapshot()
+                    .request_data
+                    .as_ref()
+                    .unwrap()
+            ),
+            Some(CommandClass::Scan)
+        );
+    }
+}
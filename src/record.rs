@@ -0,0 +1,255 @@
+//! 把 Rust struct 映射成 table 中的一组 kv pair，一般通过 `#[derive(KvRecord)]` 实现
+//!
+//! 一条记录的每个字段保存为 `TABLE` 中的一个 key：`{主键}:{字段名}`，整条记录用一个 HMSET 写入，
+//! 用 HMGET 读取，用 HMDEL 删除。
+
+use std::fmt::Display;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    CommandRequest, CommandResponse, KVError, KvPair, ProstClientStream, Service, Storage, Value,
+};
+
+pub use kv_derive::KvRecord;
+
+/// 可以保存到 kv 中的记录
+pub trait KvRecord: Sized {
+    /// 主键的类型
+    type Key: Display;
+
+    /// 记录保存在哪个 table
+    const TABLE: &'static str;
+    /// 主键字段的名字
+    const KEY_FIELD: &'static str;
+    /// 所有字段的名字，和 to_values/from_values 中的顺序一致
+    const FIELDS: &'static [&'static str];
+
+    fn key(&self) -> &Self::Key;
+
+    /// 按 FIELDS 的顺序把字段转成 Value
+    fn to_values(&self) -> Vec<Value>;
+
+    /// 按 FIELDS 的顺序从 Value 还原记录
+    fn from_values(values: Vec<Value>) -> Result<Self, KVError>;
+
+    /// 字段在 table 中的 key
+    fn field_keys(key: &Self::Key) -> Vec<String> {
+        Self::FIELDS
+            .iter()
+            .map(|f| format!("{}:{}", key, f))
+            .collect()
+    }
+
+    fn save_request(&self) -> CommandRequest {
+        let pairs = Self::field_keys(self.key())
+            .into_iter()
+            .zip(self.to_values())
+            .map(|(k, v)| KvPair::new(k, v))
+            .collect();
+        CommandRequest::new_hmset(Self::TABLE, pairs)
+    }
+
+    fn load_request(key: &Self::Key) -> CommandRequest {
+        CommandRequest::new_hmget(Self::TABLE, Self::field_keys(key))
+    }
+
+    fn delete_request(key: &Self::Key) -> CommandRequest {
+        CommandRequest::new_hmdel(Self::TABLE, Self::field_keys(key))
+    }
+
+    /// 从 load_request 的结果还原记录，主键字段不存在说明没有这条记录
+    fn from_response(res: CommandResponse) -> Result<Option<Self>, KVError> {
+        let values = res.into_result()?.values;
+        if values
+            .get(key_index::<Self>())
+            .is_none_or(|v| v.value.is_none())
+        {
+            return Ok(None);
+        }
+
+        Self::from_values(values).map(Some)
+    }
+}
+
+impl<Store: Storage> Service<Store> {
+    /// 保存一条记录，已有的同名字段会被覆盖
+    pub fn save<R: KvRecord>(&self, record: &R) -> Result<(), KVError> {
+        self.execute(record.save_request())
+            .into_result()
+            .map(|_| ())
+    }
+
+    /// 读取一条记录，不存在时返回 None
+    pub fn load<R: KvRecord>(&self, key: &R::Key) -> Result<Option<R>, KVError> {
+        R::from_response(self.execute(R::load_request(key)))
+    }
+
+    /// 删除一条记录，返回这条记录之前是否存在
+    pub fn delete<R: KvRecord>(&self, key: &R::Key) -> Result<bool, KVError> {
+        deleted::<R>(self.execute(R::delete_request(key)))
+    }
+}
+
+impl<S> ProstClientStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    /// 保存一条记录，已有的同名字段会被覆盖
+    pub async fn save<R: KvRecord>(&mut self, record: &R) -> Result<(), KVError> {
+        self.execute(record.save_request())
+            .await?
+            .into_result()
+            .map(|_| ())
+    }
+
+    /// 读取一条记录，不存在时返回 None
+    pub async fn load<R: KvRecord>(&mut self, key: &R::Key) -> Result<Option<R>, KVError> {
+        R::from_response(self.execute(R::load_request(key)).await?)
+    }
+
+    /// 删除一条记录，返回这条记录之前是否存在
+    pub async fn delete<R: KvRecord>(&mut self, key: &R::Key) -> Result<bool, KVError> {
+        deleted::<R>(self.execute(R::delete_request(key)).await?)
+    }
+}
+
+fn key_index<R: KvRecord>() -> usize {
+    R::FIELDS
+        .iter()
+        .position(|f| *f == R::KEY_FIELD)
+        .unwrap_or_default()
+}
+
+fn deleted<R: KvRecord>(res: CommandResponse) -> Result<bool, KVError> {
+    let values = res.into_result()?.values;
+    Ok(values
+        .get(key_index::<R>())
+        .is_some_and(|v| v.value.is_some()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::{MemTable, ProstServerStream, ServiceInner, TableSchema, ValueType};
+
+    #[derive(Debug, Clone, PartialEq, KvRecord)]
+    #[kv(table = "users")]
+    struct User {
+        #[kv(key)]
+        id: u64,
+        name: String,
+        score: f64,
+        email: Option<String>,
+        created_at: SystemTime,
+    }
+
+    #[derive(Debug, PartialEq, KvRecord)]
+    struct AuditLog {
+        #[kv(key)]
+        id: String,
+        tags: Vec<Value>,
+    }
+
+    fn user(id: u64) -> User {
+        User {
+            id,
+            name: format!("user{}", id),
+            score: 9.5,
+            email: None,
+            created_at: SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+        }
+    }
+
+    #[test]
+    fn derive_should_generate_table_and_fields() {
+        assert_eq!(User::TABLE, "users");
+        assert_eq!(User::KEY_FIELD, "id");
+        assert_eq!(
+            User::FIELDS,
+            &["id", "name", "score", "email", "created_at"]
+        );
+        assert_eq!(AuditLog::TABLE, "audit_log");
+        assert_eq!(User::field_keys(&1)[1], "1:name");
+
+        let u = user(1);
+        assert_eq!(User::from_values(u.to_values()), Ok(u));
+    }
+
+    #[test]
+    fn service_save_load_delete_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+
+        let mut u = user(1);
+        service.save(&u).unwrap();
+        service.save(&user(2)).unwrap();
+        assert_eq!(service.load::<User>(&1), Ok(Some(u.clone())));
+
+        u.email = Some("u1@example.com".into());
+        service.save(&u).unwrap();
+        assert_eq!(service.load::<User>(&1), Ok(Some(u)));
+
+        assert_eq!(service.delete::<User>(&1), Ok(true));
+        assert_eq!(service.delete::<User>(&1), Ok(false));
+        assert_eq!(service.load::<User>(&1), Ok(None));
+        assert_eq!(service.load::<User>(&2), Ok(Some(user(2))));
+
+        let log = AuditLog {
+            id: "a1".into(),
+            tags: vec!["x".into(), 1.into()],
+        };
+        service.save(&log).unwrap();
+        assert_eq!(service.load::<AuditLog>(&"a1".into()), Ok(Some(log)));
+    }
+
+    #[test]
+    fn load_should_fail_on_mismatched_values() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        service.execute(CommandRequest::new_hset("users", "1:id", 1.into()));
+        service.execute(CommandRequest::new_hset("users", "1:name", 2.into()));
+
+        assert!(matches!(
+            service.load::<User>(&1),
+            Err(KVError::ConvertError(..))
+        ));
+    }
+
+    #[test]
+    fn save_should_respect_table_schema() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let schema = TableSchema {
+            value_type: ValueType::String as i32,
+            ..Default::default()
+        };
+        service.execute(CommandRequest::new_hset_schema("users", Some(schema)));
+
+        assert!(matches!(
+            service.save(&user(1)),
+            Err(KVError::InvalidCommand(_))
+        ));
+        assert_eq!(service.load::<User>(&1), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn client_save_load_delete_should_work() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            ProstServerStream::new(stream, service).process().await.ok();
+        });
+
+        let mut client = ProstClientStream::new(TcpStream::connect(addr).await?);
+        client.save(&user(7)).await?;
+        assert_eq!(client.load::<User>(&7).await?, Some(user(7)));
+        assert!(client.delete::<User>(&7).await?);
+        assert_eq!(client.load::<User>(&7).await?, None);
+
+        Ok(())
+    }
+}
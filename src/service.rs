@@ -1,11 +1,25 @@
 mod command_service;
+mod deadline;
+mod namespace;
+mod schema;
+mod snapshots;
 
 use crate::{
-    CommandRequest, CommandResponse, KVError, MemTable, Storage, command_request::RequestData,
+    Acl, CommandBatch, CommandBatchResponse, CommandRequest, CommandResponse, Evicted, KVError,
+    MemTable, RateLimitConfig, RateLimiter, Storage, command_request::RequestData,
 };
-use std::sync::Arc;
+use http::StatusCode;
+use namespace::Namespaces;
+use snapshots::Snapshots;
+use std::sync::{Arc, atomic::AtomicBool};
 use tracing::debug;
 
+pub(crate) use deadline::Deadline;
+pub use namespace::{NAMESPACE_SEPARATOR, NamespaceConfig, Quota};
+pub(crate) use namespace::{qualify, unqualify, validate_namespace};
+pub use snapshots::SnapshotCommand;
+pub(crate) use snapshots::snapshot_not_found;
+
 /// 对 Command 的处理的抽象
 pub trait CommandService {
     /// 处理 Command，返回 Response
@@ -50,13 +64,110 @@ impl<Store> Clone for Service<Store> {
 }
 
 impl<Store: Storage> Service<Store> {
+    pub fn acl(&self) -> Option<&Acl> {
+        self.inner.acl.as_ref()
+    }
+
+    /// 限流状态，可以通过它查看每个客户端被限流的统计
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.inner.rate_limiter.as_ref()
+    }
+
     pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+        self.execute_until(cmd, None)
+    }
+
+    /// 和 execute 一样，cancelled 被设置后 batch 中剩下的命令和正在进行的扫描不再执行
+    pub fn execute_cancellable(
+        &self,
+        cmd: CommandRequest,
+        cancelled: &AtomicBool,
+    ) -> CommandResponse {
+        self.execute_until(cmd, Some(cancelled))
+    }
+
+    fn execute_until(
+        &self,
+        cmd: CommandRequest,
+        cancelled: Option<&AtomicBool>,
+    ) -> CommandResponse {
         debug!("Got request: {:?}", cmd);
         self.inner.on_received.notify(&cmd);
 
-        let mut res = dispatch(cmd, &self.inner.store);
+        // 已经过期的命令直接返回，整个 batch 只触发一次 hook
+        let deadline = Deadline::new(cmd.deadline, cancelled);
+        let res = match (deadline.check(), cmd.request_data) {
+            (Err(e), _) => e.into(),
+            (Ok(()), Some(RequestData::Batch(batch))) => {
+                batch.run(deadline, |cmd| self.execute_one(cmd, deadline))
+            }
+            (Ok(()), request_data) => self.execute_one(
+                CommandRequest {
+                    request_data,
+                    ..cmd
+                },
+                deadline,
+            ),
+        };
+        self.finish(res)
+    }
+
+    /// 执行命令，把结果分成一个或多个 response 依次交给 f，f 返回 false 时停止
+    ///
+    /// 只有 chunk_size 不为 0 的 Hgetall 会从 get_iter 中分块读取，除最后一个外 has_more 都为 true；
+    /// 其它命令和 execute 一样只有一个 response。
+    pub fn execute_chunked(&self, cmd: CommandRequest, f: impl FnMut(CommandResponse) -> bool) {
+        self.execute_chunked_until(cmd, None, f)
+    }
+
+    /// 和 execute_chunked 一样，cancelled 被设置后不再读取下一个 chunk
+    pub fn execute_chunked_cancellable(
+        &self,
+        cmd: CommandRequest,
+        cancelled: &AtomicBool,
+        f: impl FnMut(CommandResponse) -> bool,
+    ) {
+        self.execute_chunked_until(cmd, Some(cancelled), f)
+    }
+
+    fn execute_chunked_until(
+        &self,
+        cmd: CommandRequest,
+        cancelled: Option<&AtomicBool>,
+        mut f: impl FnMut(CommandResponse) -> bool,
+    ) {
+        if !is_chunked(&cmd) {
+            f(self.execute_until(cmd, cancelled));
+            return;
+        }
+
+        debug!("Got request: {:?}", cmd);
+        self.inner.on_received.notify(&cmd);
+
+        // 和其它命令一样经过快照和 namespace，每个 chunk 都触发 hook
+        let deadline = Deadline::new(cmd.deadline, cancelled);
+        match self.inner.snapshots.execute(cmd, &self.inner.store) {
+            Ok(res) => {
+                f(self.finish(res));
+            }
+            Err(cmd) => {
+                self.inner
+                    .namespaces
+                    .execute_chunked(cmd, &self.inner.store, deadline, |res| f(self.finish(res)))
+            }
+        }
+    }
+
+    // 执行完命令后触发的 hook
+    fn finish(&self, mut res: CommandResponse) -> CommandResponse {
         debug!("Executed response: {:?}", res);
         self.inner.on_executed.notify(&res);
+
+        for evicted in self.inner.store.drain_evicted() {
+            debug!("Evicted: {:?}", evicted);
+            self.inner.on_evicted.notify(&evicted);
+        }
+
         self.inner.on_before_send.notify(&mut res);
 
         if !self.inner.on_before_send.is_empty() {
@@ -65,17 +176,131 @@ impl<Store: Storage> Service<Store> {
 
         res
     }
+
+    fn execute_one(&self, cmd: CommandRequest, deadline: Deadline) -> CommandResponse {
+        match self.inner.snapshots.execute(cmd, &self.inner.store) {
+            Ok(res) => res,
+            Err(cmd) => self
+                .inner
+                .namespaces
+                .execute(cmd, &self.inner.store, deadline),
+        }
+    }
+}
+
+impl CommandBatch {
+    /// 按顺序执行 batch 中的命令；batch 超时或被取消后，剩下的命令不再执行
+    pub(crate) fn run(
+        self,
+        deadline: Deadline,
+        mut f: impl FnMut(CommandRequest) -> CommandResponse,
+    ) -> CommandResponse {
+        let mut responses = Vec::with_capacity(self.requests.len());
+
+        for cmd in self.requests {
+            if let Err(e) = deadline.check() {
+                responses.push(e.into());
+                break;
+            }
+
+            let res = match (deadline.min(cmd.deadline).check(), &cmd.request_data) {
+                (Err(e), _) => e.into(),
+                (Ok(()), Some(RequestData::Batch(_))) => {
+                    KVError::InvalidCommand("CommandBatch cannot be nested".into()).into()
+                }
+                _ => f(cmd),
+            };
+
+            let failed = !StatusCode::from_u16(res.status as _).is_ok_and(|s| s.is_success());
+            responses.push(res);
+            if failed && self.stop_on_error {
+                break;
+            }
+        }
+
+        CommandBatchResponse { responses }.into()
+    }
 }
 
-// 从 Request 中得到 Response，目前处理 HSET，HGET，HGETALL，HDEL
+// 从 Request 中得到 Response
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
+    dispatch_until(cmd, store, Deadline::default())
+}
+
+// 和 dispatch_until 一样，chunk_size 不为 0 的 Hgetall 分块交给 f，其它命令只有一个 response
+pub(crate) fn dispatch_chunked(
+    cmd: CommandRequest,
+    store: &impl Storage,
+    deadline: Deadline,
+    mut f: impl FnMut(CommandResponse) -> bool,
+) {
+    let deadline = deadline.min(cmd.deadline);
+    match cmd.request_data {
+        Some(RequestData::Hgetall(param)) if param.chunk_size > 0 => {
+            param.scan_chunked(store, deadline, f)
+        }
+        request_data => {
+            f(dispatch_until(
+                CommandRequest {
+                    request_data,
+                    ..cmd
+                },
+                store,
+                deadline,
+            ));
+        }
+    }
+}
+
+// 是否是需要分块返回的命令
+fn is_chunked(cmd: &CommandRequest) -> bool {
+    matches!(&cmd.request_data, Some(RequestData::Hgetall(v)) if v.chunk_size > 0)
+}
+
+// 和 dispatch 一样，长时间的扫描过程中也会检查 deadline（和命令自己的截止时间）
+pub(crate) fn dispatch_until(
+    cmd: CommandRequest,
+    store: &impl Storage,
+    deadline: Deadline,
+) -> CommandResponse {
+    let deadline = deadline.min(cmd.deadline);
+    if let Err(e) = deadline.check() {
+        return e.into();
+    }
+
     match cmd.request_data {
+        // 快照由 Service 管理，直接调用 dispatch 时没有快照
+        Some(data) if data.snapshot() != 0 => snapshot_not_found(data.snapshot()).into(),
+        Some(RequestData::Hsnapshot(_) | RequestData::Hrelease(_)) => {
+            KVError::InvalidCommand("Snapshots are only available through Service".into()).into()
+        }
         Some(RequestData::Hset(param)) => param.execute(store),
         Some(RequestData::Hget(param)) => param.execute(store),
-        Some(RequestData::Hgetall(param)) => param.execute(store),
+        Some(RequestData::Hgetall(param)) => param.scan(store, deadline),
+        Some(RequestData::Hmget(param)) => param.execute(store),
+        Some(RequestData::Hmset(param)) => param.execute(store),
         Some(RequestData::Hdel(param)) => param.execute(store),
+        Some(RequestData::Hmdel(param)) => param.execute(store),
+        Some(RequestData::Hexist(param)) => param.execute(store),
+        Some(RequestData::Hmexist(param)) => param.execute(store),
+        Some(RequestData::Hrange(param)) => param.scan(store, deadline),
+        Some(RequestData::Hprefix(param)) => param.scan(store, deadline),
+        Some(RequestData::Htables(param)) => param.execute(store),
+        Some(RequestData::Hscan(param)) => param.scan(store, deadline),
+        Some(RequestData::HgetVersion(param)) => param.execute(store),
+        Some(RequestData::Hhistory(param)) => param.execute(store),
+        Some(RequestData::HsetSchema(param)) => param.execute(store),
+        Some(RequestData::HgetSchema(param)) => param.execute(store),
+        Some(RequestData::Batch(batch)) => {
+            batch.run(deadline, |cmd| dispatch_until(cmd, store, deadline))
+        }
+        Some(RequestData::ListNamespaces(_) | RequestData::DropNamespace(_)) => {
+            KVError::InvalidCommand("Namespaces are only available through Service".into()).into()
+        }
+        Some(RequestData::Auth(_) | RequestData::UseNamespace(_)) => {
+            KVError::InvalidCommand("Command is only available through connections".into()).into()
+        }
         None => KVError::InvalidCommand("Request has no data".into()).into(),
-        _ => KVError::InternalError("Not implemented".into()).into(),
     }
 }
 
@@ -90,6 +315,16 @@ pub struct ServiceInner<Store> {
     on_before_send: Vec<fn(&mut CommandResponse)>,
     /// 在服务器发送完 CommandResponse 后触发
     on_after_send: Vec<fn()>,
+    /// 当存储因内存限制淘汰数据时触发
+    on_evicted: Vec<fn(&Evicted)>,
+    /// 客户端通过 Hsnapshot 创建的快照
+    snapshots: Snapshots,
+    /// 设置后，网络连接需要先认证，命令按 ACL 授权
+    acl: Option<Acl>,
+    /// 每个 namespace 的配额和用量
+    namespaces: Namespaces,
+    /// 设置后，网络连接的命令按连接和用户限流
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
@@ -108,6 +343,11 @@ impl<Store: Storage> ServiceInner<Store> {
             on_executed: Vec::new(),
             on_before_send: Vec::new(),
             on_after_send: Vec::new(),
+            on_evicted: Vec::new(),
+            snapshots: Snapshots::default(),
+            acl: None,
+            namespaces: Namespaces::default(),
+            rate_limiter: None,
         }
     }
 
@@ -130,6 +370,29 @@ impl<Store: Storage> ServiceInner<Store> {
         self.on_after_send.push(f);
         self
     }
+
+    pub fn fn_evicted(mut self, f: fn(&Evicted)) -> Self {
+        self.on_evicted.push(f);
+        self
+    }
+
+    /// 设置 namespace 的配额，超出配额的写入返回 507
+    pub fn namespaces(mut self, config: NamespaceConfig) -> Self {
+        self.namespaces = Namespaces::new(config);
+        self
+    }
+
+    /// 按连接和用户限流，超出速率的命令返回 429，只对 ProstServerStream 生效
+    pub fn rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(config));
+        self
+    }
+
+    /// 要求网络连接认证，只对 ProstServerStream 生效，直接调用 execute 不做检查
+    pub fn acl(mut self, acl: Acl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
 }
 
 #[cfg(test)]
@@ -216,8 +479,264 @@ mod tests {
 
         let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
 
-        assert_eq!(res.status, StatusCode::CREATED.as_u16() as _);
+        assert_eq!(res.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(res.message, "");
         assert_eq!(res.values, vec![Value::default()]);
     }
+
+    #[test]
+    fn evicted_event_should_be_notified() {
+        use crate::{EvictionConfig, EvictionPolicy};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static EVICTED: AtomicUsize = AtomicUsize::new(0);
+
+        fn on_evicted(e: &Evicted) {
+            assert_eq!(e.table, "t1");
+            EVICTED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        // 每个 entry 占 8 字节，预算只够放下一个
+        let store = MemTable::with_eviction(EvictionConfig::new(10, EvictionPolicy::Lru));
+        let service: Service = ServiceInner::new(store).fn_evicted(on_evicted).into();
+
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_eq!(EVICTED.load(Ordering::SeqCst), 0);
+
+        service.execute(CommandRequest::new_hset("t1", "k2", "v2".into()));
+        assert_eq!(EVICTED.load(Ordering::SeqCst), 1);
+
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn batch_should_run_hooks_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_received(|_| {
+                RECEIVED.fetch_add(1, Ordering::SeqCst);
+            })
+            .into();
+
+        let cmds = (0..100)
+            .map(|i| CommandRequest::new_hset("t1", format!("k{}", i), i.into()))
+            .collect();
+        let res = service.execute(CommandRequest::new_batch(cmds, false));
+
+        assert_eq!(res.status, 200);
+        assert_eq!(res.batch.unwrap().responses.len(), 100);
+        assert_eq!(RECEIVED.load(Ordering::SeqCst), 1);
+
+        let res = service.execute(CommandRequest::new_hget("t1", "k99"));
+        assert_res_ok(res, &[99.into()], &[]);
+    }
+
+    #[test]
+    fn batch_should_stop_on_first_error() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let cmds = |stop_on_error| {
+            CommandRequest::new_batch(
+                vec![
+                    CommandRequest::new_hset("t1", "k1", "v1".into()),
+                    CommandRequest::new_batch(vec![], false),
+                    CommandRequest::new_hset("t1", "k2", "v2".into()),
+                ],
+                stop_on_error,
+            )
+        };
+
+        let responses = service.execute(cmds(true)).batch.unwrap().responses;
+        assert_eq!(responses.len(), 2);
+        assert_res_error(responses[1].clone(), 400, "nested");
+        assert_res_error(
+            service.execute(CommandRequest::new_hget("t1", "k2")),
+            404,
+            "Not found",
+        );
+
+        let responses = service.execute(cmds(false)).batch.unwrap().responses;
+        assert_eq!(responses.len(), 3);
+        assert_res_ok(responses[2].clone(), &[Value::default()], &[]);
+    }
+
+    #[test]
+    fn batch_should_read_from_snapshot() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+
+        let res = service.execute(CommandRequest::new_hsnapshot());
+        let id = i64::try_from(res.values[0].clone()).unwrap() as u64;
+
+        let res = service.execute(CommandRequest::new_batch(
+            vec![
+                CommandRequest::new_hset("t1", "k1", "v2".into()),
+                CommandRequest::new_hget_at("t1", "k1", id),
+                CommandRequest::new_hrelease(id),
+            ],
+            true,
+        ));
+        let responses = res.batch.unwrap().responses;
+        assert_res_ok(responses[1].clone(), &["v1".into()], &[]);
+        assert_res_ok(responses[2].clone(), &[true.into()], &[]);
+    }
+
+    #[test]
+    fn dispatch_should_run_batch_without_service() {
+        let store = MemTable::default();
+        let res = dispatch(
+            CommandRequest::new_batch(
+                vec![
+                    CommandRequest::new_hset("t1", "k1", "v1".into()),
+                    CommandRequest::new_hget("t1", "k1"),
+                ],
+                true,
+            ),
+            &store,
+        );
+
+        let responses = res.batch.unwrap().responses;
+        assert_res_ok(responses[1].clone(), &["v1".into()], &[]);
+    }
+
+    #[test]
+    fn execute_chunked_should_split_hgetall() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let cmds = (0..10)
+            .map(|i| CommandRequest::new_hset("t1", format!("k{}", i), i.into()))
+            .collect();
+        service.execute(CommandRequest::new_batch(cmds, true));
+
+        let mut chunks = Vec::new();
+        service.execute_chunked(CommandRequest::new_hgetall_chunked("t1", 4), |res| {
+            chunks.push(res);
+            true
+        });
+        let sizes: Vec<_> = chunks.iter().map(|r| (r.pairs.len(), r.has_more)).collect();
+        assert_eq!(sizes, vec![(4, true), (4, true), (2, false)]);
+
+        // 正好分完时，最后一个 response 是空的
+        let mut chunks = Vec::new();
+        service.execute_chunked(CommandRequest::new_hgetall_chunked("t1", 5), |res| {
+            chunks.push(res);
+            true
+        });
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[2].pairs.is_empty() && !chunks[2].has_more);
+
+        // f 返回 false 时停止
+        let mut n = 0;
+        service.execute_chunked(CommandRequest::new_hgetall_chunked("t1", 1), |_| {
+            n += 1;
+            false
+        });
+        assert_eq!(n, 1);
+
+        // 其它命令只有一个 response
+        let mut chunks = Vec::new();
+        service.execute_chunked(CommandRequest::new_hgetall("t1"), |res| {
+            chunks.push(res);
+            true
+        });
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].pairs.len(), 10);
+    }
+
+    #[test]
+    fn expired_request_should_not_be_executed() {
+        use std::time::{Duration, SystemTime};
+
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let expired = SystemTime::now() - Duration::from_secs(1);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into()).with_deadline(expired);
+        assert_res_error(service.execute(cmd), 504, "Deadline exceeded");
+        assert_res_error(
+            service.execute(CommandRequest::new_hget("t1", "k1")),
+            404,
+            "Not found",
+        );
+
+        let cmd = CommandRequest::new_hget("t1", "k1").with_timeout(Duration::from_secs(60));
+        assert_res_error(service.execute(cmd), 404, "Not found");
+
+        // batch 中单个命令过期不影响其它命令
+        let res = service.execute(CommandRequest::new_batch(
+            vec![
+                CommandRequest::new_hset("t1", "k1", "v1".into()).with_deadline(expired),
+                CommandRequest::new_hset("t1", "k2", "v2".into()),
+            ],
+            false,
+        ));
+        let responses = res.batch.unwrap().responses;
+        assert_eq!(responses[0].status, 504);
+        assert_eq!(responses[1].status, 200);
+
+        let mut chunks = Vec::new();
+        let cmd = CommandRequest::new_hgetall_chunked("t1", 1).with_deadline(expired);
+        service.execute_chunked(cmd, |res| {
+            chunks.push(res);
+            true
+        });
+        assert_eq!(chunks.len(), 1);
+        assert_res_error(chunks.remove(0), 504, "Deadline exceeded");
+    }
+
+    #[test]
+    fn cancelled_batch_should_stop() {
+        use std::sync::atomic::Ordering;
+
+        let store = MemTable::default();
+        let cancelled = AtomicBool::new(false);
+        let batch = CommandBatch {
+            requests: (0..10)
+                .map(|i| CommandRequest::new_hset("t1", format!("k{}", i), i.into()))
+                .collect(),
+            stop_on_error: false,
+        };
+
+        let mut n = 0;
+        let res = batch.run(Deadline::new(0, Some(&cancelled)), |cmd| {
+            n += 1;
+            if n == 2 {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+            dispatch(cmd, &store)
+        });
+
+        let responses = res.batch.unwrap().responses;
+        assert_eq!(responses.len(), 3);
+        assert_res_error(responses[2].clone(), 499, "cancelled");
+        assert_eq!(store.get_all("t1").unwrap().len(), 2);
+
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let res = service.execute_cancellable(CommandRequest::new_batch(vec![], false), &cancelled);
+        assert_eq!(res.status, 499);
+    }
+
+    #[test]
+    fn cancelled_chunked_hgetall_should_stop() {
+        use std::sync::atomic::Ordering;
+
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        for i in 0..10 {
+            service.execute(CommandRequest::new_hset("t1", format!("k{}", i), i.into()));
+        }
+
+        // 每个 chunk 之前检查是否被取消，取消后用一个出错的 response 结束
+        let cancelled = AtomicBool::new(false);
+        let mut chunks = Vec::new();
+        let cmd = CommandRequest::new_hgetall_chunked("t1", 2);
+        service.execute_chunked_cancellable(cmd, &cancelled, |res| {
+            chunks.push(res);
+            cancelled.store(chunks.len() == 2, Ordering::Relaxed);
+            true
+        });
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[1].has_more);
+        assert_res_error(chunks.remove(2), 499, "cancelled");
+    }
 }
@@ -1,7 +1,18 @@
+use super::{Deadline, schema};
 use crate::*;
 
+/// 长时间的扫描每读取这么多 pair 检查一次 deadline，分页扫描时这也是第一页的大小
+const SCAN_PAGE_SIZE: usize = 1024;
+
 impl CommandService for Hset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
+        if let Some(v) = &self.pair {
+            let value = v.value.clone().unwrap_or_default();
+            if let Err(e) = schema::check(store, &self.table, [(v.key.as_str(), &value)]) {
+                return e.into();
+            }
+        }
+
         match self.pair {
             Some(v) => match store.set(&self.table, v.key, v.value.unwrap_or_default()) {
                 Ok(Some(v)) => v.into(),
@@ -15,7 +26,12 @@ impl CommandService for Hset {
 
 impl CommandService for Hget {
     fn execute(self, store: &impl crate::Storage) -> CommandResponse {
-        match store.get(&self.table, &self.key) {
+        let value = match self.as_of {
+            0 => store.get(&self.table, &self.key),
+            as_of => store.get_as_of(&self.table, &self.key, as_of),
+        };
+
+        match value {
             Ok(Some(v)) => v.into(),
             Ok(None) => KVError::NotFound(self.table, self.key).into(),
             Err(e) => e.into(),
@@ -32,8 +48,114 @@ impl CommandService for Hgetall {
     }
 }
 
+impl Hgetall {
+    /// 遍历 table 的过程中检查 deadline，超时或者被取消时返回错误
+    pub(crate) fn scan(self, store: &impl Storage, deadline: Deadline) -> CommandResponse {
+        if !deadline.is_set() {
+            return self.execute(store);
+        }
+
+        let iter = match store.get_iter(&self.table) {
+            Ok(iter) => iter,
+            Err(e) => return e.into(),
+        };
+
+        let mut pairs = Vec::new();
+        for (i, pair) in iter.enumerate() {
+            if i % SCAN_PAGE_SIZE == 0
+                && let Err(e) = deadline.check()
+            {
+                return e.into();
+            }
+            pairs.push(pair);
+        }
+
+        pairs.into()
+    }
+
+    /// 按 chunk_size 分块读取，依次交给 f，除最后一个外 has_more 都为 true；f 返回 false 时停止
+    ///
+    /// 每个 chunk 之前检查 deadline，超时或者被取消时用一个出错的 response 结束。
+    pub(crate) fn scan_chunked(
+        self,
+        store: &impl Storage,
+        deadline: Deadline,
+        mut f: impl FnMut(CommandResponse) -> bool,
+    ) {
+        let chunk_size = (self.chunk_size as usize).max(1);
+        let mut iter = match store.get_iter(&self.table) {
+            Ok(iter) => iter,
+            Err(e) => {
+                f(e.into());
+                return;
+            }
+        };
+
+        loop {
+            if let Err(e) = deadline.check() {
+                f(e.into());
+                return;
+            }
+
+            let pairs: Vec<_> = iter.by_ref().take(chunk_size).collect();
+            let done = pairs.len() < chunk_size;
+            let mut res = CommandResponse::from(pairs);
+            res.has_more = !done;
+
+            if !f(res) || done {
+                return;
+            }
+        }
+    }
+}
+
+impl CommandService for Hmget {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_many(&self.table, self.keys) {
+            Ok(v) => v
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let default = Value::default();
+        let pairs = self
+            .pairs
+            .iter()
+            .map(|p| (p.key.as_str(), p.value.as_ref().unwrap_or(&default)));
+        if let Err(e) = schema::check(store, &self.table, pairs) {
+            return e.into();
+        }
+
+        let ops = self
+            .pairs
+            .into_iter()
+            .map(|p| WriteOp::set(&self.table, p.key, p.value.unwrap_or_default()))
+            .collect();
+
+        match store.write_batch(ops) {
+            Ok(v) => v
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl CommandService for Hdel {
     fn execute(self, store: &impl Storage) -> CommandResponse {
+        if let Err(e) = schema::check_writable(&self.table) {
+            return e.into();
+        }
+
         match store.del(&self.table, &self.key) {
             Ok(Some(v)) => v.into(),
             Ok(None) => KVError::NotFound(self.table, self.key).into(),
@@ -42,6 +164,271 @@ impl CommandService for Hdel {
     }
 }
 
+impl CommandService for Hmdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if let Err(e) = schema::check_writable(&self.table) {
+            return e.into();
+        }
+
+        let ops = self
+            .keys
+            .into_iter()
+            .map(|k| WriteOp::del(&self.table, k))
+            .collect();
+
+        match store.write_batch(ops) {
+            Ok(v) => v
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hexist {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.contains(&self.table, &self.key) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmexist {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.contains_many(&self.table, self.keys) {
+            Ok(v) => v.into_iter().map(Value::from).collect::<Vec<_>>().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // 空字符串表示没有上界
+        let end = (!self.end.is_empty()).then_some(self.end.as_str());
+
+        match store.get_range(
+            &self.table,
+            &self.start,
+            end,
+            self.limit as usize,
+            self.reverse,
+        ) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hprefix {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_prefix(&self.table, &self.prefix) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hscan {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let end = (!self.end.is_empty()).then_some(self.end.as_str());
+
+        match store.get_range(&self.table, &self.start, end, self.limit as usize, false) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl Hrange {
+    /// 分页扫描，每页之前检查 deadline
+    pub(crate) fn scan(self, store: &impl Storage, deadline: Deadline) -> CommandResponse {
+        if !deadline.is_set() {
+            return self.execute(store);
+        }
+
+        let end = (!self.end.is_empty()).then_some(self.end.as_str());
+        let limit = self.limit as usize;
+
+        match scan_range(
+            store,
+            &self.table,
+            &self.start,
+            end,
+            limit,
+            self.reverse,
+            deadline,
+        ) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl Hprefix {
+    /// 以 prefix 开头的 key 就是 [prefix, prefix_end(prefix)) 范围内的 key，和 Hrange 一样分页扫描
+    pub(crate) fn scan(self, store: &impl Storage, deadline: Deadline) -> CommandResponse {
+        if !deadline.is_set() {
+            return self.execute(store);
+        }
+
+        let end = prefix_end(&self.prefix);
+
+        match scan_range(
+            store,
+            &self.table,
+            &self.prefix,
+            end.as_deref(),
+            0,
+            false,
+            deadline,
+        ) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl Hscan {
+    /// 分页扫描，每页之前检查 deadline
+    pub(crate) fn scan(self, store: &impl Storage, deadline: Deadline) -> CommandResponse {
+        if !deadline.is_set() {
+            return self.execute(store);
+        }
+
+        let end = (!self.end.is_empty()).then_some(self.end.as_str());
+        let limit = self.limit as usize;
+
+        match scan_range(store, &self.table, &self.start, end, limit, false, deadline) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// 和 Storage::get_range 的结果一样，但分成多次 get_range 读取，每次之前检查 deadline
+//
+// 页的大小从 SCAN_PAGE_SIZE 开始翻倍：有序的存储每页只读取需要的数据，
+// 没有顺序的存储（比如 MemTable）每页都要遍历整个 table，翻倍让页数只有对数级别。
+fn scan_range(
+    store: &impl Storage,
+    table: &str,
+    start: &str,
+    end: Option<&str>,
+    limit: usize,
+    reverse: bool,
+    deadline: Deadline,
+) -> Result<Vec<KvPair>, KVError> {
+    let (mut start, mut end) = (start.to_owned(), end.map(str::to_owned));
+    let mut pairs = Vec::new();
+    let mut page = SCAN_PAGE_SIZE;
+
+    loop {
+        deadline.check()?;
+
+        let want = match limit {
+            0 => page,
+            limit => page.min(limit - pairs.len()),
+        };
+        let batch = store.get_range(table, &start, end.as_deref(), want, reverse)?;
+        let done = batch.len() < want;
+
+        // 下一页从这一页最后一个 key 之后（反向时之前）开始
+        if let Some(last) = batch.last() {
+            if reverse {
+                end = Some(last.key.clone());
+            } else {
+                start = format!("{}\0", last.key);
+            }
+        }
+        pairs.extend(batch);
+
+        if done || (limit != 0 && pairs.len() >= limit) {
+            return Ok(pairs);
+        }
+        page = page.saturating_mul(2);
+    }
+}
+
+// 比所有以 prefix 开头的 key 都大的最小的 key，None 表示没有上界
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut end = prefix.to_owned();
+
+    while let Some(c) = end.pop() {
+        // 跳过 surrogate 区间，char::MAX 没有下一个字符，只能去掉它再进位
+        let next = match c {
+            '\u{d7ff}' => Some('\u{e000}'),
+            c => char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            end.push(next);
+            return Some(end);
+        }
+    }
+
+    None
+}
+
+impl CommandService for Htables {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.tables() {
+            Ok(v) => self.filter(v),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl Htables {
+    // 只保留以 prefix 开头的 table，去掉 prefix 后仍然有序
+    pub(crate) fn filter(&self, tables: Vec<String>) -> CommandResponse {
+        tables
+            .into_iter()
+            .filter_map(|t| t.strip_prefix(&self.prefix).map(Value::from))
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+impl CommandService for HgetVersion {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_version(&self.table, &self.key, self.version) {
+            Ok(Some(v)) => vec![v].into(),
+            Ok(None) => KVError::NotFound(self.table, self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hhistory {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.history(&self.table, &self.key) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HsetSchema {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match schema::set_schema(store, &self.table, self.schema) {
+            Ok(()) => CommandResponse::from(None),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HgetSchema {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match schema::get_schema(store, &self.table) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -104,6 +491,123 @@ mod test {
         assert_res_ok(res, &[], pairs);
     }
 
+    #[test]
+    fn hrange_should_work() {
+        let store = MemTable::new();
+        for key in ["u3", "u1", "u4", "u2"] {
+            dispatch(CommandRequest::new_hset("score", key, 1.into()), &store);
+        }
+
+        let keys = |res: CommandResponse| res.pairs.into_iter().map(|p| p.key).collect::<Vec<_>>();
+
+        let cmd = CommandRequest::new_hrange("score", "u2", "", 0, false);
+        assert_eq!(keys(dispatch(cmd, &store)), ["u2", "u3", "u4"]);
+
+        let cmd = CommandRequest::new_hrange("score", "u1", "u4", 2, true);
+        assert_eq!(keys(dispatch(cmd, &store)), ["u3", "u2"]);
+
+        let cmd = CommandRequest::new_hrange("score", "u5", "", 0, false);
+        assert_res_ok(dispatch(cmd, &store), &[], &[]);
+    }
+
+    #[test]
+    fn hprefix_should_work() {
+        let store = MemTable::new();
+        for key in ["user:2", "order:1", "user:1"] {
+            dispatch(CommandRequest::new_hset("t1", key, key.into()), &store);
+        }
+
+        let cmd = CommandRequest::new_hprefix("t1", "user:");
+        let res = dispatch(cmd, &store);
+        assert_eq!(
+            res.pairs,
+            [
+                KvPair::new("user:1", "user:1".into()),
+                KvPair::new("user:2", "user:2".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn hhistory_and_hget_version_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 2);
+        for i in 1..=3 {
+            dispatch(CommandRequest::new_hset("score", "u1", i.into()), &store);
+        }
+
+        let res = dispatch(CommandRequest::new_hhistory("score", "u1"), &store);
+        assert_res_ok(res.clone(), &[], &[]);
+        let versions: Vec<_> = res
+            .versions
+            .into_iter()
+            .map(|v| (v.version, v.value))
+            .collect();
+        assert_eq!(versions, [(3, Some(3.into())), (2, Some(2.into()))]);
+
+        let res = dispatch(CommandRequest::new_hget_version("score", "u1", 2), &store);
+        assert_eq!(res.versions[0].value, Some(2.into()));
+
+        // 已经被淘汰的版本
+        let res = dispatch(CommandRequest::new_hget_version("score", "u1", 1), &store);
+        assert_res_error(res, 404, "Not found");
+
+        let res = dispatch(CommandRequest::new_hget_as_of("score", "u1", 1), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn hhistory_on_unversioned_storage_should_fail() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hhistory("score", "u1"), &store);
+        assert_res_error(res, 400, "Versions are not supported");
+    }
+
+    #[test]
+    fn schema_should_reject_invalid_writes() {
+        let store = MemTable::new();
+        let schema = TableSchema {
+            value_type: ValueType::Integer as i32,
+            max_value_size: 0,
+            key_pattern: "u[0-9]+".into(),
+        };
+
+        let cmd = CommandRequest::new_hset_schema("score", Some(schema.clone()));
+        assert_res_ok(dispatch(cmd, &store), &[], &[]);
+        let res = dispatch(CommandRequest::new_hget_schema("score"), &store);
+        assert_eq!(res.schema, Some(schema));
+
+        let res = dispatch(CommandRequest::new_hset("score", "u1", 10.into()), &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = dispatch(CommandRequest::new_hset("score", "u1", "10".into()), &store);
+        assert_res_error(res, 400, "must be INTEGER");
+        let res = dispatch(CommandRequest::new_hset("score", "x1", 10.into()), &store);
+        assert_res_error(res, 400, "does not match");
+
+        // Hmset 中有一个不满足 schema，整个命令都不执行
+        let pairs = vec![KvPair::new("u2", 1.into()), KvPair::new("u3", "3".into())];
+        let res = dispatch(CommandRequest::new_hmset("score", pairs), &store);
+        assert_res_error(res, 400, "must be INTEGER");
+        let res = dispatch(CommandRequest::new_hexist("score", "u2"), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+
+        // schema 只能通过 HsetSchema 修改
+        let cmd = CommandRequest::new_hset(schema::SCHEMA_TABLE, "score", "x".into());
+        assert_res_error(dispatch(cmd, &store), 400, "reserved");
+        let cmd = CommandRequest::new_hdel(schema::SCHEMA_TABLE, "score");
+        assert_res_error(dispatch(cmd, &store), 400, "reserved");
+        let cmd = CommandRequest::new_hmdel(schema::SCHEMA_TABLE, vec!["score".into()]);
+        assert_res_error(dispatch(cmd, &store), 400, "reserved");
+        let res = dispatch(CommandRequest::new_hset("score", "x1", 10.into()), &store);
+        assert_res_error(res, 400, "does not match");
+
+        let cmd = CommandRequest::new_hset_schema("score", None);
+        assert_res_ok(dispatch(cmd, &store), &[], &[]);
+        let res = dispatch(CommandRequest::new_hget_schema("score"), &store);
+        assert_eq!(res.schema, None);
+        let res = dispatch(CommandRequest::new_hset("score", "x1", "10".into()), &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+    }
+
     #[test]
     fn hdel_should_work() {
         let store = MemTable::new();
@@ -130,14 +634,174 @@ mod test {
         assert_res_error(res, 404, "Not found");
     }
 
-    // 从 Request 中得到 Response，目前处理 HSET，HGET，HGETALL，HDEL
+    #[test]
+    fn hmget_should_work() {
+        let store = MemTable::new();
+        set_key_pairs(
+            "user",
+            vec![("u1", "Tyr"), ("u2", "Lindsey"), ("u3", "Rosie")],
+            &store,
+        );
+
+        let cmd = CommandRequest::new_hmget("user", vec!["u1".into(), "u4".into(), "u3".into()]);
+        let res = dispatch(cmd, &store);
+        let values = &["Tyr".into(), Value::default(), "Rosie".into()];
+
+        assert_res_ok(res, values, &[]);
+    }
+
+    #[test]
+    fn hmset_should_work() {
+        let store = MemTable::new();
+        set_key_pairs("t1", vec![("u1", "world")], &store);
+
+        let pairs = vec![
+            KvPair::new("u1", 10.1.into()),
+            KvPair::new("u2", 8.1.into()),
+        ];
+        let cmd = CommandRequest::new_hmset("t1", pairs);
+        let res = dispatch(cmd, &store);
+
+        assert_res_ok(res, &["world".into(), Value::default()], &[]);
+    }
+
+    #[test]
+    fn hmdel_should_work() {
+        let store = MemTable::new();
+        set_key_pairs("user", vec![("u1", "v1"), ("u2", "v2")], &store);
+
+        let cmd = CommandRequest::new_hmdel("user", vec!["u1".into(), "u3".into()]);
+        let res = dispatch(cmd, &store);
+
+        assert_res_ok(res, &["v1".into(), Value::default()], &[]);
+    }
+
+    #[test]
+    fn hexist_should_work() {
+        let store = MemTable::new();
+        set_key_pairs("user", vec![("u1", "v1")], &store);
+
+        let res = dispatch(CommandRequest::new_hexist("user", "u1"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hexist("user", "u2"), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+    }
+
+    #[test]
+    fn hmexist_should_work() {
+        let store = MemTable::new();
+        set_key_pairs("user", vec![("u1", "v1"), ("u2", "v2")], &store);
+
+        let cmd = CommandRequest::new_hmexist("user", vec!["u1".into(), "u3".into()]);
+        let res = dispatch(cmd, &store);
+
+        assert_res_ok(res, &[true.into(), false.into()], &[]);
+    }
+
+    #[test]
+    fn paged_scans_should_match_single_scans() {
+        let store = MemTable::new();
+        let pairs = (0..3000)
+            .map(|i| KvPair::new(format!("k{:04}", i), i.into()))
+            .collect();
+        store.set_many("t1", pairs).unwrap();
+        store.set("t1", "l1".into(), 1.into()).unwrap();
+
+        let deadline = Deadline::new(u64::MAX, None);
+        let cmds = [
+            CommandRequest::new_hrange("t1", "k0100", "k2900", 0, false),
+            CommandRequest::new_hrange("t1", "k0100", "", 2500, true),
+            CommandRequest::new_hprefix("t1", "k1"),
+            CommandRequest::new_hprefix("t1", "k"),
+            CommandRequest::new_hscan("t1", "k0999", "", 1500, 0),
+        ];
+        for cmd in cmds {
+            assert_eq!(scan(cmd.clone(), &store, deadline), dispatch(cmd, &store));
+        }
+
+        // MemTable 的 Hgetall 没有顺序
+        let mut res = scan(CommandRequest::new_hgetall("t1"), &store, deadline);
+        res.pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut expected = store.get_all("t1").unwrap();
+        expected.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(res.pairs, expected);
+    }
+
+    #[test]
+    fn scans_should_stop_at_deadline() {
+        let store = MemTable::new();
+        set_key_pairs("t1", vec![("k1", "v1"), ("k2", "v2")], &store);
+
+        let deadline = Deadline::new(1, None);
+        for cmd in [
+            CommandRequest::new_hgetall("t1"),
+            CommandRequest::new_hrange("t1", "", "", 0, false),
+            CommandRequest::new_hprefix("t1", "k"),
+            CommandRequest::new_hscan("t1", "", "", 0, 0),
+        ] {
+            assert_res_error(scan(cmd, &store, deadline), 504, "Deadline exceeded");
+        }
+    }
+
+    #[test]
+    fn prefix_end_should_work() {
+        assert_eq!(prefix_end("ab"), Some("ac".into()));
+        assert_eq!(prefix_end("a\u{d7ff}"), Some("a\u{e000}".into()));
+        assert_eq!(prefix_end("a\u{10ffff}"), Some("b".into()));
+        assert_eq!(prefix_end("\u{10ffff}"), None);
+        assert_eq!(prefix_end(""), None);
+    }
+
+    fn set_key_pairs<T: Into<Value>>(table: &str, pairs: Vec<(&str, T)>, store: &impl Storage) {
+        pairs
+            .into_iter()
+            .map(|(k, v)| CommandRequest::new_hset(table, k, v.into()))
+            .for_each(|cmd| {
+                dispatch(cmd, store);
+            });
+    }
+
+    fn scan(cmd: CommandRequest, store: &impl Storage, deadline: Deadline) -> CommandResponse {
+        match cmd.request_data.unwrap() {
+            RequestData::Hgetall(v) => v.scan(store, deadline),
+            RequestData::Hrange(v) => v.scan(store, deadline),
+            RequestData::Hprefix(v) => v.scan(store, deadline),
+            RequestData::Hscan(v) => v.scan(store, deadline),
+            _ => unreachable!(),
+        }
+    }
+
+    // 从 Request 中得到 Response
     fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
         match cmd.request_data.unwrap() {
             RequestData::Hset(v) => v.execute(store),
             RequestData::Hget(v) => v.execute(store),
             RequestData::Hgetall(v) => v.execute(store),
+            RequestData::Hmget(v) => v.execute(store),
+            RequestData::Hmset(v) => v.execute(store),
             RequestData::Hdel(v) => v.execute(store),
-            _ => todo!(), // TODO
+            RequestData::Hmdel(v) => v.execute(store),
+            RequestData::Hexist(v) => v.execute(store),
+            RequestData::Hmexist(v) => v.execute(store),
+            RequestData::Hrange(v) => v.execute(store),
+            RequestData::Hprefix(v) => v.execute(store),
+            RequestData::Htables(v) => v.execute(store),
+            RequestData::Hscan(v) => v.execute(store),
+            RequestData::HgetVersion(v) => v.execute(store),
+            RequestData::Hhistory(v) => v.execute(store),
+            RequestData::HsetSchema(v) => v.execute(store),
+            RequestData::HgetSchema(v) => v.execute(store),
+            // 快照命令只能通过 Service 执行，batch 由 Service 展开
+            RequestData::Hsnapshot(_)
+            | RequestData::Hrelease(_)
+            | RequestData::Batch(_)
+            | RequestData::Auth(_)
+            | RequestData::UseNamespace(_)
+            | RequestData::ListNamespaces(_)
+            | RequestData::DropNamespace(_) => {
+                unreachable!()
+            }
         }
     }
 }
@@ -0,0 +1,84 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::KVError;
+
+/// 命令的截止时间和取消标志，batch、分块命令和长时间的扫描在每一步之前检查
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Deadline<'a> {
+    /// UNIX 毫秒时间戳，0 表示不限制
+    at: u64,
+    /// 客户端断开时被设置
+    cancelled: Option<&'a AtomicBool>,
+}
+
+impl<'a> Deadline<'a> {
+    pub(crate) fn new(at: u64, cancelled: Option<&'a AtomicBool>) -> Self {
+        Self { at, cancelled }
+    }
+
+    /// 和另一个截止时间比较，取更早的那个
+    pub(crate) fn min(self, at: u64) -> Self {
+        let at = match (self.at, at) {
+            (0, at) | (at, 0) => at,
+            (a, b) => a.min(b),
+        };
+
+        Self { at, ..self }
+    }
+
+    pub(crate) fn at(&self) -> u64 {
+        self.at
+    }
+
+    /// 是否设置了截止时间或者可以被取消
+    pub(crate) fn is_set(&self) -> bool {
+        self.at != 0 || self.cancelled.is_some()
+    }
+
+    pub(crate) fn check(&self) -> Result<(), KVError> {
+        if self.cancelled.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            return Err(KVError::Cancelled);
+        }
+
+        if self.at != 0 && now_ms() >= self.at {
+            return Err(KVError::DeadlineExceeded(self.at));
+        }
+
+        Ok(())
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline_should_expire_and_cancel() {
+        assert!(Deadline::default().check().is_ok());
+        assert!(Deadline::new(now_ms() + 60_000, None).check().is_ok());
+        assert_eq!(
+            Deadline::new(1, None).check(),
+            Err(KVError::DeadlineExceeded(1))
+        );
+
+        let d = Deadline::new(0, None).min(5);
+        assert_eq!(d.at, 5);
+        assert_eq!(d.min(0).at, 5);
+        assert_eq!(d.min(3).at, 3);
+
+        let cancelled = AtomicBool::new(false);
+        let d = Deadline::new(0, Some(&cancelled));
+        assert!(d.check().is_ok());
+        cancelled.store(true, Ordering::Relaxed);
+        assert_eq!(d.check(), Err(KVError::Cancelled));
+    }
+}
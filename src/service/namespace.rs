@@ -0,0 +1,546 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use prost::Message;
+
+use super::{Deadline, dispatch_chunked, dispatch_until, schema::SCHEMA_TABLE};
+use crate::{
+    CommandRequest, CommandResponse, KVError, NamespaceInfo, Storage, Value,
+    command_request::RequestData,
+};
+
+/// namespace 中的 table 在存储中的名字是 `{namespace}/{table}`
+pub const NAMESPACE_SEPARATOR: char = '/';
+
+/// namespace 的配额，0 表示不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_keys: u64,
+    /// key 和编码后的 value 的总字节数
+    pub max_bytes: u64,
+}
+
+impl Quota {
+    fn is_unlimited(&self) -> bool {
+        self.max_keys == 0 && self.max_bytes == 0
+    }
+}
+
+/// namespace 的配额配置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NamespaceConfig {
+    /// 所有 namespace 默认的配额
+    pub quota: Quota,
+    /// 为某些 namespace 单独指定配额
+    pub quotas: HashMap<String, Quota>,
+}
+
+impl NamespaceConfig {
+    fn quota(&self, namespace: &str) -> Quota {
+        self.quotas.get(namespace).copied().unwrap_or(self.quota)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    keys: u64,
+    bytes: u64,
+}
+
+/// Service 统计的每个 namespace 的用量
+///
+/// 用量在第一次需要时（有配额的 namespace 写入、ListNamespaces）从存储中统计，之后随 Service 执行的写入更新。
+/// 每个 namespace 有自己的锁，同一个 namespace 的写入和统计串行执行，不同 namespace 之间互不影响。
+/// 绕过 Service 直接写入存储的数据不会被统计。
+#[derive(Default)]
+pub(crate) struct Namespaces {
+    config: NamespaceConfig,
+    /// None 表示还没有统计过
+    usage: Mutex<HashMap<String, Arc<Mutex<Option<Usage>>>>>,
+}
+
+impl Namespaces {
+    pub(crate) fn new(config: NamespaceConfig) -> Self {
+        Self {
+            config,
+            usage: Mutex::default(),
+        }
+    }
+
+    /// 执行 namespace 管理命令，检查写入是否超出配额，其它命令直接交给 dispatch
+    pub(crate) fn execute(
+        &self,
+        cmd: CommandRequest,
+        store: &impl Storage,
+        deadline: Deadline,
+    ) -> CommandResponse {
+        let result = match &cmd.request_data {
+            Some(RequestData::ListNamespaces(_)) => self.list(store).map(CommandResponse::from),
+            Some(RequestData::DropNamespace(v)) => self
+                .drop_namespace(&v.namespace, store)
+                .map(|n| Value::from(n as i64).into()),
+            _ => self.write(cmd, store, deadline),
+        };
+
+        result.unwrap_or_else(CommandResponse::from)
+    }
+
+    /// 和 execute 一样，分块读取的结果依次交给 f；读命令不影响用量，直接交给 dispatch_chunked
+    pub(crate) fn execute_chunked(
+        &self,
+        cmd: CommandRequest,
+        store: &impl Storage,
+        deadline: Deadline,
+        mut f: impl FnMut(CommandResponse) -> bool,
+    ) {
+        let managed = cmd.request_data.as_ref().is_some_and(|data| {
+            matches!(
+                data,
+                RequestData::ListNamespaces(_) | RequestData::DropNamespace(_)
+            ) || changes(data).is_some()
+        });
+
+        if managed {
+            f(self.execute(cmd, store, deadline));
+        } else {
+            dispatch_chunked(cmd, store, deadline, f);
+        }
+    }
+
+    fn write(
+        &self,
+        cmd: CommandRequest,
+        store: &impl Storage,
+        deadline: Deadline,
+    ) -> Result<CommandResponse, KVError> {
+        let Some((table, changes)) = cmd.request_data.as_ref().and_then(changes) else {
+            return Ok(dispatch_until(cmd, store, deadline));
+        };
+        let Some(namespace) = namespace_of(&table) else {
+            return Ok(dispatch_until(cmd, store, deadline));
+        };
+
+        let quota = self.config.quota(namespace);
+        let slot = self.slot(namespace);
+        let mut current = lock(&slot);
+        // 没有配额也没有统计过的 namespace 不需要统计，但仍然持有锁，避免和并发的统计交错
+        if quota.is_unlimited() && current.is_none() {
+            return Ok(dispatch_until(cmd, store, deadline));
+        }
+
+        let usage = match *current {
+            Some(usage) => usage,
+            None => count(namespace, store)?,
+        };
+
+        let keys = changes.keys().cloned().collect::<Vec<_>>();
+        let olds = store.get_many(&table, keys.clone())?;
+        let (mut keys_delta, mut bytes_delta) = (0i64, 0i64);
+        for (key, old) in keys.iter().zip(olds) {
+            match (old, changes[key]) {
+                (Some(old), Some(new)) => bytes_delta += new - size(key, &old),
+                (Some(old), None) => {
+                    keys_delta -= 1;
+                    bytes_delta -= size(key, &old);
+                }
+                (None, Some(new)) => {
+                    keys_delta += 1;
+                    bytes_delta += new;
+                }
+                (None, None) => {}
+            }
+        }
+
+        let keys = usage.keys.saturating_add_signed(keys_delta);
+        let bytes = usage.bytes.saturating_add_signed(bytes_delta);
+        // 删除总是允许的，即使删除之后仍然超出配额
+        if keys_delta > 0 && quota.max_keys > 0 && keys > quota.max_keys {
+            return Err(KVError::QuotaExceeded(
+                namespace.into(),
+                format!("{} keys exceeds the limit of {}", keys, quota.max_keys),
+            ));
+        }
+        if bytes_delta > 0 && quota.max_bytes > 0 && bytes > quota.max_bytes {
+            return Err(KVError::QuotaExceeded(
+                namespace.into(),
+                format!("{} bytes exceeds the limit of {}", bytes, quota.max_bytes),
+            ));
+        }
+
+        let res = dispatch_until(cmd, store, deadline);
+        *current = Some(match res.status {
+            200..=299 => Usage { keys, bytes },
+            _ => usage,
+        });
+
+        Ok(res)
+    }
+
+    fn list(&self, store: &impl Storage) -> Result<Vec<NamespaceInfo>, KVError> {
+        let tables = store.tables()?;
+        let mut names: BTreeSet<_> = tables.iter().filter_map(|t| namespace_of(t)).collect();
+        names.extend(self.config.quotas.keys().map(String::as_str));
+
+        names
+            .into_iter()
+            .map(|name| {
+                // 统计过之后，这个 namespace 的写入都会更新用量
+                let slot = self.slot(name);
+                let mut current = lock(&slot);
+                let usage = match *current {
+                    Some(usage) => usage,
+                    None => *current.insert(count(name, store)?),
+                };
+                let quota = self.config.quota(name);
+
+                Ok(NamespaceInfo {
+                    name: name.into(),
+                    keys: usage.keys,
+                    bytes: usage.bytes,
+                    max_keys: quota.max_keys,
+                    max_bytes: quota.max_bytes,
+                })
+            })
+            .collect()
+    }
+
+    // 删除 namespace 中所有 table 的数据和 schema，返回删除的 key 的数量
+    fn drop_namespace(&self, namespace: &str, store: &impl Storage) -> Result<usize, KVError> {
+        validate_namespace(namespace)?;
+        let prefix = format!("{}{}", namespace, NAMESPACE_SEPARATOR);
+
+        let slot = self.slot(namespace);
+        let mut current = lock(&slot);
+        let deleted = store.drop_tables(&prefix)?;
+
+        let schemas = store
+            .get_iter(SCHEMA_TABLE)?
+            .map(|p| p.key)
+            .filter(|k| k.starts_with(&prefix))
+            .collect();
+        store.del_many(SCHEMA_TABLE, schemas)?;
+        *current = None;
+
+        Ok(deleted)
+    }
+
+    fn slot(&self, namespace: &str) -> Arc<Mutex<Option<Usage>>> {
+        lock(&self.usage)
+            .entry(namespace.into())
+            .or_default()
+            .clone()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// namespace 不能为空，不能包含分隔符，也不能以 `__` 开头（和内部使用的 table 冲突）
+pub(crate) fn validate_namespace(namespace: &str) -> Result<(), KVError> {
+    if namespace.is_empty()
+        || namespace.contains(NAMESPACE_SEPARATOR)
+        || namespace.starts_with("__")
+    {
+        return Err(KVError::InvalidCommand(format!(
+            "Invalid namespace: {:?}",
+            namespace
+        )));
+    }
+
+    Ok(())
+}
+
+/// 把命令（包括 batch 中的命令）中的 table 放到 namespace 中
+pub(crate) fn qualify(namespace: &str, cmd: &mut CommandRequest) {
+    match &mut cmd.request_data {
+        Some(RequestData::Batch(batch)) => {
+            for cmd in &mut batch.requests {
+                qualify(namespace, cmd);
+            }
+        }
+        Some(RequestData::Htables(v)) => {
+            v.prefix = format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, v.prefix);
+        }
+        Some(data) => {
+            if let Some(table) = data.table_mut() {
+                *table = format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, table);
+            }
+        }
+        None => {}
+    }
+}
+
+/// 去掉错误信息中 table 的 namespace 前缀，客户端看到的是自己发送的 table 名
+pub(crate) fn unqualify(namespace: &str, res: &mut CommandResponse) {
+    if let Some(batch) = &mut res.batch {
+        for res in &mut batch.responses {
+            unqualify(namespace, res);
+        }
+    }
+
+    let prefix = format!("{}{}", namespace, NAMESPACE_SEPARATOR);
+    if let Some(info) = &mut res.error
+        && let Some(table) = info.table.strip_prefix(&prefix)
+    {
+        info.table = table.into();
+        res.message = KVError::from(info.clone()).to_string();
+    }
+}
+
+// 存储中的 table 属于哪个 namespace，内部使用的 table 不属于任何 namespace
+fn namespace_of(table: &str) -> Option<&str> {
+    if table.starts_with("__") {
+        return None;
+    }
+
+    table
+        .split_once(NAMESPACE_SEPARATOR)
+        .map(|(namespace, _)| namespace)
+        .filter(|namespace| !namespace.is_empty())
+}
+
+// 写命令修改的 table，以及每个 key 写入后的大小，None 表示删除；同一个 key 以最后一次为准
+fn changes(data: &RequestData) -> Option<(String, HashMap<String, Option<i64>>)> {
+    let new = |key: &String, value: &Option<Value>| {
+        let size = size(key, value.as_ref().unwrap_or(&Value::default()));
+        (key.clone(), Some(size))
+    };
+
+    let changes = match data {
+        RequestData::Hset(v) => {
+            let pair = v.pair.as_ref()?;
+            (&v.table, [new(&pair.key, &pair.value)].into())
+        }
+        RequestData::Hmset(v) => (
+            &v.table,
+            v.pairs.iter().map(|p| new(&p.key, &p.value)).collect(),
+        ),
+        RequestData::Hdel(v) => (&v.table, [(v.key.clone(), None)].into()),
+        RequestData::Hmdel(v) => (&v.table, v.keys.iter().map(|k| (k.clone(), None)).collect()),
+        _ => return None,
+    };
+
+    Some((changes.0.clone(), changes.1))
+}
+
+// 一个 kv pair 占用的字节数
+fn size(key: &str, value: &Value) -> i64 {
+    (key.len() + value.encoded_len()) as i64
+}
+
+// 遍历存储统计 namespace 的用量
+fn count(namespace: &str, store: &impl Storage) -> Result<Usage, KVError> {
+    let mut usage = Usage::default();
+    for table in store.tables()? {
+        if namespace_of(&table) != Some(namespace) {
+            continue;
+        }
+
+        for pair in store.get_iter(&table)? {
+            usage.keys += 1;
+            usage.bytes += size(&pair.key, &pair.value.unwrap_or_default()) as u64;
+        }
+    }
+
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, VersionedStore, assert_res_error, assert_res_ok};
+
+    fn namespaced(namespace: &str, mut cmd: CommandRequest) -> CommandRequest {
+        qualify(namespace, &mut cmd);
+        cmd
+    }
+
+    #[test]
+    fn qualify_should_prefix_tables() {
+        let cmd = CommandRequest::new_batch(
+            vec![
+                CommandRequest::new_hget("users", "k1"),
+                CommandRequest::new_htables(),
+            ],
+            false,
+        );
+        let Some(RequestData::Batch(batch)) = namespaced("team_a", cmd).request_data else {
+            panic!("expected a batch");
+        };
+
+        assert_eq!(
+            batch.requests[0],
+            CommandRequest::new_hget("team_a/users", "k1")
+        );
+        let Some(RequestData::Htables(v)) = &batch.requests[1].request_data else {
+            panic!("expected htables");
+        };
+        assert_eq!(v.prefix, "team_a/");
+
+        let mut res = CommandResponse::from(KVError::NotFound("team_a/users".into(), "k1".into()));
+        unqualify("team_a", &mut res);
+        assert_eq!(
+            res.into_result(),
+            Err(KVError::NotFound("users".into(), "k1".into()))
+        );
+    }
+
+    #[test]
+    fn namespaces_should_enforce_quota() {
+        let store = MemTable::new();
+        let quota = Quota {
+            max_keys: 2,
+            max_bytes: 0,
+        };
+        let config = NamespaceConfig {
+            quotas: [("team_a".into(), quota)].into(),
+            ..Default::default()
+        };
+        let namespaces = Namespaces::new(config);
+        let exec = |cmd| namespaces.execute(namespaced("team_a", cmd), &store, Deadline::default());
+
+        assert_res_ok(
+            exec(CommandRequest::new_hset("t1", "k1", 1.into())),
+            &[Value::default()],
+            &[],
+        );
+        // 覆盖已有的 key 不增加 key 的数量
+        assert_res_ok(
+            exec(CommandRequest::new_hset("t1", "k1", 2.into())),
+            &[1.into()],
+            &[],
+        );
+        exec(CommandRequest::new_hset("t2", "k1", 1.into()));
+        assert_res_error(
+            exec(CommandRequest::new_hset("t1", "k2", 1.into())),
+            507,
+            "team_a",
+        );
+        assert_eq!(store.get("team_a/t1", "k2"), Ok(None));
+
+        // 其它 namespace 和默认 namespace 不受影响
+        let res = namespaces.execute(
+            namespaced("team_b", CommandRequest::new_hset("t1", "k2", 1.into())),
+            &store,
+            Deadline::default(),
+        );
+        assert_eq!(res.status, 200);
+        let res = namespaces.execute(
+            CommandRequest::new_hset("t1", "k2", 1.into()),
+            &store,
+            Deadline::default(),
+        );
+        assert_eq!(res.status, 200);
+
+        exec(CommandRequest::new_hdel("t2", "k1"));
+        let res = exec(CommandRequest::new_hset("t1", "k2", 1.into()));
+        assert_eq!(res.status, 200);
+    }
+
+    #[test]
+    fn namespaces_should_be_listed_and_dropped() {
+        let store = MemTable::new();
+        let namespaces = Namespaces::default();
+
+        for (ns, table) in [("team_a", "t1"), ("team_a", "t2"), ("team_b", "t1")] {
+            let cmd = CommandRequest::new_hset(table, "key", "value".into());
+            namespaces.execute(namespaced(ns, cmd), &store, Deadline::default());
+        }
+        namespaces.execute(
+            CommandRequest::new_hset("t1", "k", 1.into()),
+            &store,
+            Deadline::default(),
+        );
+        namespaces.execute(
+            namespaced("team_a", CommandRequest::new_hset_schema("t1", None)),
+            &store,
+            Deadline::default(),
+        );
+
+        let res = namespaces.execute(
+            CommandRequest::new_list_namespaces(),
+            &store,
+            Deadline::default(),
+        );
+        let names: Vec<_> = res.namespaces.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, ["team_a", "team_b"]);
+        assert_eq!(res.namespaces[0].keys, 2);
+        let size = "key".len() + Value::from("value").encoded_len();
+        assert_eq!(res.namespaces[0].bytes, 2 * size as u64);
+
+        let res = namespaces.execute(
+            CommandRequest::new_drop_namespace("team_a"),
+            &store,
+            Deadline::default(),
+        );
+        assert_res_ok(res, &[2.into()], &[]);
+        assert_eq!(store.tables(), Ok(vec!["t1".into(), "team_b/t1".into()]));
+
+        let res = namespaces.execute(
+            CommandRequest::new_drop_namespace("a/b"),
+            &store,
+            Deadline::default(),
+        );
+        assert_res_error(res, 400, "Invalid namespace");
+    }
+
+    #[test]
+    fn drop_namespace_should_remove_versions() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        let namespaces = Namespaces::default();
+        let exec = |cmd| namespaces.execute(namespaced("team_a", cmd), &store, Deadline::default());
+
+        exec(CommandRequest::new_hset("t1", "k1", 1.into()));
+        exec(CommandRequest::new_hset("t1", "k1", 2.into()));
+        // 所有 key 都被删除的 table 只剩下历史版本
+        exec(CommandRequest::new_hset("t2", "k1", 1.into()));
+        exec(CommandRequest::new_hdel("t2", "k1"));
+        assert_eq!(store.history("team_a/t1", "k1").unwrap().len(), 2);
+
+        let res = namespaces.execute(
+            CommandRequest::new_drop_namespace("team_a"),
+            &store,
+            Deadline::default(),
+        );
+        assert_res_ok(res, &[1.into()], &[]);
+        assert_eq!(store.history("team_a/t1", "k1"), Ok(vec![]));
+        assert_eq!(store.history("team_a/t2", "k1"), Ok(vec![]));
+        assert_eq!(store.inner().tables(), Ok(vec![]));
+    }
+
+    #[test]
+    fn usage_should_stay_accurate_with_concurrent_list() {
+        let store = MemTable::new();
+        let namespaces = Namespaces::default();
+
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let (store, namespaces) = (&store, &namespaces);
+                s.spawn(move || {
+                    for j in 0..50 {
+                        let cmd = CommandRequest::new_hset("t1", format!("k{i}-{j}"), 1.into());
+                        namespaces.execute(namespaced("team_a", cmd), store, Deadline::default());
+                    }
+                });
+            }
+            for _ in 0..20 {
+                namespaces.execute(
+                    CommandRequest::new_list_namespaces(),
+                    &store,
+                    Deadline::default(),
+                );
+            }
+        });
+
+        let res = namespaces.execute(
+            CommandRequest::new_list_namespaces(),
+            &store,
+            Deadline::default(),
+        );
+        assert_eq!(res.namespaces[0].keys, 200);
+        assert_eq!(count("team_a", &store).unwrap().keys, 200);
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use bytes::Bytes;
+use prost::Message;
+use regex::Regex;
+
+use crate::{KVError, Storage, TableSchema, Value, ValueType, value};
+
+/// table 的 schema 保存在这个 table 中，key 是 table 名，value 是编码后的 TableSchema
+pub(crate) const SCHEMA_TABLE: &str = "__schema__";
+
+/// 缓存的正则数量上限，超过后清空重新编译
+const MAX_PATTERNS: usize = 1024;
+
+/// 编译好的 key 正则，以 pattern 为 key，和存储无关
+static PATTERNS: LazyLock<Mutex<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+
+impl TableSchema {
+    /// 检查要写入的 key 和 value 是否满足 schema
+    pub fn validate(&self, table: &str, key: &str, value: &Value) -> Result<(), KVError> {
+        let expected = self.value_type();
+        if expected != ValueType::Any && value_type(value) != Some(expected) {
+            return Err(KVError::InvalidCommand(format!(
+                "Value of table: {}, key: {} must be {}",
+                table,
+                key,
+                expected.as_str_name()
+            )));
+        }
+
+        if self.max_value_size > 0 && value.encoded_len() > self.max_value_size as usize {
+            return Err(KVError::InvalidCommand(format!(
+                "Value of table: {}, key: {} exceeds {} bytes",
+                table, key, self.max_value_size
+            )));
+        }
+
+        if !self.key_pattern.is_empty() && !key_regex(&self.key_pattern)?.is_match(key) {
+            return Err(KVError::InvalidCommand(format!(
+                "Key {} of table: {} does not match {}",
+                key, table, self.key_pattern
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// 读取 table 的 schema
+pub(crate) fn get_schema(
+    store: &impl Storage,
+    table: &str,
+) -> Result<Option<TableSchema>, KVError> {
+    match store.get(SCHEMA_TABLE, table)? {
+        Some(v) => Ok(Some(TableSchema::decode(Bytes::try_from(v)?)?)),
+        None => Ok(None),
+    }
+}
+
+/// 设置 table 的 schema，None 表示删除；已有的数据不会被检查
+pub(crate) fn set_schema(
+    store: &impl Storage,
+    table: &str,
+    schema: Option<TableSchema>,
+) -> Result<(), KVError> {
+    if table == SCHEMA_TABLE {
+        return Err(KVError::InvalidCommand(format!(
+            "Cannot set schema for table {}",
+            SCHEMA_TABLE
+        )));
+    }
+
+    match schema {
+        Some(schema) => {
+            if ValueType::try_from(schema.value_type).is_err() {
+                return Err(KVError::InvalidCommand(format!(
+                    "Unknown value type {}",
+                    schema.value_type
+                )));
+            }
+            if !schema.key_pattern.is_empty() {
+                key_regex(&schema.key_pattern)?;
+            }
+
+            let data = Bytes::from(schema.encode_to_vec());
+            store.set(SCHEMA_TABLE, table.into(), data.into())?;
+        }
+        None => {
+            store.del(SCHEMA_TABLE, table)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 检查写入 table 的一组 kv 是否满足 table 的 schema
+pub(crate) fn check<'a>(
+    store: &impl Storage,
+    table: &str,
+    pairs: impl IntoIterator<Item = (&'a str, &'a Value)>,
+) -> Result<(), KVError> {
+    check_writable(table)?;
+
+    let Some(schema) = get_schema(store, table)? else {
+        return Ok(());
+    };
+
+    pairs
+        .into_iter()
+        .try_for_each(|(key, value)| schema.validate(table, key, value))
+}
+
+/// schema 只能通过 HsetSchema 修改，其它写入和删除都不能直接操作 SCHEMA_TABLE
+pub(crate) fn check_writable(table: &str) -> Result<(), KVError> {
+    if table == SCHEMA_TABLE {
+        return Err(KVError::InvalidCommand(format!(
+            "Table {} is reserved",
+            SCHEMA_TABLE
+        )));
+    }
+
+    Ok(())
+}
+
+fn value_type(v: &Value) -> Option<ValueType> {
+    match v.value.as_ref()? {
+        value::Value::String(_) => Some(ValueType::String),
+        value::Value::Binary(_) => Some(ValueType::Binary),
+        value::Value::Integer(_) => Some(ValueType::Integer),
+        value::Value::Float(_) => Some(ValueType::Float),
+        value::Value::Bool(_) => Some(ValueType::Bool),
+        value::Value::List(_) => Some(ValueType::List),
+        value::Value::Map(_) => Some(ValueType::Map),
+        value::Value::Timestamp(_) => Some(ValueType::Timestamp),
+        value::Value::Unsigned(_) => Some(ValueType::Unsigned),
+        value::Value::Decimal(_) => Some(ValueType::Decimal),
+    }
+}
+
+// key 需要完整匹配 pattern
+fn key_regex(pattern: &str) -> Result<Regex, KVError> {
+    let mut patterns = PATTERNS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(re) = patterns.get(pattern) {
+        return Ok(re.clone());
+    }
+
+    let re = Regex::new(&format!("^(?:{})$", pattern))
+        .map_err(|e| KVError::InvalidCommand(format!("Invalid key pattern: {}", e)))?;
+    if patterns.len() >= MAX_PATTERNS {
+        patterns.clear();
+    }
+    patterns.insert(pattern.to_owned(), re.clone());
+
+    Ok(re)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_should_validate_type_size_and_key() {
+        let schema = TableSchema {
+            value_type: ValueType::Integer as i32,
+            max_value_size: 4,
+            key_pattern: "user:[0-9]+".into(),
+        };
+
+        assert!(schema.validate("t1", "user:1", &10.into()).is_ok());
+        assert!(schema.validate("t1", "user:1", &"10".into()).is_err());
+        assert!(schema.validate("t1", "user:1", &Value::default()).is_err());
+        assert!(schema.validate("t1", "user:1", &i64::MAX.into()).is_err());
+        // 正则需要完整匹配
+        assert!(schema.validate("t1", "user:1x", &10.into()).is_err());
+        assert!(schema.validate("t1", "xuser:1", &10.into()).is_err());
+
+        assert!(
+            TableSchema::default()
+                .validate("t1", "any", &"v".into())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn invalid_schema_should_be_rejected() {
+        let store = crate::MemTable::new();
+        let schema = |pattern: &str, value_type| TableSchema {
+            value_type,
+            max_value_size: 0,
+            key_pattern: pattern.into(),
+        };
+
+        let res = set_schema(&store, "t1", Some(schema("(", 0)));
+        assert!(matches!(res, Err(KVError::InvalidCommand(_))));
+        let res = set_schema(&store, "t1", Some(schema("", 100)));
+        assert!(matches!(res, Err(KVError::InvalidCommand(_))));
+        assert_eq!(get_schema(&store, "t1"), Ok(None));
+    }
+}
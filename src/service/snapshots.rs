@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{
+    CommandRequest, CommandResponse, Hget, Hscan, Htables, KVError, Snapshot, Storage, Value,
+    command_request::RequestData,
+};
+
+/// 同时打开的快照数量上限，避免客户端忘记释放时内存无限增长
+const MAX_SNAPSHOTS: usize = 64;
+
+/// 在快照上执行的只读命令
+pub trait SnapshotCommand {
+    fn execute_at(self, snapshot: &dyn Snapshot) -> CommandResponse;
+}
+
+/// Service 持有的快照，客户端通过 Hsnapshot 返回的 id 引用，用完后用 Hrelease 释放
+#[derive(Default)]
+pub(crate) struct Snapshots {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, Arc<dyn Snapshot>>>,
+}
+
+impl Snapshots {
+    /// 执行快照相关的命令，其它命令原样返回
+    pub(crate) fn execute(
+        &self,
+        cmd: CommandRequest,
+        store: &impl Storage,
+    ) -> Result<CommandResponse, CommandRequest> {
+        match cmd.request_data {
+            Some(RequestData::Hsnapshot(_)) => Ok(self.create(store)),
+            Some(RequestData::Hrelease(v)) => Ok(Value::from(self.release(v.snapshot)).into()),
+            Some(data) if data.snapshot() != 0 => Ok(self.read(data)),
+            request_data => Err(CommandRequest {
+                request_data,
+                ..cmd
+            }),
+        }
+    }
+
+    /// 当前打开的快照数量
+    pub(crate) fn len(&self) -> usize {
+        self.open().len()
+    }
+
+    fn create(&self, store: &impl Storage) -> CommandResponse {
+        if self.len() >= MAX_SNAPSHOTS {
+            return KVError::InvalidCommand(format!(
+                "Too many open snapshots, at most {}",
+                MAX_SNAPSHOTS
+            ))
+            .into();
+        }
+
+        match store.snapshot() {
+            Ok(snapshot) => {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+                self.open().insert(id, snapshot);
+
+                Value::from(id as i64).into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+
+    fn release(&self, id: u64) -> bool {
+        self.open().remove(&id).is_some()
+    }
+
+    fn read(&self, data: RequestData) -> CommandResponse {
+        let id = data.snapshot();
+        // 不持有锁执行命令，释放快照不影响正在进行的读取
+        let Some(snapshot) = self.open().get(&id).cloned() else {
+            return snapshot_not_found(id).into();
+        };
+
+        match data {
+            RequestData::Hget(v) => v.execute_at(&*snapshot),
+            RequestData::Htables(v) => v.execute_at(&*snapshot),
+            RequestData::Hscan(v) => v.execute_at(&*snapshot),
+            _ => KVError::InvalidCommand("Command does not support snapshots".into()).into(),
+        }
+    }
+
+    fn open(&self) -> MutexGuard<'_, HashMap<u64, Arc<dyn Snapshot>>> {
+        self.open.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl SnapshotCommand for Hget {
+    fn execute_at(self, snapshot: &dyn Snapshot) -> CommandResponse {
+        if self.as_of != 0 {
+            return KVError::InvalidCommand("Cannot read as_of in a snapshot".into()).into();
+        }
+
+        match snapshot.get(&self.table, &self.key) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => KVError::NotFound(self.table, self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl SnapshotCommand for Htables {
+    fn execute_at(self, snapshot: &dyn Snapshot) -> CommandResponse {
+        match snapshot.tables() {
+            Ok(v) => self.filter(v),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl SnapshotCommand for Hscan {
+    fn execute_at(self, snapshot: &dyn Snapshot) -> CommandResponse {
+        let end = (!self.end.is_empty()).then_some(self.end.as_str());
+
+        match snapshot.scan(&self.table, &self.start, end, self.limit as usize) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+pub(crate) fn snapshot_not_found(id: u64) -> KVError {
+    KVError::InvalidCommand(format!("Snapshot {} not found", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KvPair, MemTable, Service, ServiceInner, assert_res_error, assert_res_ok};
+    use std::time::Duration;
+
+    #[test]
+    fn snapshot_should_not_see_later_writes() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+
+        let res = service.execute(CommandRequest::new_hsnapshot());
+        let id = i64::try_from(res.values[0].clone()).unwrap() as u64;
+
+        service.execute(CommandRequest::new_hset("t1", "k1", "v2".into()));
+        service.execute(CommandRequest::new_hset("t1", "k2", "v2".into()));
+        service.execute(CommandRequest::new_hset("t2", "k1", "v1".into()));
+
+        let res = service.execute(CommandRequest::new_hget_at("t1", "k1", id));
+        assert_res_ok(res, &["v1".into()], &[]);
+        let res = service.execute(CommandRequest::new_hget_at("t1", "k2", id));
+        assert_res_error(res, 404, "Not found");
+        let res = service.execute(CommandRequest::new_hscan("t1", "", "", 0, id));
+        assert_res_ok(res, &[], &[KvPair::new("k1", "v1".into())]);
+        let res = service.execute(CommandRequest::new_htables_at(id));
+        assert_res_ok(res, &["t1".into()], &[]);
+
+        // 不带快照的命令读取最新的数据
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_ok(res, &["v2".into()], &[]);
+        let res = service.execute(CommandRequest::new_hscan("t1", "k1\0", "", 1, 0));
+        assert_res_ok(res, &[], &[KvPair::new("k2", "v2".into())]);
+
+        let res = service.execute(CommandRequest::new_hrelease(id));
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = service.execute(CommandRequest::new_hrelease(id));
+        assert_res_ok(res, &[false.into()], &[]);
+        let res = service.execute(CommandRequest::new_hget_at("t1", "k1", id));
+        assert_res_error(res, 400, "Snapshot");
+    }
+
+    #[test]
+    fn open_snapshots_should_be_limited() {
+        let snapshots = Snapshots::default();
+        let store = MemTable::new();
+
+        for _ in 0..MAX_SNAPSHOTS {
+            let res = snapshots.execute(CommandRequest::new_hsnapshot(), &store);
+            assert_eq!(res.unwrap().status, 200);
+        }
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS);
+
+        let res = snapshots.execute(CommandRequest::new_hsnapshot(), &store);
+        assert_res_error(res.unwrap(), 400, "Too many open snapshots");
+
+        // 与快照无关的命令原样返回
+        let cmd = CommandRequest::new_hget("t1", "k1").with_timeout(Duration::from_secs(60));
+        assert_eq!(snapshots.execute(cmd.clone(), &store), Err(cmd));
+    }
+}
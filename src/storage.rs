@@ -1,9 +1,33 @@
+mod bitcask;
+mod cached;
+mod compression;
+mod encrypted;
+mod lsm;
 mod memory;
+mod redb_db;
+mod sharded;
 mod sled_db;
+mod snapshot;
+mod versioned;
 
-use crate::{KVError, KvPair, Value};
-pub use memory::MemTable;
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::{KVError, KvPair, Value, Version};
+pub use bitcask::{Bitcask, BitcaskConfig};
+pub use cached::{CachedStore, WritePolicy};
+pub(crate) use compression::Compressor;
+pub use compression::{Codec, CompressionConfig, CompressionStats};
+pub use encrypted::{EncryptedStore, Keyring};
+pub use lsm::{LsmConfig, LsmTree};
+pub use memory::{EvictionConfig, EvictionPolicy, MemTable};
+pub use redb_db::{RedbBatch, RedbDb};
+pub use sharded::ShardedTable;
 pub use sled_db::SledDb;
+pub(crate) use snapshot::Capture;
+pub use snapshot::{MemSnapshot, Snapshot};
+pub use versioned::VersionedStore;
 
 /// 对存储的抽象，我们不关心数据存在哪儿，但需要定义外界如何和存储打交道
 pub trait Storage {
@@ -20,6 +44,198 @@ pub trait Storage {
     /// 遍历 HashTable，返回 kv pair 的 Iterator
     fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError>;
     // fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = KvPair>>, KVError>;
+    /// 返回所有至少有一个 key 的 table，按名字排序
+    fn tables(&self) -> Result<Vec<String>, KVError>;
+    /// 原子地执行一组写操作，要么全部生效，要么全部不生效；按顺序返回每个操作之前的 value
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError>;
+
+    /// 获取一组 key 的 value，结果与 keys 一一对应
+    fn get_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KVError> {
+        keys.iter().map(|key| self.get(table, key)).collect()
+    }
+    /// 设置一组 kv pair，返回它们旧的 value
+    fn set_many(&self, table: &str, pairs: Vec<KvPair>) -> Result<Vec<Option<Value>>, KVError> {
+        let ops = pairs
+            .into_iter()
+            .map(|pair| WriteOp::set(table, pair.key, pair.value.unwrap_or_default()))
+            .collect();
+
+        self.write_batch(ops)
+    }
+    /// 删除一组 key，返回它们之前的 value
+    fn del_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KVError> {
+        self.write_batch(
+            keys.into_iter()
+                .map(|key| WriteOp::del(table, key))
+                .collect(),
+        )
+    }
+    /// 按 key 的顺序返回 [start, end) 范围内最多 limit 个 kv pair，end 为 None 表示没有上界，limit 为 0 表示不限数量
+    ///
+    /// 缺省实现遍历整个 table 后排序，适用于 MemTable 这类没有顺序的存储；有序的存储应该提供自己的实现。
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let mut pairs: Vec<_> = self
+            .get_iter(table)?
+            .filter(|p| p.key.as_str() >= start && end.is_none_or(|end| p.key.as_str() < end))
+            .collect();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(take_ordered(pairs.into_iter(), limit, reverse))
+    }
+    /// 按 key 的顺序返回以 prefix 开头的 kv pair，缺省实现和 get_range 一样需要遍历整个 table
+    fn get_prefix(&self, table: &str, prefix: &str) -> Result<Vec<KvPair>, KVError> {
+        let mut pairs: Vec<_> = self
+            .get_iter(table)?
+            .filter(|p| p.key.starts_with(prefix))
+            .collect();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(pairs)
+    }
+    /// 查看一组 key 是否存在
+    fn contains_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<bool>, KVError> {
+        keys.iter().map(|key| self.contains(table, key)).collect()
+    }
+    /// 返回当前时间点的只读快照
+    ///
+    /// 缺省实现逐个 table 复制数据，期间的并发写入可能只有一部分出现在快照中；
+    /// 需要一致性的存储应该在自己的锁或读事务中创建快照。
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        let tables = self
+            .tables()?
+            .into_iter()
+            .map(|t| self.get_all(&t).map(|pairs| (t, pairs)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Arc::new(MemSnapshot::new(tables)))
+    }
+    /// key 保存的历史版本，从新到旧，不保存历史版本的存储返回错误
+    fn history(&self, _table: &str, _key: &str) -> Result<Vec<Version>, KVError> {
+        Err(KVError::InvalidCommand(
+            "Versions are not supported by this storage".into(),
+        ))
+    }
+    /// 获取 key 的某个历史版本，已经被淘汰的版本返回 None
+    fn get_version(
+        &self,
+        table: &str,
+        key: &str,
+        version: u64,
+    ) -> Result<Option<Version>, KVError> {
+        Ok(self
+            .history(table, key)?
+            .into_iter()
+            .find(|v| v.version == version))
+    }
+    /// 获取 key 在 as_of（unix 时间戳，毫秒）时的 value，当时不存在或者对应版本已经被淘汰时返回 None
+    fn get_as_of(&self, table: &str, key: &str, as_of: u64) -> Result<Option<Value>, KVError> {
+        Ok(self
+            .history(table, key)?
+            .into_iter()
+            .find(|v| v.timestamp <= as_of)
+            .filter(|v| !v.deleted)
+            .and_then(|v| v.value))
+    }
+    /// 删除名字以 prefix 开头的所有 table，包括存储为它们保存的附属数据（比如历史版本），返回删除的 key 的数量
+    fn drop_tables(&self, prefix: &str) -> Result<usize, KVError> {
+        let mut deleted = 0;
+        for table in self.tables()? {
+            if table.starts_with(prefix) {
+                let keys: Vec<_> = self.get_iter(&table)?.map(|p| p.key).collect();
+                deleted += keys.len();
+                self.del_many(&table, keys)?;
+            }
+        }
+
+        Ok(deleted)
+    }
+    /// 取走因内存限制而被淘汰的数据，不支持淘汰的存储返回空
+    fn drain_evicted(&self) -> Vec<Evicted> {
+        Vec::new()
+    }
+}
+
+/// 从有序的数据中按方向取出最多 limit 个，limit 为 0 表示不限数量
+pub(crate) fn take_ordered<T>(
+    pairs: impl DoubleEndedIterator<Item = T>,
+    limit: usize,
+    reverse: bool,
+) -> Vec<T> {
+    let limit = if limit == 0 { usize::MAX } else { limit };
+
+    if reverse {
+        pairs.rev().take(limit).collect()
+    } else {
+        pairs.take(limit).collect()
+    }
+}
+
+/// write_batch 中的一个写操作
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteOp {
+    Set {
+        table: String,
+        key: String,
+        value: Value,
+    },
+    Del {
+        table: String,
+        key: String,
+    },
+}
+
+impl WriteOp {
+    pub fn set(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self::Set {
+            table: table.into(),
+            key: key.into(),
+            value,
+        }
+    }
+
+    pub fn del(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self::Del {
+            table: table.into(),
+            key: key.into(),
+        }
+    }
+
+    pub fn table(&self) -> &str {
+        match self {
+            Self::Set { table, .. } | Self::Del { table, .. } => table,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Set { key, .. } | Self::Del { key, .. } => key,
+        }
+    }
+}
+
+/// 因内存限制而被淘汰的数据
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evicted {
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+}
+
+impl Evicted {
+    pub fn new(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            table: table.into(),
+            key: key.into(),
+            value,
+        }
+    }
 }
 
 /// 提供 Storage iterator，这样 trait 实现者只需将它们的 iterator 提供给 StorageIter，并保证 next() 的传出类型实现了 Into<KvPair> 即可
@@ -45,6 +261,16 @@ where
     }
 }
 
+/// get_iter 返回的迭代器不能传出错误，读取或解码失败的 entry 记录日志后跳过；需要完整的数据时用 get_all
+pub(crate) fn skip_errors(
+    iter: impl Iterator<Item = Result<KvPair, KVError>>,
+) -> impl Iterator<Item = KvPair> {
+    iter.filter_map(|v| {
+        v.inspect_err(|e| warn!("Skipped an unreadable entry: {}", e))
+            .ok()
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,6 +293,35 @@ mod tests {
         test_get_iter(store);
     }
 
+    #[test]
+    fn memtable_write_batch_should_work() {
+        let store = MemTable::new();
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn memtable_range_should_work() {
+        let store = MemTable::new();
+        test_get_range(store);
+    }
+
+    #[test]
+    fn memtable_tables_should_work() {
+        let store = MemTable::new();
+        test_tables(store);
+    }
+
+    #[test]
+    fn memtable_snapshot_should_work() {
+        let store = MemTable::new();
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn memtable_snapshot_should_not_block_writes() {
+        test_snapshot_during_writes(Arc::new(MemTable::new()));
+    }
+
     #[test]
     fn sled_db_basic_interface_should_work() {
         let dir = tempfile::tempdir().unwrap();
@@ -88,6 +343,458 @@ mod tests {
         test_get_iter(store);
     }
 
+    #[test]
+    fn sled_db_write_batch_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn sled_db_range_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_get_range(store);
+    }
+
+    #[test]
+    fn sled_db_tables_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_tables(store);
+    }
+
+    #[test]
+    fn sled_db_snapshot_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn sled_db_snapshot_should_not_block_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        test_snapshot_during_writes(Arc::new(SledDb::new(dir)));
+    }
+
+    #[test]
+    fn redb_db_basic_interface_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_basic_interface(store);
+    }
+
+    #[test]
+    fn redb_db_get_all_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_get_all(store);
+    }
+
+    #[test]
+    fn redb_db_iter_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn redb_db_write_batch_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn redb_db_range_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_get_range(store);
+    }
+
+    #[test]
+    fn redb_db_tables_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_tables(store);
+    }
+
+    #[test]
+    fn redb_db_snapshot_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn encrypted_store_basic_interface_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedStore::new(SledDb::new(dir), Keyring::new(1, [7; 32]));
+        test_basic_interface(store);
+    }
+
+    #[test]
+    fn encrypted_store_get_all_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedStore::new(SledDb::new(dir), Keyring::new(1, [7; 32]));
+        test_get_all(store);
+    }
+
+    #[test]
+    fn encrypted_store_iter_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedStore::new(SledDb::new(dir), Keyring::new(1, [7; 32]));
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn encrypted_store_write_batch_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedStore::new(SledDb::new(dir), Keyring::new(1, [7; 32]));
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn encrypted_store_range_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedStore::new(SledDb::new(dir), Keyring::new(1, [7; 32]));
+        test_get_range(store);
+    }
+
+    #[test]
+    fn encrypted_store_snapshot_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedStore::new(SledDb::new(dir), Keyring::new(1, [7; 32]));
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn sled_db_compression_should_work() {
+        for codec in [Codec::Lz4, Codec::Zstd(0)] {
+            let dir = tempfile::tempdir().unwrap();
+            let store = SledDb::with_compression(&dir, compress_all(codec));
+            test_basic_interface(store);
+
+            let dir = tempfile::tempdir().unwrap();
+            let store = SledDb::with_compression(&dir, compress_all(codec));
+            test_get_range(store);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        test_compression(|config| SledDb::with_compression(dir.path(), config));
+    }
+
+    #[test]
+    fn redb_db_compression_should_work() {
+        for codec in [Codec::Lz4, Codec::Zstd(0)] {
+            let dir = tempfile::tempdir().unwrap();
+            let store = RedbDb::with_compression(dir.path().join("kv.redb"), compress_all(codec));
+            test_basic_interface(store);
+
+            let dir = tempfile::tempdir().unwrap();
+            let store = RedbDb::with_compression(dir.path().join("kv.redb"), compress_all(codec));
+            test_snapshot(store);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        test_compression(|config| RedbDb::with_compression(dir.path().join("kv.redb"), config));
+    }
+
+    #[test]
+    fn bitcask_basic_interface_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Bitcask::new(dir.path());
+        test_basic_interface(store);
+    }
+
+    #[test]
+    fn bitcask_get_all_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Bitcask::new(dir.path());
+        test_get_all(store);
+    }
+
+    #[test]
+    fn bitcask_iter_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Bitcask::new(dir.path());
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn bitcask_write_batch_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Bitcask::new(dir.path());
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn bitcask_range_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Bitcask::new(dir.path());
+        test_get_range(store);
+    }
+
+    #[test]
+    fn bitcask_tables_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Bitcask::new(dir.path());
+        test_tables(store);
+    }
+
+    #[test]
+    fn bitcask_snapshot_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Bitcask::new(dir.path());
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn lsm_tree_basic_interface_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LsmTree::new(dir.path());
+        test_basic_interface(store);
+    }
+
+    #[test]
+    fn lsm_tree_get_all_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LsmTree::new(dir.path());
+        test_get_all(store);
+    }
+
+    #[test]
+    fn lsm_tree_iter_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LsmTree::new(dir.path());
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn lsm_tree_write_batch_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LsmTree::new(dir.path());
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn lsm_tree_range_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LsmTree::new(dir.path());
+        test_get_range(store);
+    }
+
+    #[test]
+    fn lsm_tree_tables_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LsmTree::new(dir.path());
+        test_tables(store);
+    }
+
+    #[test]
+    fn lsm_tree_snapshot_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LsmTree::new(dir.path());
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn rich_values_should_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+
+        test_rich_values(MemTable::new());
+        test_rich_values(SledDb::new(dir.path().join("sled")));
+        test_rich_values(RedbDb::new(dir.path().join("kv.redb")));
+        test_rich_values(Bitcask::new(dir.path().join("bitcask")));
+        test_rich_values(LsmTree::new(dir.path().join("lsm")));
+    }
+
+    #[test]
+    fn sharded_table_basic_interface_should_work() {
+        let store = ShardedTable::new(4);
+        test_basic_interface(store);
+    }
+
+    #[test]
+    fn sharded_table_get_all_should_work() {
+        let store = ShardedTable::new(4);
+        test_get_all(store);
+    }
+
+    #[test]
+    fn sharded_table_iter_should_work() {
+        let store = ShardedTable::new(4);
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn sharded_table_write_batch_should_work() {
+        let store = ShardedTable::new(4);
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn sharded_table_range_should_work() {
+        let store = ShardedTable::new(4);
+        test_get_range(store);
+    }
+
+    #[test]
+    fn sharded_table_tables_should_work() {
+        let store = ShardedTable::new(4);
+        test_tables(store);
+    }
+
+    #[test]
+    fn sharded_table_snapshot_should_work() {
+        let store = ShardedTable::new(4);
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn versioned_store_basic_interface_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        test_basic_interface(store);
+    }
+
+    #[test]
+    fn versioned_store_get_all_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        test_get_all(store);
+    }
+
+    #[test]
+    fn versioned_store_iter_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn versioned_store_write_batch_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        test_write_batch(store);
+    }
+
+    #[test]
+    fn versioned_store_range_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        test_get_range(store);
+    }
+
+    #[test]
+    fn versioned_store_tables_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        test_tables(store);
+    }
+
+    #[test]
+    fn versioned_store_snapshot_should_work() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        test_snapshot(store);
+    }
+
+    #[test]
+    fn cached_store_basic_interface_should_work() {
+        for policy in cached_policies() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CachedStore::new(MemTable::new(), SledDb::new(dir), policy);
+            test_basic_interface(store);
+        }
+    }
+
+    #[test]
+    fn cached_store_get_all_should_work() {
+        for policy in cached_policies() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CachedStore::new(MemTable::new(), SledDb::new(dir), policy);
+            test_get_all(store);
+        }
+    }
+
+    #[test]
+    fn cached_store_iter_should_work() {
+        for policy in cached_policies() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CachedStore::new(MemTable::new(), SledDb::new(dir), policy);
+            test_get_iter(store);
+        }
+    }
+
+    #[test]
+    fn cached_store_write_batch_should_work() {
+        for policy in cached_policies() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CachedStore::new(MemTable::new(), SledDb::new(dir), policy);
+            test_write_batch(store);
+        }
+    }
+
+    #[test]
+    fn cached_store_range_should_work() {
+        for policy in cached_policies() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CachedStore::new(MemTable::new(), SledDb::new(dir), policy);
+            test_get_range(store);
+        }
+    }
+
+    #[test]
+    fn cached_store_tables_should_work() {
+        for policy in cached_policies() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CachedStore::new(MemTable::new(), SledDb::new(dir), policy);
+            test_tables(store);
+        }
+    }
+
+    #[test]
+    fn cached_store_snapshot_should_work() {
+        for policy in cached_policies() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CachedStore::new(MemTable::new(), SledDb::new(dir), policy);
+            test_snapshot(store);
+        }
+    }
+
+    // threshold 为 0，所有能变小的 value 都会被压缩
+    fn compress_all(codec: Codec) -> CompressionConfig {
+        CompressionConfig {
+            threshold: 0,
+            ..CompressionConfig::new(codec)
+        }
+    }
+
+    // open 每次打开同一个数据库
+    fn test_compression<S: Storage>(open: impl Fn(CompressionConfig) -> S) {
+        let large: Value = "compress me ".repeat(100).into();
+
+        // 没有开启压缩时写入的数据
+        let store = open(CompressionConfig::default());
+        store.set("t1", "old".into(), large.clone()).unwrap();
+        drop(store);
+
+        let mut config = CompressionConfig::new(Codec::Zstd(0));
+        config.tables.insert("t2".into(), None);
+        let store = open(config);
+        assert_eq!(store.get("t1", "old"), Ok(Some(large.clone())));
+
+        store.set("t1", "new".into(), large.clone()).unwrap();
+        store.set("t1", "small".into(), "v".into()).unwrap();
+        store.set("t2", "k1".into(), large.clone()).unwrap();
+        assert_eq!(store.get("t1", "new"), Ok(Some(large.clone())));
+        assert_eq!(store.get("t2", "k1"), Ok(Some(large.clone())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 3);
+    }
+
+    fn cached_policies() -> [WritePolicy; 2] {
+        [
+            WritePolicy::WriteThrough,
+            WritePolicy::WriteBehind {
+                flush_interval: std::time::Duration::from_millis(5),
+            },
+        ]
+    }
+
     fn test_basic_interface(store: impl Storage) {
         // 第一次 set 会创建 table，插入 key 并返回 None（之前没值）
         let v = store.set("t1", "hello".into(), "world".into());
@@ -155,4 +862,218 @@ mod tests {
             ]
         );
     }
+
+    fn test_write_batch(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        // batch 可以跨 table，靠前的操作对后面的操作可见
+        let ops = vec![
+            WriteOp::set("t1", "k1", "v2".into()),
+            WriteOp::set("t2", "k2", "v3".into()),
+            WriteOp::del("t1", "k1"),
+            WriteOp::del("t1", "nope"),
+            WriteOp::set("t2", "k2", "v4".into()),
+        ];
+        let old = store.write_batch(ops);
+
+        assert_eq!(
+            old,
+            Ok(vec![
+                Some("v1".into()),
+                None,
+                Some("v2".into()),
+                None,
+                Some("v3".into()),
+            ])
+        );
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t2", "k2"), Ok(Some("v4".into())));
+        assert_eq!(store.write_batch(vec![]), Ok(vec![]));
+    }
+
+    fn test_get_range(store: impl Storage) {
+        for key in ["b", "a:1", "a:2", "c", "a", "d"] {
+            store.set("t1", key.into(), key.into()).unwrap();
+        }
+        // 相邻的 table 不应该出现在结果中
+        store.set("t10", "b".into(), "x".into()).unwrap();
+        store.set("t0", "z".into(), "x".into()).unwrap();
+
+        let keys = |pairs: Vec<KvPair>| pairs.into_iter().map(|p| p.key).collect::<Vec<_>>();
+
+        let all = store.get_range("t1", "", None, 0, false).unwrap();
+        assert_eq!(keys(all), ["a", "a:1", "a:2", "b", "c", "d"]);
+
+        let range = store.get_range("t1", "a:2", Some("d"), 0, false).unwrap();
+        assert_eq!(range[0], KvPair::new("a:2", "a:2".into()));
+        assert_eq!(keys(range), ["a:2", "b", "c"]);
+
+        let range = store.get_range("t1", "a:1", Some("d"), 2, false).unwrap();
+        assert_eq!(keys(range), ["a:1", "a:2"]);
+
+        let range = store.get_range("t1", "a:1", None, 2, true).unwrap();
+        assert_eq!(keys(range), ["d", "c"]);
+
+        // 空区间和不存在的 table 返回空结果
+        assert!(
+            store
+                .get_range("t1", "c", Some("c"), 0, false)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            store
+                .get_range("t1", "d", Some("a"), 0, false)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            store
+                .get_range("t2", "", None, 0, false)
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(
+            keys(store.get_prefix("t1", "a").unwrap()),
+            ["a", "a:1", "a:2"]
+        );
+        assert_eq!(keys(store.get_prefix("t1", "a:").unwrap()), ["a:1", "a:2"]);
+        assert_eq!(keys(store.get_prefix("t1", "").unwrap()).len(), 6);
+        assert!(store.get_prefix("t1", "e").unwrap().is_empty());
+        assert!(store.get_prefix("t2", "a").unwrap().is_empty());
+    }
+
+    fn test_tables(store: impl Storage) {
+        assert!(store.tables().unwrap().is_empty());
+
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        store.set("t3", "k1".into(), "v1".into()).unwrap();
+        // 读取不存在的 table 不会让它出现在结果中
+        store.get("t4", "k1").unwrap();
+
+        assert_eq!(
+            store.tables(),
+            Ok(vec!["t1".into(), "t2".into(), "t3".into()])
+        );
+
+        // 删除所有 key 后 table 不再出现
+        store.del("t3", "k1").unwrap();
+        store.del("t1", "k1").unwrap();
+        assert_eq!(store.tables(), Ok(vec!["t1".into(), "t2".into()]));
+    }
+
+    fn test_rich_values(store: impl Storage) {
+        use std::{collections::BTreeMap, time::SystemTime};
+
+        let nested = BTreeMap::from([
+            ("tags".to_string(), vec!["a".into(), "b".into()].into()),
+            ("count".to_string(), u64::MAX.into()),
+        ]);
+        let values: Vec<Value> = vec![
+            vec![1.into(), Value::from(nested.clone()), Value::default()].into(),
+            nested.into(),
+            SystemTime::now().into(),
+            u64::MAX.into(),
+            "-12.345".parse::<crate::Decimal>().unwrap().into(),
+        ];
+
+        let pairs: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| KvPair::new(format!("k{}", i), v.clone()))
+            .collect();
+        store.set_many("t1", pairs.clone()).unwrap();
+
+        for p in &pairs {
+            assert_eq!(store.get("t1", &p.key).unwrap(), p.value);
+        }
+        assert_eq!(store.get_range("t1", "", None, 0, false), Ok(pairs));
+    }
+
+    fn test_snapshot(store: impl Storage) {
+        for i in 0..5 {
+            store.set("t1", format!("k{}", i), i.into()).unwrap();
+        }
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+
+        let snapshot = store.snapshot().unwrap();
+
+        // 之后的写入对快照不可见
+        store.set("t1", "k0".into(), "new".into()).unwrap();
+        store.del("t1", "k1").unwrap();
+        store.set("t1", "k9".into(), 9.into()).unwrap();
+        store.del("t2", "k1").unwrap();
+        store.set("t3", "k1".into(), "v1".into()).unwrap();
+
+        assert_eq!(snapshot.get("t1", "k0"), Ok(Some(0.into())));
+        assert_eq!(snapshot.get("t1", "k1"), Ok(Some(1.into())));
+        assert_eq!(snapshot.get("t1", "k9"), Ok(None));
+        assert_eq!(snapshot.get("t3", "k1"), Ok(None));
+        assert_eq!(snapshot.tables(), Ok(vec!["t1".into(), "t2".into()]));
+
+        let keys = |pairs: Vec<KvPair>| pairs.into_iter().map(|p| p.key).collect::<Vec<_>>();
+        assert_eq!(
+            keys(snapshot.scan("t1", "", None, 0).unwrap()),
+            ["k0", "k1", "k2", "k3", "k4"]
+        );
+        assert_eq!(
+            keys(snapshot.scan("t1", "k1", Some("k4"), 2).unwrap()),
+            ["k1", "k2"]
+        );
+        assert!(snapshot.scan("t1", "k4", Some("k1"), 0).unwrap().is_empty());
+
+        // 存储本身读到的是最新的数据
+        assert_eq!(store.get("t1", "k0"), Ok(Some("new".into())));
+        assert_eq!(store.tables(), Ok(vec!["t1".into(), "t3".into()]));
+    }
+
+    // 一边在 batch 中转账一边创建快照，每个快照中的总额都不变
+    fn test_snapshot_during_writes(store: Arc<impl Storage + Send + Sync + 'static>) {
+        const ACCOUNTS: i64 = 200;
+        for i in 0..ACCOUNTS {
+            store.set("bank", format!("a{:03}", i), 100.into()).unwrap();
+        }
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let writer = {
+            let (store, done) = (store.clone(), done.clone());
+            std::thread::spawn(move || {
+                let mut i = 0;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let (from, to) = (
+                        format!("a{:03}", i % ACCOUNTS),
+                        format!("a{:03}", (i * 7 + 3) % ACCOUNTS),
+                    );
+                    let balance =
+                        |k: &str| i64::try_from(store.get("bank", k).unwrap().unwrap()).unwrap();
+                    let (a, b) = (balance(&from), balance(&to));
+                    if from != to {
+                        store
+                            .write_batch(vec![
+                                WriteOp::set("bank", from, (a - 1).into()),
+                                WriteOp::set("bank", to, (b + 1).into()),
+                            ])
+                            .unwrap();
+                    }
+                    i += 1;
+                }
+            })
+        };
+
+        for _ in 0..20 {
+            let snapshot = store.snapshot().unwrap();
+            let pairs = snapshot.scan("bank", "", None, 0).unwrap();
+            let total: i64 = pairs
+                .into_iter()
+                .map(|p| i64::try_from(p.value.unwrap()).unwrap())
+                .sum();
+            assert_eq!(total, 100 * ACCOUNTS);
+        }
+
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        writer.join().unwrap();
+    }
 }
@@ -0,0 +1,981 @@
+pub(super) mod record;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use record::{Hint, Record};
+use tracing::warn;
+
+use crate::{KVError, KvPair, MemSnapshot, Snapshot, Storage, StorageIter, Value, WriteOp};
+
+const DATA_EXT: &str = "data";
+const HINT_EXT: &str = "hint";
+
+/// Bitcask 的配置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitcaskConfig {
+    /// 当前写入的数据文件超过这个大小后，切换到新的数据文件
+    pub max_segment_size: u64,
+    /// 每次写入后是否 fsync
+    pub sync: bool,
+    /// 后台 merge 的检查间隔，None 表示不启动后台 merge
+    pub merge_interval: Option<Duration>,
+    /// 只读数据文件中过期数据的比例超过这个值时，后台 merge 才会执行
+    pub merge_ratio: f64,
+}
+
+impl Default for BitcaskConfig {
+    fn default() -> Self {
+        Self {
+            max_segment_size: 64 * 1024 * 1024,
+            sync: false,
+            merge_interval: None,
+            merge_ratio: 0.5,
+        }
+    }
+}
+
+/// keydir 中记录的某个 key 最新的 record 所在位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    segment: u64,
+    offset: u64,
+    len: u32,
+    seq: u64,
+}
+
+/// 当前正在追加写入的数据文件
+struct Active {
+    id: u64,
+    file: File,
+    size: u64,
+    /// 这个数据文件中所有 record 的 hint，切换数据文件时写入 hint 文件
+    hints: Vec<Hint>,
+}
+
+struct Inner {
+    /// table -> (key -> Entry)
+    keydir: HashMap<String, HashMap<String, Entry>>,
+    /// 所有数据文件（包括 active）的读句柄
+    readers: BTreeMap<u64, File>,
+    /// 每个数据文件的大小
+    sizes: HashMap<u64, u64>,
+    /// 每个数据文件中已经过期的字节数
+    stale: HashMap<u64, u64>,
+    active: Active,
+    next_id: u64,
+    next_seq: u64,
+}
+
+struct Shared {
+    dir: PathBuf,
+    config: BitcaskConfig,
+    inner: Mutex<Inner>,
+    /// 保证同一时间只有一个 merge 在执行
+    merging: Mutex<()>,
+}
+
+/// Bitcask 风格的日志结构存储，实现了 Storage trait
+///
+/// 所有写入都追加到数据文件末尾，内存中的 keydir 记录每个 key 最新 record 的位置，读取时只需一次磁盘读。
+/// 每条 record 都带有递增的 seq，启动时按 seq 重建 keydir，所以 merge 产生的新文件和旧文件的顺序无关紧要。
+/// 只读数据文件都有对应的 hint 文件，启动时无需读取 value。
+pub struct Bitcask {
+    shared: Arc<Shared>,
+    stop: Option<Sender<()>>,
+    merger: Option<JoinHandle<()>>,
+}
+
+impl Bitcask {
+    /// 使用缺省配置打开 path 目录下的 Bitcask，目录不存在则创建
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::open(path, BitcaskConfig::default()).unwrap()
+    }
+
+    /// 打开 path 目录下的 Bitcask，重建 keydir；数据文件末尾不完整或者校验失败的 record 会被截掉
+    pub fn open(path: impl AsRef<Path>, config: BitcaskConfig) -> Result<Self, KVError> {
+        let dir = path.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let inner = Inner::load(&dir)?;
+        let shared = Arc::new(Shared {
+            dir,
+            config,
+            inner: Mutex::new(inner),
+            merging: Mutex::new(()),
+        });
+
+        let (stop, merger) = match config.merge_interval {
+            Some(interval) => {
+                let (tx, rx) = mpsc::channel::<()>();
+                let shared = shared.clone();
+                let handle = thread::spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                        if shared.needs_merge()
+                            && let Err(e) = shared.merge(false)
+                        {
+                            warn!("Failed to merge bitcask segments: {}", e);
+                        }
+                    }
+                });
+
+                (Some(tx), Some(handle))
+            }
+            None => (None, None),
+        };
+
+        Ok(Self {
+            shared,
+            stop,
+            merger,
+        })
+    }
+
+    /// 把当前数据文件切换为只读，然后把所有只读数据文件中仍然有效的 record 重写到新的数据文件，删除旧文件
+    pub fn merge(&self) -> Result<(), KVError> {
+        self.shared.merge(true)
+    }
+
+    /// 数据文件的数量
+    pub fn segments(&self) -> usize {
+        self.shared.lock().readers.len()
+    }
+
+    /// 所有数据文件中已经过期的字节数
+    pub fn stale_bytes(&self) -> u64 {
+        self.shared.lock().stale.values().sum()
+    }
+}
+
+impl Drop for Bitcask {
+    fn drop(&mut self) {
+        self.stop.take();
+
+        if let Some(handle) = self.merger.take() {
+            let _ = handle.join();
+        }
+
+        if let Err(e) = self.shared.lock().active.file.sync_all() {
+            warn!("Failed to sync bitcask segment: {}", e);
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 只读数据文件中过期数据的比例是否超过 merge_ratio
+    fn needs_merge(&self) -> bool {
+        let inner = self.lock();
+        let (total, stale) = inner
+            .sizes
+            .iter()
+            .filter(|(id, _)| **id != inner.active.id)
+            .fold((0, 0), |(total, stale), (id, size)| {
+                (
+                    total + size,
+                    stale + inner.stale.get(id).copied().unwrap_or(0),
+                )
+            });
+
+        stale > 0 && stale as f64 >= total as f64 * self.config.merge_ratio
+    }
+
+    fn merge(&self, rotate: bool) -> Result<(), KVError> {
+        let _merging = self.merging.lock().unwrap_or_else(|e| e.into_inner());
+
+        // 1. 找出要 merge 的只读数据文件，以及其中仍然有效的 entry
+        let (ids, live) = {
+            let mut inner = self.lock();
+
+            if rotate && inner.active.size > 0 {
+                inner.rotate(&self.dir)?;
+            }
+
+            let active = inner.active.id;
+            let ids: Vec<u64> = inner
+                .readers
+                .keys()
+                .copied()
+                .filter(|id| *id != active)
+                .collect();
+            let live: Vec<(String, String, Entry)> = inner
+                .keydir
+                .iter()
+                .flat_map(|(t, keys)| keys.iter().map(move |(k, e)| (t.clone(), k.clone(), *e)))
+                .filter(|(_, _, e)| e.segment != active)
+                .collect();
+
+            (ids, live)
+        };
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        // 2. 不持有锁，把有效的 record 复制到新的数据文件
+        let mut sources = HashMap::new();
+
+        for id in &ids {
+            sources.insert(*id, File::open(segment_path(&self.dir, *id, DATA_EXT))?);
+        }
+
+        let mut writer: Option<Active> = None;
+        let mut outputs = Vec::new();
+        let mut moved = Vec::with_capacity(live.len());
+
+        for (table, key, old) in live {
+            // batch 标记只在恢复时有用，复制时清除
+            let mut record =
+                Record::decode(&read_at(&sources[&old.segment], old.offset, old.len)?)?;
+            record.chained = false;
+            let data = record.encode();
+
+            if writer
+                .as_ref()
+                .is_some_and(|w| w.size + data.len() as u64 > self.config.max_segment_size)
+            {
+                outputs.push(finish_segment(&self.dir, writer.take().unwrap())?);
+            }
+
+            let w = match writer.as_mut() {
+                Some(w) => w,
+                None => {
+                    let id = self.lock().alloc_id();
+                    writer.insert(Active::create(&self.dir, id)?)
+                }
+            };
+
+            let new = Entry {
+                segment: w.id,
+                offset: w.size,
+                len: old.len,
+                seq: old.seq,
+            };
+
+            w.file.write_all(&data)?;
+            w.size += data.len() as u64;
+            w.hints.push(Hint {
+                seq: old.seq,
+                table: table.clone(),
+                key: key.clone(),
+                offset: new.offset,
+                len: new.len,
+            });
+            moved.push((table, key, old, new));
+        }
+
+        if let Some(w) = writer {
+            outputs.push(finish_segment(&self.dir, w)?);
+        }
+
+        // 3. 更新 keydir，只替换 merge 期间没有被修改过的 entry，然后删除旧文件
+        let mut inner = self.lock();
+
+        for (id, size) in outputs {
+            inner
+                .readers
+                .insert(id, File::open(segment_path(&self.dir, id, DATA_EXT))?);
+            inner.sizes.insert(id, size);
+        }
+
+        for (table, key, old, new) in moved {
+            match inner.keydir.get_mut(&table).and_then(|t| t.get_mut(&key)) {
+                Some(e) if *e == old => *e = new,
+                _ => *inner.stale.entry(new.segment).or_default() += new.len as u64,
+            }
+        }
+
+        // 按 id 从小到大删除，即使中途崩溃，留下的也只会是较新的 record
+        for id in ids {
+            inner.readers.remove(&id);
+            inner.sizes.remove(&id);
+            inner.stale.remove(&id);
+            fs::remove_file(segment_path(&self.dir, id, DATA_EXT))?;
+
+            let hint = segment_path(&self.dir, id, HINT_EXT);
+            if hint.exists() {
+                fs::remove_file(hint)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Inner {
+    // 从目录中的数据文件和 hint 文件重建 keydir
+    fn load(dir: &Path) -> Result<Self, KVError> {
+        let mut ids: Vec<u64> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let path = e.path();
+                (path.extension()? == DATA_EXT)
+                    .then(|| path.file_stem()?.to_str()?.parse().ok())
+                    .flatten()
+            })
+            .collect();
+        ids.sort_unstable();
+
+        let mut replay = Replay::default();
+        let mut readers = BTreeMap::new();
+        let mut sizes = HashMap::new();
+        let mut last_hints = None;
+
+        for (i, id) in ids.iter().copied().enumerate() {
+            let data_path = segment_path(dir, id, DATA_EXT);
+            let hint_path = segment_path(dir, id, HINT_EXT);
+            let is_last = i + 1 == ids.len();
+
+            let hints = match hint_path.exists() {
+                true => Hint::decode_all(&fs::read(&hint_path)?)?,
+                false => {
+                    let hints = scan_segment(&data_path)?;
+
+                    // 非最后一个数据文件不会再被写入，为它生成 hint 文件，下次启动时更快
+                    if !is_last {
+                        write_hints(dir, id, &hints)?;
+                    }
+
+                    hints
+                }
+            };
+
+            for h in &hints {
+                replay.apply(id, h);
+            }
+
+            let file = File::open(&data_path)?;
+            sizes.insert(id, file.metadata()?.len());
+            readers.insert(id, file);
+
+            // 最后一个数据文件如果没有 hint 文件，说明它是上次的 active，继续在它后面追加
+            if is_last && !hint_path.exists() {
+                last_hints = Some(hints);
+            }
+        }
+
+        let next_id = ids.last().map_or(0, |id| id + 1);
+        let active = match (ids.last(), last_hints) {
+            (Some(id), Some(hints)) => Active::open(dir, *id, hints)?,
+            _ => {
+                let active = Active::create(dir, next_id)?;
+                readers.insert(next_id, File::open(segment_path(dir, next_id, DATA_EXT))?);
+                sizes.insert(next_id, 0);
+                active
+            }
+        };
+
+        Ok(Self {
+            keydir: replay.keydir,
+            readers,
+            sizes,
+            stale: replay.stale,
+            next_id: active.id + 1,
+            next_seq: replay.max_seq + 1,
+            active,
+        })
+    }
+
+    fn alloc_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    // 把 active 切换为只读（写入 hint 文件），再创建新的 active
+    fn rotate(&mut self, dir: &Path) -> Result<(), KVError> {
+        let id = self.alloc_id();
+        let active = std::mem::replace(&mut self.active, Active::create(dir, id)?);
+
+        finish_segment(dir, active)?;
+        self.readers
+            .insert(id, File::open(segment_path(dir, id, DATA_EXT))?);
+        self.sizes.insert(id, 0);
+
+        Ok(())
+    }
+
+    // 把一组 record 一次性追加到 active，返回它们的位置
+    fn append(
+        &mut self,
+        dir: &Path,
+        config: &BitcaskConfig,
+        records: Vec<Record>,
+    ) -> Result<Vec<Entry>, KVError> {
+        let encoded: Vec<Vec<u8>> = records.iter().map(Record::encode).collect();
+        let total: u64 = encoded.iter().map(|d| d.len() as u64).sum();
+
+        if self.active.size > 0 && self.active.size + total > config.max_segment_size {
+            self.rotate(dir)?;
+        }
+
+        let active = &mut self.active;
+        let mut buf = Vec::with_capacity(total as usize);
+        let mut entries = Vec::with_capacity(records.len());
+        let mut hints = Vec::with_capacity(records.len());
+
+        for (record, data) in records.into_iter().zip(encoded) {
+            let entry = Entry {
+                segment: active.id,
+                offset: active.size + buf.len() as u64,
+                len: data.len() as u32,
+                seq: record.seq,
+            };
+
+            hints.push(Hint {
+                seq: record.seq,
+                table: record.table,
+                key: record.key,
+                offset: entry.offset,
+                len: if record.value.is_some() { entry.len } else { 0 },
+            });
+            entries.push(entry);
+            buf.extend_from_slice(&data);
+        }
+
+        if let Err(e) = active.file.write_all(&buf) {
+            // 去掉写了一半的数据，保证后续 record 的位置正确
+            let _ = active.file.set_len(active.size);
+            return Err(e.into());
+        }
+
+        if config.sync {
+            active.file.sync_data()?;
+        }
+
+        active.size += total;
+        active.hints.append(&mut hints);
+        self.sizes.insert(self.active.id, self.active.size);
+
+        Ok(entries)
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq - 1
+    }
+
+    fn entry(&self, table: &str, key: &str) -> Option<Entry> {
+        self.keydir.get(table).and_then(|t| t.get(key)).copied()
+    }
+
+    // 读取 entry 指向的 value
+    fn read_value(&self, entry: Entry) -> Result<Value, KVError> {
+        let file = self.readers.get(&entry.segment).ok_or_else(|| {
+            KVError::InternalError(format!("Bitcask segment {} is missing", entry.segment))
+        })?;
+        let record = Record::decode(&read_at(file, entry.offset, entry.len)?)?;
+
+        match record.value {
+            Some(v) => v.as_slice().try_into(),
+            None => Err(KVError::InternalError(
+                "Bitcask keydir points to a tombstone".into(),
+            )),
+        }
+    }
+
+    fn mark_stale(&mut self, entry: Entry) {
+        *self.stale.entry(entry.segment).or_default() += entry.len as u64;
+    }
+}
+
+impl Active {
+    fn create(dir: &Path, id: u64) -> Result<Self, KVError> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(segment_path(dir, id, DATA_EXT))?;
+
+        Ok(Self {
+            id,
+            file,
+            size: 0,
+            hints: Vec::new(),
+        })
+    }
+
+    fn open(dir: &Path, id: u64, hints: Vec<Hint>) -> Result<Self, KVError> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(segment_path(dir, id, DATA_EXT))?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            id,
+            file,
+            size,
+            hints,
+        })
+    }
+}
+
+/// 按 seq 重建 keydir：同一个 key 只保留 seq 最大的 record，删除记录会屏蔽 seq 更小的写入
+#[derive(Default)]
+struct Replay {
+    keydir: HashMap<String, HashMap<String, Entry>>,
+    tombstones: HashMap<(String, String), u64>,
+    stale: HashMap<u64, u64>,
+    max_seq: u64,
+}
+
+impl Replay {
+    fn apply(&mut self, segment: u64, h: &Hint) {
+        self.max_seq = self.max_seq.max(h.seq);
+
+        let deleted = self
+            .tombstones
+            .get(&(h.table.clone(), h.key.clone()))
+            .copied()
+            .unwrap_or(0);
+        let table = self.keydir.entry(h.table.clone()).or_default();
+        let current = table.get(&h.key).copied();
+
+        // 已经有更新的记录，这条 record 是过期的
+        if current.is_some_and(|e| e.seq > h.seq) || deleted > h.seq {
+            *self.stale.entry(segment).or_default() += h.len as u64;
+            return;
+        }
+
+        if let Some(e) = current {
+            *self.stale.entry(e.segment).or_default() += e.len as u64;
+        }
+
+        if h.len == 0 {
+            table.remove(&h.key);
+            self.tombstones
+                .insert((h.table.clone(), h.key.clone()), h.seq);
+        } else {
+            table.insert(
+                h.key.clone(),
+                Entry {
+                    segment,
+                    offset: h.offset,
+                    len: h.len,
+                    seq: h.seq,
+                },
+            );
+        }
+    }
+}
+
+impl Storage for Bitcask {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let inner = self.shared.lock();
+
+        inner
+            .entry(table, key)
+            .map(|e| inner.read_value(e))
+            .transpose()
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+        Ok(self
+            .write_batch(vec![WriteOp::set(table, key, value)])?
+            .pop()
+            .flatten())
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        Ok(self.shared.lock().entry(table, key).is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        Ok(self
+            .write_batch(vec![WriteOp::del(table, key)])?
+            .pop()
+            .flatten())
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        let inner = self.shared.lock();
+
+        inner
+            .keydir
+            .get(table)
+            .into_iter()
+            .flatten()
+            .map(|(k, e)| Ok(KvPair::new(k, inner.read_value(*e)?)))
+            .collect()
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+        Ok(StorageIter::new(self.get_all(table)?.into_iter()))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let inner = self.shared.lock();
+        let mut names: Vec<_> = inner
+            .keydir
+            .iter()
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+
+        Ok(names)
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        // 持有锁把所有 value 读出来，数据文件只追加，但 merge 会删除旧文件，不能只保存 keydir
+        let inner = self.shared.lock();
+        let tables = inner
+            .keydir
+            .iter()
+            .map(|(table, keys)| {
+                let pairs = keys
+                    .iter()
+                    .map(|(k, e)| Ok(KvPair::new(k, inner.read_value(*e)?)))
+                    .collect::<Result<Vec<_>, KVError>>()?;
+                Ok((table.clone(), pairs))
+            })
+            .collect::<Result<Vec<_>, KVError>>()?;
+
+        Ok(Arc::new(MemSnapshot::new(tables)))
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        let mut inner = self.shared.lock();
+        let mut staged: HashMap<(String, String), Option<Value>> = HashMap::new();
+        let mut old_values = Vec::with_capacity(ops.len());
+        let mut records = Vec::with_capacity(ops.len());
+
+        // 按顺序计算旧的 value 并编码，全部成功后才写入
+        for op in ops {
+            let k = (op.table().to_owned(), op.key().to_owned());
+            let old = match staged.get(&k) {
+                Some(v) => v.clone(),
+                None => inner
+                    .entry(&k.0, &k.1)
+                    .map(|e| inner.read_value(e))
+                    .transpose()?,
+            };
+
+            match op {
+                WriteOp::Set { table, key, value } => {
+                    staged.insert(k, Some(value.clone()));
+                    let seq = inner.next_seq();
+                    records.push(Record::put(seq, &table, &key, value.try_into()?));
+                }
+                // 删除不存在的 key 不需要写入删除记录
+                WriteOp::Del { table, key } if old.is_some() => {
+                    staged.insert(k, None);
+                    let seq = inner.next_seq();
+                    records.push(Record::tombstone(seq, &table, &key));
+                }
+                WriteOp::Del { .. } => {}
+            }
+
+            old_values.push(old);
+        }
+
+        if let Some((_, init)) = records.split_last_mut() {
+            init.iter_mut().for_each(|r| r.chained = true);
+        }
+
+        let targets: Vec<_> = records
+            .iter()
+            .map(|r| (r.table.clone(), r.key.clone(), r.value.is_some()))
+            .collect();
+        let entries = inner.append(&self.shared.dir, &self.shared.config, records)?;
+
+        for ((table, key, is_put), entry) in targets.into_iter().zip(entries) {
+            if let Some(old) = inner.entry(&table, &key) {
+                inner.mark_stale(old);
+            }
+
+            if is_put {
+                inner.keydir.entry(table).or_default().insert(key, entry);
+            } else {
+                // 删除记录本身在 merge 后也不再需要
+                inner.mark_stale(entry);
+                if let Some(t) = inner.keydir.get_mut(&table) {
+                    t.remove(&key);
+                }
+            }
+        }
+
+        Ok(old_values)
+    }
+}
+
+fn segment_path(dir: &Path, id: u64, ext: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", id, ext))
+}
+
+fn read_at(mut file: &File, offset: u64, len: u32) -> Result<Vec<u8>, KVError> {
+    let mut buf = vec![0; len as usize];
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+
+    Ok(buf)
+}
+
+// 顺序读取整个数据文件，生成 hint
+fn scan_segment(path: &Path) -> Result<Vec<Hint>, KVError> {
+    let mut hints = Vec::new();
+
+    record::replay(path, |r, offset, len| {
+        hints.push(Hint {
+            seq: r.seq,
+            table: r.table,
+            key: r.key,
+            offset,
+            len: if r.value.is_some() { len as u32 } else { 0 },
+        })
+    })?;
+
+    Ok(hints)
+}
+
+// 先写临时文件再 rename，保证 hint 文件要么完整要么不存在
+fn write_hints(dir: &Path, id: u64, hints: &[Hint]) -> Result<(), KVError> {
+    let mut buf = Vec::new();
+    hints.iter().for_each(|h| h.encode(&mut buf));
+
+    let tmp = segment_path(dir, id, "hint.tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+    fs::rename(tmp, segment_path(dir, id, HINT_EXT))?;
+
+    Ok(())
+}
+
+// 数据文件不再写入：fsync 并写入 hint 文件，返回 (id, 文件大小)
+fn finish_segment(dir: &Path, active: Active) -> Result<(u64, u64), KVError> {
+    active.file.sync_all()?;
+    write_hints(dir, active.id, &active.hints)?;
+
+    Ok((active.id, active.size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_segments() -> BitcaskConfig {
+        BitcaskConfig {
+            max_segment_size: 256,
+            ..Default::default()
+        }
+    }
+
+    fn data_files(dir: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().unwrap() == DATA_EXT)
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn data_should_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = Bitcask::open(dir.path(), small_segments()).unwrap();
+        for i in 0..50 {
+            store
+                .set("t1", format!("k{}", i), (i as i64).into())
+                .unwrap();
+        }
+        store.del("t1", "k7").unwrap();
+        store.set("t1", "k8".into(), "updated".into()).unwrap();
+        assert!(store.segments() > 1);
+        drop(store);
+
+        let store = Bitcask::open(dir.path(), small_segments()).unwrap();
+        assert_eq!(store.get("t1", "k0"), Ok(Some(0.into())));
+        assert_eq!(store.get("t1", "k7"), Ok(None));
+        assert_eq!(store.get("t1", "k8"), Ok(Some("updated".into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 49);
+
+        // 只读数据文件都应该有 hint 文件
+        let files = data_files(dir.path());
+        for f in &files[..files.len() - 1] {
+            assert!(f.with_extension(HINT_EXT).exists(), "{:?}", f);
+        }
+    }
+
+    #[test]
+    fn truncated_record_should_be_dropped_on_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = Bitcask::new(dir.path());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        drop(store);
+
+        // 模拟写入 k2 的过程中崩溃：截掉最后一条 record 的一部分
+        let path = data_files(dir.path()).pop().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let store = Bitcask::new(dir.path());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+
+        // 截断后继续写入的数据在下次启动时仍然可读
+        store.set("t1", "k3".into(), "v3".into()).unwrap();
+        drop(store);
+
+        let store = Bitcask::new(dir.path());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k3"), Ok(Some("v3".into())));
+    }
+
+    #[test]
+    fn truncated_header_should_be_dropped_on_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = Bitcask::new(dir.path());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        drop(store);
+
+        // 只写了 record header 的前几个字节
+        let path = data_files(dir.path()).pop().unwrap();
+        let valid = fs::metadata(&path).unwrap().len();
+        let partial = Record::put(9, "t1", "k2", b"v2".to_vec()).encode();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&partial[..5])
+            .unwrap();
+
+        let store = Bitcask::new(dir.path());
+        assert_eq!(store.get_all("t1").unwrap().len(), 1);
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid);
+    }
+
+    #[test]
+    fn incomplete_batch_should_be_dropped_on_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = Bitcask::new(dir.path());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store
+            .write_batch(vec![
+                WriteOp::set("t1", "k1", "changed".into()),
+                WriteOp::set("t1", "k2", "v2".into()),
+                WriteOp::set("t2", "k3", "v3".into()),
+            ])
+            .unwrap();
+        drop(store);
+
+        // batch 的最后一条 record 不完整，整个 batch 都应该被丢弃
+        let path = data_files(dir.path()).pop().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let store = Bitcask::new(dir.path());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t2", "k3"), Ok(None));
+    }
+
+    #[test]
+    fn corrupted_record_should_be_dropped_on_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = Bitcask::new(dir.path());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        drop(store);
+
+        // 修改最后一条 record 的最后一个字节，crc 校验失败
+        let path = data_files(dir.path()).pop().unwrap();
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let store = Bitcask::new(dir.path());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn merge_should_remove_stale_data() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = Bitcask::open(dir.path(), small_segments()).unwrap();
+        for round in 0..10 {
+            for i in 0..5 {
+                store
+                    .set("t1", format!("k{}", i), (round as i64).into())
+                    .unwrap();
+            }
+        }
+        store.del("t1", "k4").unwrap();
+
+        let before = store.segments();
+        assert!(store.stale_bytes() > 0);
+
+        store.merge().unwrap();
+        assert!(store.segments() < before);
+        assert_eq!(store.stale_bytes(), 0);
+        assert_eq!(store.get("t1", "k0"), Ok(Some(9.into())));
+        assert_eq!(store.get("t1", "k4"), Ok(None));
+        assert_eq!(store.get_all("t1").unwrap().len(), 4);
+
+        // merge 后重启，数据不变
+        store.set("t1", "k5".into(), "new".into()).unwrap();
+        drop(store);
+
+        let store = Bitcask::open(dir.path(), small_segments()).unwrap();
+        assert_eq!(store.get("t1", "k0"), Ok(Some(9.into())));
+        assert_eq!(store.get("t1", "k4"), Ok(None));
+        assert_eq!(store.get("t1", "k5"), Ok(Some("new".into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 5);
+    }
+
+    #[test]
+    fn background_merge_should_compact_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = BitcaskConfig {
+            merge_interval: Some(Duration::from_millis(10)),
+            ..small_segments()
+        };
+
+        let store = Bitcask::open(dir.path(), config).unwrap();
+        for round in 0..20 {
+            store.set("t1", "k1".into(), (round as i64).into()).unwrap();
+            store.set("t1", "k2".into(), (round as i64).into()).unwrap();
+        }
+
+        for _ in 0..100 {
+            if !store.shared.needs_merge() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(!store.shared.needs_merge());
+        assert_eq!(store.get("t1", "k1"), Ok(Some(19.into())));
+        assert_eq!(store.get("t1", "k2"), Ok(Some(19.into())));
+    }
+}
@@ -0,0 +1,396 @@
+//! 数据文件和 hint 文件的格式
+//!
+//! 数据文件由连续的 record 组成：
+//!
+//! ```text
+//! | crc: u32 | seq: u64 | flags: u8 | table_len: u32 | key_len: u32 | value_len: u32 | table | key | value |
+//! ```
+//!
+//! crc 覆盖 crc 之后的所有字节；value_len 为 TOMBSTONE 时表示删除，没有 value 部分。
+//! 一个 batch 中除最后一条外的 record 都带有 FLAG_CHAINED，恢复时不完整的 batch 整个丢弃。
+//! 整数都使用小端序。hint 文件由连续的 hint 组成，用于启动时不读取 value 就能重建 keydir：
+//!
+//! ```text
+//! | seq: u64 | table_len: u32 | key_len: u32 | value_len: u32 | offset: u64 | table | key |
+//! ```
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use tracing::warn;
+
+/// value_len 为这个值时表示删除
+pub(crate) const TOMBSTONE: u32 = u32::MAX;
+
+/// 下一条 record 和这条属于同一个 batch
+const FLAG_CHAINED: u8 = 1;
+
+const RECORD_HEADER_LEN: usize = 4 + 8 + 1 + 4 + 4 + 4;
+/// 读取 record 时预先分配的最大长度
+const READ_CHUNK_LEN: usize = 64 * 1024;
+const HINT_HEADER_LEN: usize = 8 + 4 + 4 + 4 + 8;
+
+/// 数据文件中的一条记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub seq: u64,
+    pub table: String,
+    pub key: String,
+    /// None 表示删除
+    pub value: Option<Vec<u8>>,
+    /// 为 true 时，下一条 record 和这条属于同一个 batch
+    pub chained: bool,
+}
+
+/// 读取 record 的结果
+#[derive(Debug)]
+pub(crate) enum ReadResult {
+    /// 读到一条完整的 record 和它的长度
+    Record(Record, u64),
+    /// 文件结束
+    Eof,
+    /// record 不完整或者校验失败，通常是写入过程中崩溃导致的
+    Corrupted,
+}
+
+impl Record {
+    pub fn put(seq: u64, table: &str, key: &str, value: Vec<u8>) -> Self {
+        Self {
+            seq,
+            table: table.into(),
+            key: key.into(),
+            value: Some(value),
+            chained: false,
+        }
+    }
+
+    pub fn tombstone(seq: u64, table: &str, key: &str) -> Self {
+        Self {
+            seq,
+            table: table.into(),
+            key: key.into(),
+            value: None,
+            chained: false,
+        }
+    }
+
+    /// 编码成数据文件中的格式
+    pub fn encode(&self) -> Vec<u8> {
+        let value = self.value.as_deref().unwrap_or_default();
+        let value_len = match self.value {
+            Some(_) => value.len() as u32,
+            None => TOMBSTONE,
+        };
+        let mut buf = Vec::with_capacity(self.encoded_len());
+
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.push(if self.chained { FLAG_CHAINED } else { 0 });
+        buf.extend_from_slice(&(self.table.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&value_len.to_le_bytes());
+        buf.extend_from_slice(self.table.as_bytes());
+        buf.extend_from_slice(self.key.as_bytes());
+        buf.extend_from_slice(value);
+
+        let crc = crc32fast::hash(&buf[4..]);
+        buf[..4].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        RECORD_HEADER_LEN
+            + self.table.len()
+            + self.key.len()
+            + self.value.as_ref().map_or(0, |v| v.len())
+    }
+
+    /// 从 reader 中读取下一条 record
+    pub fn read_from(reader: &mut impl Read) -> io::Result<ReadResult> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+
+        match read_full(reader, &mut header)? {
+            0 => return Ok(ReadResult::Eof),
+            n if n < RECORD_HEADER_LEN => return Ok(ReadResult::Corrupted),
+            _ => {}
+        }
+
+        let crc = u32_at(&header, 0);
+        let seq = u64_at(&header, 4);
+        let flags = header[12];
+        let table_len = u32_at(&header, 13) as usize;
+        let key_len = u32_at(&header, 17) as usize;
+        let value_len = u32_at(&header, 21);
+        let body_len = table_len
+            + key_len
+            + if value_len == TOMBSTONE {
+                0
+            } else {
+                value_len as usize
+            };
+
+        // 长度在校验 crc 之前还不可信，随读随分配，最多分配剩下的数据那么多，而不是按 header 一次分配
+        let mut body = Vec::with_capacity(body_len.min(READ_CHUNK_LEN));
+        if reader.take(body_len as u64).read_to_end(&mut body)? < body_len {
+            return Ok(ReadResult::Corrupted);
+        }
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header[4..]);
+        hasher.update(&body);
+
+        if hasher.finalize() != crc {
+            return Ok(ReadResult::Corrupted);
+        }
+
+        let value = (value_len != TOMBSTONE).then(|| body.split_off(table_len + key_len));
+        let key = body.split_off(table_len);
+
+        match (String::from_utf8(body), String::from_utf8(key)) {
+            (Ok(table), Ok(key)) => Ok(ReadResult::Record(
+                Record {
+                    seq,
+                    table,
+                    key,
+                    value,
+                    chained: flags & FLAG_CHAINED != 0,
+                },
+                (RECORD_HEADER_LEN + body_len) as u64,
+            )),
+            _ => Ok(ReadResult::Corrupted),
+        }
+    }
+
+    /// 从一段完整的 record 字节中解码
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        match Self::read_from(&mut &data[..])? {
+            ReadResult::Record(r, _) => Ok(r),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Corrupted bitcask record",
+            )),
+        }
+    }
+}
+
+/// 顺序读取整个日志文件，对每条属于完整 batch 的 record 调用 f(record, offset, len)
+///
+/// 遇到不完整或者校验失败的 record（通常是写入过程中崩溃导致的）时，把文件截断到最后一个完整 batch 的末尾。
+pub(crate) fn replay(path: &Path, mut f: impl FnMut(Record, u64, u64)) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut pending = Vec::new();
+    let (mut offset, mut committed) = (0, 0);
+
+    loop {
+        match Record::read_from(&mut reader)? {
+            ReadResult::Record(r, len) => {
+                let chained = r.chained;
+                pending.push((r, offset, len));
+                offset += len;
+
+                if !chained {
+                    pending
+                        .drain(..)
+                        .for_each(|(r, offset, len)| f(r, offset, len));
+                    committed = offset;
+                }
+            }
+            ReadResult::Eof if pending.is_empty() => return Ok(()),
+            _ => break,
+        }
+    }
+
+    warn!(
+        "Truncating corrupted log {:?} at offset {}",
+        path, committed
+    );
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(committed)
+}
+
+/// hint 文件中的一条记录，对应数据文件中的一条 record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hint {
+    pub seq: u64,
+    pub table: String,
+    pub key: String,
+    /// record 在数据文件中的起始位置
+    pub offset: u64,
+    /// record 的总长度，为 0 表示删除
+    pub len: u32,
+}
+
+impl Hint {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.extend_from_slice(&(self.table.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.extend_from_slice(&self.offset.to_le_bytes());
+        buf.extend_from_slice(self.table.as_bytes());
+        buf.extend_from_slice(self.key.as_bytes());
+    }
+
+    /// 解码整个 hint 文件
+    pub fn decode_all(mut data: &[u8]) -> io::Result<Vec<Hint>> {
+        let mut hints = Vec::new();
+
+        while !data.is_empty() {
+            if data.len() < HINT_HEADER_LEN {
+                return Err(invalid_hint());
+            }
+
+            let seq = u64_at(data, 0);
+            let table_len = u32_at(data, 8) as usize;
+            let key_len = u32_at(data, 12) as usize;
+            let len = u32_at(data, 16);
+            let offset = u64_at(data, 20);
+            let end = HINT_HEADER_LEN + table_len + key_len;
+
+            if data.len() < end {
+                return Err(invalid_hint());
+            }
+
+            let table = std::str::from_utf8(&data[HINT_HEADER_LEN..][..table_len])
+                .map_err(|_| invalid_hint())?;
+            let key = std::str::from_utf8(&data[HINT_HEADER_LEN + table_len..end])
+                .map_err(|_| invalid_hint())?;
+
+            hints.push(Hint {
+                seq,
+                table: table.into(),
+                key: key.into(),
+                offset,
+                len,
+            });
+            data = &data[end..];
+        }
+
+        Ok(hints)
+    }
+}
+
+// 尽量读满 buf，返回实际读到的字节数
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+fn invalid_hint() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Corrupted bitcask hint file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_should_roundtrip() {
+        let chained = Record {
+            chained: true,
+            ..Record::put(1, "t1", "k1", b"hello".to_vec())
+        };
+        let records = [
+            chained,
+            Record::tombstone(2, "t1", "k1"),
+            Record::put(3, "", "", vec![]),
+        ];
+        let data: Vec<u8> = records.iter().flat_map(|r| r.encode()).collect();
+        let mut reader = &data[..];
+
+        for r in &records {
+            match Record::read_from(&mut reader).unwrap() {
+                ReadResult::Record(read, len) => {
+                    assert_eq!(&read, r);
+                    assert_eq!(len as usize, r.encoded_len());
+                }
+                res => panic!("unexpected {:?}", res),
+            }
+        }
+
+        assert!(matches!(
+            Record::read_from(&mut reader).unwrap(),
+            ReadResult::Eof
+        ));
+    }
+
+    #[test]
+    fn truncated_or_modified_record_should_be_detected() {
+        let data = Record::put(1, "t1", "k1", b"hello".to_vec()).encode();
+
+        for len in 1..data.len() {
+            assert!(matches!(
+                Record::read_from(&mut &data[..len]).unwrap(),
+                ReadResult::Corrupted
+            ));
+        }
+
+        let mut modified = data.clone();
+        modified[data.len() - 1] ^= 0xff;
+        assert!(matches!(
+            Record::read_from(&mut &modified[..]).unwrap(),
+            ReadResult::Corrupted
+        ));
+    }
+
+    #[test]
+    fn garbage_header_should_be_corrupted() {
+        // header 中的长度是随机数据，后面只有几个字节
+        let mut data = vec![0u8; RECORD_HEADER_LEN];
+        data[13..25].copy_from_slice(&[0xfe; 12]);
+        data.extend_from_slice(b"abc");
+
+        assert!(matches!(
+            Record::read_from(&mut &data[..]).unwrap(),
+            ReadResult::Corrupted
+        ));
+    }
+
+    #[test]
+    fn hint_should_roundtrip() {
+        let hints = vec![
+            Hint {
+                seq: 1,
+                table: "t1".into(),
+                key: "k1".into(),
+                offset: 0,
+                len: 42,
+            },
+            Hint {
+                seq: 2,
+                table: "t2".into(),
+                key: "k2".into(),
+                offset: 42,
+                len: 0,
+            },
+        ];
+        let mut buf = Vec::new();
+        hints.iter().for_each(|h| h.encode(&mut buf));
+
+        assert_eq!(Hint::decode_all(&buf).unwrap(), hints);
+        assert!(Hint::decode_all(&buf[..buf.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{Evicted, KVError, KvPair, Snapshot, Storage, StorageIter, Value, WriteOp};
+
+/// 写入策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// 同时写入缓存层和持久层，写操作返回时数据已经落到持久层
+    WriteThrough,
+    /// 只写入缓存层，由后台线程每隔 flush_interval 批量写入持久层
+    WriteBehind { flush_interval: Duration },
+}
+
+/// 按 (table, key) 分段的锁的数量
+const LOCK_STRIPES: usize = 64;
+
+/// 尚未写入持久层的修改：(table, key) -> Some(value) 表示 set，None 表示 del
+type Dirty = HashMap<(String, String), Option<Value>>;
+
+struct CachedInner<Fast, Slow> {
+    fast: Fast,
+    slow: Slow,
+    dirty: Mutex<Dirty>,
+    /// flush 期间持有，保证 flush 返回时之前取出的修改都已经写入持久层
+    flushing: Mutex<()>,
+    /// 按 (table, key) 分段的锁，同时修改两层（包括读取时填充缓存）的操作持有 key 对应的锁，
+    /// 避免并发的读写交错执行，让缓存层和持久层不一致
+    stripes: Vec<Mutex<()>>,
+}
+
+/// 在持久层 Slow 之上加一层缓存 Fast 的存储，实现了 Storage trait
+///
+/// 读操作先查缓存，未命中时从持久层读取并填充缓存；写操作按 WritePolicy 写入。
+/// 缓存层可能只有部分数据（比如被淘汰），所以 get_all/get_iter 以持久层为准，再叠加尚未写入持久层的修改。
+/// 同一个 key 上修改两层的操作按 key 分段加锁，并发执行时缓存层也不会和持久层不一致。
+pub struct CachedStore<Fast: Storage, Slow: Storage> {
+    inner: Arc<CachedInner<Fast, Slow>>,
+    policy: WritePolicy,
+    stop: Option<Sender<()>>,
+    flusher: Option<JoinHandle<()>>,
+}
+
+impl<Fast, Slow> CachedStore<Fast, Slow>
+where
+    Fast: Storage + Send + Sync + 'static,
+    Slow: Storage + Send + Sync + 'static,
+{
+    pub fn new(fast: Fast, slow: Slow, policy: WritePolicy) -> Self {
+        let inner = Arc::new(CachedInner {
+            fast,
+            slow,
+            dirty: Mutex::new(Dirty::new()),
+            flushing: Mutex::new(()),
+            stripes: (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+        });
+
+        let (stop, flusher) = match policy {
+            WritePolicy::WriteThrough => (None, None),
+            WritePolicy::WriteBehind { flush_interval } => {
+                let (tx, rx) = mpsc::channel::<()>();
+                let inner = inner.clone();
+                let handle = thread::spawn(move || {
+                    // 收到停止信号或者 Sender 被 drop 时退出
+                    while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(flush_interval) {
+                        if let Err(e) = inner.flush() {
+                            warn!("Failed to flush cached writes: {}", e);
+                        }
+                    }
+                });
+
+                (Some(tx), Some(handle))
+            }
+        };
+
+        Self {
+            inner,
+            policy,
+            stop,
+            flusher,
+        }
+    }
+}
+
+impl<Fast: Storage, Slow: Storage> CachedStore<Fast, Slow> {
+    /// 缓存层
+    pub fn fast(&self) -> &Fast {
+        &self.inner.fast
+    }
+
+    /// 持久层
+    pub fn slow(&self) -> &Slow {
+        &self.inner.slow
+    }
+
+    pub fn policy(&self) -> WritePolicy {
+        self.policy
+    }
+
+    /// 尚未写入持久层的修改数
+    pub fn pending(&self) -> usize {
+        self.inner.dirty().len()
+    }
+
+    /// 把尚未写入持久层的修改立即写入持久层
+    pub fn flush(&self) -> Result<(), KVError> {
+        self.inner.flush()
+    }
+
+    fn is_write_behind(&self) -> bool {
+        matches!(self.policy, WritePolicy::WriteBehind { .. })
+    }
+
+    // 在持有 key 对应的锁时读取，缓存未命中时从持久层读取并填充缓存
+    fn load(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        if let Some(v) = self.pending_value(table, key) {
+            return Ok(v);
+        }
+
+        if let Some(v) = self.inner.fast.get(table, key)? {
+            return Ok(Some(v));
+        }
+
+        let v = self.inner.slow.get(table, key)?;
+
+        if let Some(v) = &v {
+            self.inner.fast.set(table, key.into(), v.clone())?;
+        }
+
+        Ok(v)
+    }
+
+    // 读取 (table, key) 尚未写入持久层的修改
+    fn pending_value(&self, table: &str, key: &str) -> Option<Option<Value>> {
+        self.inner
+            .dirty()
+            .get(&(table.to_owned(), key.to_owned()))
+            .cloned()
+    }
+}
+
+impl<Fast: Storage, Slow: Storage> CachedInner<Fast, Slow> {
+    fn dirty(&self) -> MutexGuard<'_, Dirty> {
+        self.dirty.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_key(&self, table: &str, key: &str) -> MutexGuard<'_, ()> {
+        lock(&self.stripes[stripe(table, key)])
+    }
+
+    // 按分段的顺序加锁，多个 key 的操作之间不会死锁
+    fn lock_keys(&self, ops: &[WriteOp]) -> Vec<MutexGuard<'_, ()>> {
+        let mut stripes: Vec<_> = ops.iter().map(|op| stripe(op.table(), op.key())).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+
+        stripes
+            .into_iter()
+            .map(|i| lock(&self.stripes[i]))
+            .collect()
+    }
+
+    // 把当前所有修改作为一个 batch 写入持久层。写入完成前修改一直留在 dirty 中，
+    // 保证读操作始终能看到最新的数据；成功后只删除期间没有再被修改的条目
+    fn flush(&self) -> Result<(), KVError> {
+        let _flushing = lock(&self.flushing);
+        let pending = self.dirty().clone();
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let ops = pending
+            .iter()
+            .map(|((table, key), value)| match value {
+                Some(v) => WriteOp::set(table, key, v.clone()),
+                None => WriteOp::del(table, key),
+            })
+            .collect();
+
+        self.slow.write_batch(ops)?;
+
+        let mut dirty = self.dirty();
+        for (k, v) in pending {
+            if dirty.get(&k) == Some(&v) {
+                dirty.remove(&k);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn stripe(table: &str, key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    table.hash(&mut hasher);
+    key.hash(&mut hasher);
+
+    (hasher.finish() % LOCK_STRIPES as u64) as usize
+}
+
+fn lock(m: &Mutex<()>) -> MutexGuard<'_, ()> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl<Fast: Storage, Slow: Storage> Drop for CachedStore<Fast, Slow> {
+    fn drop(&mut self) {
+        // 先停止后台线程，再把剩下的修改写入持久层
+        self.stop.take();
+
+        if let Some(handle) = self.flusher.take() {
+            let _ = handle.join();
+        }
+
+        if let Err(e) = self.inner.flush() {
+            warn!("Failed to flush cached writes on drop: {}", e);
+        }
+    }
+}
+
+impl<Fast: Storage, Slow: Storage> Storage for CachedStore<Fast, Slow> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        if let Some(v) = self.pending_value(table, key) {
+            return Ok(v);
+        }
+
+        if let Some(v) = self.inner.fast.get(table, key)? {
+            return Ok(Some(v));
+        }
+
+        // 填充缓存时持有锁，避免把并发删除或者覆盖之前的 value 写入缓存
+        let _key = self.inner.lock_key(table, key);
+        self.load(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+        let _key = self.inner.lock_key(table, &key);
+
+        if !self.is_write_behind() {
+            let old = self.inner.slow.set(table, key.clone(), value.clone())?;
+            self.inner.fast.set(table, key, value)?;
+            return Ok(old);
+        }
+
+        let old = self.load(table, &key)?;
+        self.inner.fast.set(table, key.clone(), value.clone())?;
+        self.inner.dirty().insert((table.into(), key), Some(value));
+
+        Ok(old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        if let Some(v) = self.pending_value(table, key) {
+            return Ok(v.is_some());
+        }
+
+        Ok(self.inner.fast.contains(table, key)? || self.inner.slow.contains(table, key)?)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let _key = self.inner.lock_key(table, key);
+
+        if !self.is_write_behind() {
+            let old = self.inner.slow.del(table, key)?;
+            self.inner.fast.del(table, key)?;
+            return Ok(old);
+        }
+
+        let old = self.load(table, key)?;
+        self.inner.fast.del(table, key)?;
+        self.inner.dirty().insert((table.into(), key.into()), None);
+
+        Ok(old)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        Ok(self.get_iter(table)?.collect())
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+        // 取出这个 table 尚未写入持久层的修改，持久层中对应的 key 以修改为准
+        let overlay: HashMap<String, Option<Value>> = self
+            .inner
+            .dirty()
+            .iter()
+            .filter(|((t, _), _)| t == table)
+            .map(|((_, k), v)| (k.clone(), v.clone()))
+            .collect();
+
+        let pending: Vec<_> = overlay
+            .iter()
+            .filter_map(|(k, v)| v.clone().map(|v| KvPair::new(k, v)))
+            .collect();
+
+        let iter = self
+            .inner
+            .slow
+            .get_iter(table)?
+            .filter(move |pair| !overlay.contains_key(&pair.key))
+            .chain(pending);
+
+        Ok(StorageIter::new(iter))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        // 持久层中的 table 可能因为尚未写入的删除而变空，也可能有只存在于 dirty 中的 table
+        let mut names = self.inner.slow.tables()?;
+        names.extend(self.inner.dirty().keys().map(|(t, _)| t.clone()));
+        names.sort();
+        names.dedup();
+
+        let mut tables = Vec::with_capacity(names.len());
+        for name in names {
+            if self.get_iter(&name)?.next().is_some() {
+                tables.push(name);
+            }
+        }
+
+        Ok(tables)
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        // 持久层总是包含写入历史的一个前缀，先把 dirty 写下去，快照就包含调用之前的所有写入
+        if self.is_write_behind() {
+            self.inner.flush()?;
+        }
+
+        self.inner.slow.snapshot()
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        let _keys = self.inner.lock_keys(&ops);
+
+        if !self.is_write_behind() {
+            let old = self.inner.slow.write_batch(ops.clone())?;
+            self.inner.fast.write_batch(ops)?;
+            return Ok(old);
+        }
+
+        // 按顺序计算旧的 value，batch 中靠前的操作对后面的操作可见
+        let mut staged = Dirty::new();
+        let mut old = Vec::with_capacity(ops.len());
+
+        for op in &ops {
+            let k = (op.table().to_owned(), op.key().to_owned());
+            old.push(match staged.get(&k) {
+                Some(v) => v.clone(),
+                None => self.load(op.table(), op.key())?,
+            });
+            staged.insert(
+                k,
+                match op {
+                    WriteOp::Set { value, .. } => Some(value.clone()),
+                    WriteOp::Del { .. } => None,
+                },
+            );
+        }
+
+        self.inner.fast.write_batch(ops)?;
+        self.inner.dirty().extend(staged);
+
+        Ok(old)
+    }
+
+    fn drop_tables(&self, prefix: &str) -> Result<usize, KVError> {
+        // 先把 dirty 写下去，持久层删除的 key 就是 store 中可见的所有 key
+        if self.is_write_behind() {
+            self.inner.flush()?;
+        }
+
+        self.inner.fast.drop_tables(prefix)?;
+        self.inner.slow.drop_tables(prefix)
+    }
+
+    fn drain_evicted(&self) -> Vec<Evicted> {
+        self.inner.fast.drain_evicted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, RedbDb, SledDb};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn write_behind() -> WritePolicy {
+        // 间隔足够长，测试中由 flush() 显式触发
+        WritePolicy::WriteBehind {
+            flush_interval: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn read_should_fill_cache_from_slow_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let slow = SledDb::new(dir);
+        slow.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        let store = CachedStore::new(MemTable::new(), slow, WritePolicy::WriteThrough);
+        assert_eq!(store.fast().get("t1", "k1"), Ok(None));
+
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.fast().get("t1", "k1"), Ok(Some("v1".into())));
+    }
+
+    #[test]
+    fn write_through_should_write_both_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CachedStore::new(MemTable::new(), SledDb::new(dir), WritePolicy::WriteThrough);
+
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        assert_eq!(store.slow().get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.fast().get("t1", "k1"), Ok(Some("v1".into())));
+
+        store.del("t1", "k1").unwrap();
+        assert_eq!(store.slow().get("t1", "k1"), Ok(None));
+        assert_eq!(store.fast().get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn write_behind_should_defer_writes_until_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let slow = SledDb::new(dir);
+        slow.set("t1", "k2".into(), "old".into()).unwrap();
+
+        let store = CachedStore::new(MemTable::new(), slow, write_behind());
+
+        assert_eq!(store.set("t1", "k1".into(), "v1".into()), Ok(None));
+        assert_eq!(store.del("t1", "k2"), Ok(Some("old".into())));
+        assert_eq!(store.pending(), 2);
+
+        // 持久层还没有变化，但从 store 读到的是最新的数据
+        assert_eq!(store.slow().get("t1", "k1"), Ok(None));
+        assert_eq!(store.slow().get("t1", "k2"), Ok(Some("old".into())));
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.contains("t1", "k2"), Ok(false));
+        assert_eq!(
+            store.get_all("t1"),
+            Ok(vec![KvPair::new("k1", "v1".into())])
+        );
+
+        store.flush().unwrap();
+        assert_eq!(store.pending(), 0);
+        assert_eq!(store.slow().get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.slow().get("t1", "k2"), Ok(None));
+    }
+
+    // 很慢的持久层，用来观察 flush 和读取过程中的并发读写
+    struct SlowStore {
+        inner: MemTable,
+        read_delay: Duration,
+        write_delay: Duration,
+        writing: AtomicBool,
+    }
+
+    impl SlowStore {
+        fn new(read_delay: Duration, write_delay: Duration) -> Self {
+            Self {
+                inner: MemTable::new(),
+                read_delay,
+                write_delay,
+                writing: AtomicBool::new(false),
+            }
+        }
+    }
+
+    impl Storage for SlowStore {
+        fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+            thread::sleep(self.read_delay);
+            self.inner.get(table, key)
+        }
+        fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+            self.inner.set(table, key, value)
+        }
+        fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+            self.inner.contains(table, key)
+        }
+        fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+            self.inner.del(table, key)
+        }
+        fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+            self.inner.get_all(table)
+        }
+        fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+            self.inner.get_iter(table)
+        }
+        fn tables(&self) -> Result<Vec<String>, KVError> {
+            self.inner.tables()
+        }
+        fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+            self.writing.store(true, Ordering::SeqCst);
+            thread::sleep(self.write_delay);
+            self.inner.write_batch(ops)
+        }
+    }
+
+    #[test]
+    fn reads_during_slow_flush_should_see_pending_writes() {
+        let slow = SlowStore::new(Duration::ZERO, Duration::from_millis(200));
+        let store = Arc::new(CachedStore::new(MemTable::new(), slow, write_behind()));
+        for i in 0..10 {
+            store.set("t1", format!("k{i}"), i.into()).unwrap();
+        }
+
+        let flusher = {
+            let store = store.clone();
+            thread::spawn(move || store.flush())
+        };
+        while !store.slow().writing.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+
+        // flush 进行中，get/get_all 始终能看到所有数据
+        let mut overwritten = false;
+        while !flusher.is_finished() {
+            assert_eq!(store.get("t1", "k3"), Ok(Some(3.into())));
+            assert_eq!(store.get_all("t1").unwrap().len(), 10);
+            assert_eq!(store.get_iter("t1").unwrap().count(), 10);
+
+            // flush 期间的新修改不能因为 flush 完成而丢失
+            if !overwritten {
+                store.set("t1", "k9".into(), "new".into()).unwrap();
+                overwritten = true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        flusher.join().unwrap().unwrap();
+
+        assert!(overwritten);
+        assert_eq!(store.pending(), 1);
+        assert_eq!(store.get("t1", "k9"), Ok(Some("new".into())));
+        assert_eq!(store.slow().get("t1", "k3"), Ok(Some(3.into())));
+
+        store.flush().unwrap();
+        assert_eq!(store.pending(), 0);
+        assert_eq!(store.slow().get("t1", "k9"), Ok(Some("new".into())));
+    }
+
+    #[test]
+    fn read_fill_should_not_cache_concurrently_deleted_value() {
+        let slow = SlowStore::new(Duration::from_millis(100), Duration::ZERO);
+        slow.inner.set("t1", "k1".into(), "v1".into()).unwrap();
+        let store = Arc::new(CachedStore::new(
+            MemTable::new(),
+            slow,
+            WritePolicy::WriteThrough,
+        ));
+
+        // 读取在持久层中等待时删除 key，删除要等读取填充完缓存之后才能执行
+        let reader = {
+            let store = store.clone();
+            thread::spawn(move || store.get("t1", "k1"))
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.del("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(reader.join().unwrap(), Ok(Some("v1".into())));
+
+        assert_eq!(store.fast().get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn write_behind_should_flush_periodically_and_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = WritePolicy::WriteBehind {
+            flush_interval: Duration::from_millis(10),
+        };
+
+        let store = CachedStore::new(MemTable::new(), SledDb::new(dir.path()), policy);
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        for _ in 0..100 {
+            if store.pending() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(store.slow().get("t1", "k1"), Ok(Some("v1".into())));
+
+        // sled 在 drop 后释放文件锁有延迟，重新打开用 redb
+        let path = dir.path().join("kv.redb");
+        let store = CachedStore::new(MemTable::new(), RedbDb::new(&path), write_behind());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        drop(store);
+
+        let slow = RedbDb::new(&path);
+        assert_eq!(slow.get("t1", "k1"), Ok(Some("v1".into())));
+    }
+}
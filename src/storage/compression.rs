@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{KVError, Value};
+
+// 压缩后的数据以 tag 开头。protobuf 编码的第一个字节是 field key，field 号至少为 1，
+// 所以不会小于 0x08，没有 tag 的旧数据仍然按原样解码
+const TAG_LZ4: u8 = 0x01;
+const TAG_ZSTD: u8 = 0x02;
+
+/// 压缩算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Lz4,
+    /// zstd 的压缩级别，0 表示使用默认级别
+    Zstd(i32),
+}
+
+/// 持久化存储中 value 的压缩配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// 默认的压缩算法，None 表示不压缩
+    pub codec: Option<Codec>,
+    /// 编码后小于这个大小的 value 不压缩
+    pub threshold: usize,
+    /// 为某些 table 单独指定压缩算法，None 表示这个 table 不压缩
+    pub tables: HashMap<String, Option<Codec>>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            codec: None,
+            threshold: 128,
+            tables: HashMap::new(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// 所有 table 都使用 codec 压缩
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec: Some(codec),
+            ..Default::default()
+        }
+    }
+
+    fn codec(&self, table: &str) -> Option<Codec> {
+        self.tables.get(table).copied().unwrap_or(self.codec)
+    }
+}
+
+/// 打开存储以来写入的 value 的压缩统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// 写入的 value 数量
+    pub values: u64,
+    /// 其中被压缩的数量
+    pub compressed: u64,
+    /// protobuf 编码后的总大小
+    pub raw_bytes: u64,
+    /// 实际写入的总大小
+    pub stored_bytes: u64,
+}
+
+impl CompressionStats {
+    /// 压缩比（原始大小 / 写入大小），没有写入时为 1
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+
+        self.raw_bytes as f64 / self.stored_bytes as f64
+    }
+}
+
+/// 按配置编码和解码 value，持久化存储用它代替直接的 protobuf 编解码
+#[derive(Debug, Default)]
+pub(crate) struct Compressor {
+    config: CompressionConfig,
+    values: AtomicU64,
+    compressed: AtomicU64,
+    raw_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+}
+
+impl Compressor {
+    pub(crate) fn new(config: CompressionConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn encode(&self, table: &str, value: Value) -> Result<Vec<u8>, KVError> {
+        let raw: Vec<u8> = value.try_into()?;
+        let raw_len = raw.len();
+
+        let data = match self.config.codec(table) {
+            Some(codec) if raw_len >= self.config.threshold => {
+                let compressed = compress(codec, &raw)?;
+                // 压缩后没有变小就保存原始数据，读取时少一次解压
+                if compressed.len() < raw_len {
+                    self.compressed.fetch_add(1, Ordering::Relaxed);
+                    compressed
+                } else {
+                    raw
+                }
+            }
+            _ => raw,
+        };
+
+        self.values.fetch_add(1, Ordering::Relaxed);
+        self.raw_bytes.fetch_add(raw_len as u64, Ordering::Relaxed);
+        self.stored_bytes
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+
+        Ok(data)
+    }
+
+    pub(crate) fn decode(&self, data: &[u8]) -> Result<Value, KVError> {
+        match data.split_first() {
+            Some((&TAG_LZ4, rest)) => lz4_flex::decompress_size_prepended(rest)
+                .map_err(|e| KVError::CompressionError(e.to_string()))?
+                .as_slice()
+                .try_into(),
+            Some((&TAG_ZSTD, rest)) => zstd::decode_all(rest)
+                .map_err(|e| KVError::CompressionError(e.to_string()))?
+                .as_slice()
+                .try_into(),
+            _ => data.try_into(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            values: self.values.load(Ordering::Relaxed),
+            compressed: self.compressed.load(Ordering::Relaxed),
+            raw_bytes: self.raw_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn compress(codec: Codec, data: &[u8]) -> Result<Vec<u8>, KVError> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 1);
+
+    match codec {
+        Codec::Lz4 => {
+            out.push(TAG_LZ4);
+            out.extend(lz4_flex::compress_prepend_size(data));
+        }
+        Codec::Zstd(level) => {
+            out.push(TAG_ZSTD);
+            zstd::stream::copy_encode(data, &mut out, level)
+                .map_err(|e| KVError::CompressionError(e.to_string()))?;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn large_value() -> Value {
+        "hello world ".repeat(100).into()
+    }
+
+    #[test]
+    fn compressor_should_roundtrip_with_all_codecs() {
+        for codec in [Codec::Lz4, Codec::Zstd(0)] {
+            let c = Compressor::new(CompressionConfig::new(codec));
+
+            let data = c.encode("t1", large_value()).unwrap();
+            assert!(data[0] < 0x08);
+            assert_eq!(c.decode(&data), Ok(large_value()));
+
+            // 小于 threshold 的 value 不压缩
+            let data = c.encode("t1", "small".into()).unwrap();
+            assert_eq!(data, Vec::<u8>::try_from(Value::from("small")).unwrap());
+            assert_eq!(c.decode(&data), Ok("small".into()));
+
+            let stats = c.stats();
+            assert_eq!((stats.values, stats.compressed), (2, 1));
+            assert!(stats.ratio() > 5.0);
+        }
+    }
+
+    #[test]
+    fn compressor_should_follow_table_config() {
+        let mut config = CompressionConfig::new(Codec::Lz4);
+        config.tables.insert("raw".into(), None);
+        config.tables.insert("zstd".into(), Some(Codec::Zstd(3)));
+        let c = Compressor::new(config);
+
+        let raw: Vec<u8> = large_value().try_into().unwrap();
+        assert_eq!(c.encode("raw", large_value()).unwrap(), raw);
+        assert_eq!(c.encode("zstd", large_value()).unwrap()[0], TAG_ZSTD);
+        assert_eq!(c.encode("t1", large_value()).unwrap()[0], TAG_LZ4);
+    }
+
+    #[test]
+    fn corrupted_data_should_fail_to_decode() {
+        let c = Compressor::default();
+        assert!(matches!(
+            c.decode(&[TAG_LZ4, 0xff, 0xff]),
+            Err(KVError::CompressionError(_))
+        ));
+    }
+}
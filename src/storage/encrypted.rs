@@ -0,0 +1,413 @@
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use chacha20poly1305::{
+    AeadCore, ChaCha20Poly1305, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload},
+};
+
+use crate::{
+    KVError, KvPair, Snapshot, Storage, StorageIter, Value, WriteOp, storage::skip_errors,
+};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+// 密文格式：key id（4 字节，大端）+ nonce + 密文和 tag
+const HEADER_LEN: usize = 4 + NONCE_LEN;
+
+/// 加密用的一组密钥，新写入的数据用 current 加密，其它密钥只用来解密之前写入的数据
+#[derive(Clone)]
+pub struct Keyring {
+    keys: BTreeMap<u32, ChaCha20Poly1305>,
+    current: u32,
+}
+
+impl Keyring {
+    pub fn new(id: u32, key: [u8; KEY_LEN]) -> Self {
+        Self {
+            keys: BTreeMap::from([(id, ChaCha20Poly1305::new(&key.into()))]),
+            current: id,
+        }
+    }
+
+    /// 从密钥文件读取，每行一个 "id:64 位十六进制密钥"，id 最大的密钥用于加密新数据；空行和 # 开头的行会被忽略
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, KVError> {
+        let content = fs::read_to_string(path)?;
+        let mut keyring: Option<Keyring> = None;
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (id, key) = parse_key(line)?;
+            match keyring.as_mut() {
+                Some(k) if id > k.current => k.rotate(id, key),
+                Some(k) => k.add(id, key),
+                None => keyring = Some(Keyring::new(id, key)),
+            }
+        }
+
+        keyring.ok_or_else(|| KVError::CryptoError("Key file contains no keys".into()))
+    }
+
+    /// 添加一个只用于解密的旧密钥
+    pub fn add(&mut self, id: u32, key: [u8; KEY_LEN]) {
+        self.keys.insert(id, ChaCha20Poly1305::new(&key.into()));
+    }
+
+    /// 添加新密钥并用它加密之后写入的数据，之前的密钥仍然保留用于解密
+    pub fn rotate(&mut self, id: u32, key: [u8; KEY_LEN]) {
+        self.add(id, key);
+        self.current = id;
+    }
+
+    /// 当前用于加密的密钥 id
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    fn encrypt(&self, table: &str, key: &str, value: Value) -> Result<Value, KVError> {
+        let plaintext: Vec<u8> = value.try_into()?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = associated_data(table, key);
+
+        let ciphertext = self.keys[&self.current]
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| KVError::CryptoError("Failed to encrypt value".into()))?;
+
+        let mut buf = BytesMut::with_capacity(HEADER_LEN + ciphertext.len());
+        buf.put_u32(self.current);
+        buf.put_slice(&nonce);
+        buf.put_slice(&ciphertext);
+
+        Ok(buf.freeze().into())
+    }
+
+    fn decrypt(&self, table: &str, key: &str, value: Value) -> Result<Value, KVError> {
+        let data = Bytes::try_from(value)?;
+        if data.len() < HEADER_LEN {
+            return Err(KVError::CryptoError(format!(
+                "Value of table: {}, key: {} is not encrypted",
+                table, key
+            )));
+        }
+
+        let id = u32::from_be_bytes(data[..4].try_into().unwrap());
+        let cipher = self
+            .keys
+            .get(&id)
+            .ok_or_else(|| KVError::CryptoError(format!("Key {} not found", id)))?;
+        let aad = associated_data(table, key);
+
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&data[4..HEADER_LEN]),
+                Payload {
+                    msg: &data[HEADER_LEN..],
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| {
+                KVError::CryptoError(format!(
+                    "Failed to decrypt value of table: {}, key: {}",
+                    table, key
+                ))
+            })?;
+
+        plaintext.as_slice().try_into()
+    }
+
+    fn decrypt_pair(&self, table: &str, pair: KvPair) -> Result<KvPair, KVError> {
+        let value = self.decrypt(table, &pair.key, pair.value.unwrap_or_default())?;
+        Ok(KvPair::new(pair.key, value))
+    }
+
+    fn decrypt_pairs(&self, table: &str, pairs: Vec<KvPair>) -> Result<Vec<KvPair>, KVError> {
+        pairs
+            .into_iter()
+            .map(|p| self.decrypt_pair(table, p))
+            .collect()
+    }
+}
+
+/// 在任意 Storage 之上加密 value 的存储，table 和 key 作为关联数据参与认证，密文不能挪到别的 key 下使用
+///
+/// table 和 key 本身不加密。
+pub struct EncryptedStore<S> {
+    inner: S,
+    keyring: Arc<Keyring>,
+}
+
+impl<S: Storage> EncryptedStore<S> {
+    pub fn new(inner: S, keyring: Keyring) -> Self {
+        Self {
+            inner,
+            keyring: Arc::new(keyring),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn decrypt(&self, table: &str, key: &str, v: Option<Value>) -> Result<Option<Value>, KVError> {
+        v.map(|v| self.keyring.decrypt(table, key, v)).transpose()
+    }
+}
+
+impl<S: Storage> Storage for EncryptedStore<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        self.decrypt(table, key, self.inner.get(table, key)?)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+        let data = self.keyring.encrypt(table, &key, value)?;
+        let old = self.inner.set(table, key.clone(), data)?;
+
+        self.decrypt(table, &key, old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        self.inner.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        self.decrypt(table, key, self.inner.del(table, key)?)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        self.keyring
+            .decrypt_pairs(table, self.inner.get_all(table)?)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+        let iter = self.inner.get_iter(table)?;
+        let table = table.to_owned();
+
+        Ok(StorageIter::new(skip_errors(
+            iter.map(move |p| self.keyring.decrypt_pair(&table, p)),
+        )))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        self.inner.tables()
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        let keys: Vec<_> = ops
+            .iter()
+            .map(|op| (op.table().to_owned(), op.key().to_owned()))
+            .collect();
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Set { table, key, value } => {
+                    let value = self.keyring.encrypt(&table, &key, value)?;
+                    Ok(WriteOp::Set { table, key, value })
+                }
+                op => Ok(op),
+            })
+            .collect::<Result<Vec<_>, KVError>>()?;
+
+        self.inner
+            .write_batch(ops)?
+            .into_iter()
+            .zip(keys)
+            .map(|(v, (table, key))| self.decrypt(&table, &key, v))
+            .collect()
+    }
+
+    fn contains_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<bool>, KVError> {
+        self.inner.contains_many(table, keys)
+    }
+
+    fn drop_tables(&self, prefix: &str) -> Result<usize, KVError> {
+        self.inner.drop_tables(prefix)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let pairs = self.inner.get_range(table, start, end, limit, reverse)?;
+        self.keyring.decrypt_pairs(table, pairs)
+    }
+
+    fn get_prefix(&self, table: &str, prefix: &str) -> Result<Vec<KvPair>, KVError> {
+        let pairs = self.inner.get_prefix(table, prefix)?;
+        self.keyring.decrypt_pairs(table, pairs)
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        Ok(Arc::new(EncryptedSnapshot {
+            inner: self.inner.snapshot()?,
+            keyring: self.keyring.clone(),
+        }))
+    }
+}
+
+/// 读取时解密的快照
+struct EncryptedSnapshot {
+    inner: Arc<dyn Snapshot>,
+    keyring: Arc<Keyring>,
+}
+
+impl Snapshot for EncryptedSnapshot {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        self.inner
+            .get(table, key)?
+            .map(|v| self.keyring.decrypt(table, key, v))
+            .transpose()
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let pairs = self.inner.scan(table, start, end, limit)?;
+        self.keyring.decrypt_pairs(table, pairs)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        self.inner.tables()
+    }
+}
+
+// 关联数据：table 的长度 + table + key，长度前缀避免 ("a", "bc") 和 ("ab", "c") 相同
+fn associated_data(table: &str, key: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(4 + table.len() + key.len());
+    aad.put_u32(table.len() as u32);
+    aad.put_slice(table.as_bytes());
+    aad.put_slice(key.as_bytes());
+    aad
+}
+
+fn parse_key(line: &str) -> Result<(u32, [u8; KEY_LEN]), KVError> {
+    let invalid = || KVError::CryptoError(format!("Invalid key line: {}", line));
+
+    let (id, hex) = line.split_once(':').ok_or_else(invalid)?;
+    let id = id.trim().parse().map_err(|_| invalid())?;
+    let hex = hex.trim().as_bytes();
+    if hex.len() != KEY_LEN * 2 {
+        return Err(invalid());
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    for (i, b) in key.iter_mut().enumerate() {
+        let s = str::from_utf8(&hex[i * 2..i * 2 + 2]).map_err(|_| invalid())?;
+        *b = u8::from_str_radix(s, 16).map_err(|_| invalid())?;
+    }
+
+    Ok((id, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+
+    fn keyring() -> Keyring {
+        Keyring::new(1, [1; KEY_LEN])
+    }
+
+    #[test]
+    fn values_should_be_encrypted_in_inner_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedStore::new(SledDb::new(&dir), keyring());
+
+        store.set("t1", "k1".into(), "secret".into()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("secret".into())));
+
+        let raw = store.inner().get("t1", "k1").unwrap().unwrap();
+        let raw = Bytes::try_from(raw).unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"secret"));
+        assert_eq!(&raw[..4], &1u32.to_be_bytes());
+    }
+
+    #[test]
+    fn swapped_ciphertext_should_fail_to_decrypt() {
+        let store = EncryptedStore::new(MemTable::new(), keyring());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        // 把 k1 的密文复制到 k2 和另一个 table 下
+        let raw = store.inner().get("t1", "k1").unwrap().unwrap();
+        store.inner().set("t1", "k2".into(), raw.clone()).unwrap();
+        store.inner().set("t2", "k1".into(), raw).unwrap();
+
+        assert!(matches!(
+            store.get("t1", "k2"),
+            Err(KVError::CryptoError(_))
+        ));
+        assert!(matches!(
+            store.get("t2", "k1"),
+            Err(KVError::CryptoError(_))
+        ));
+
+        // 未加密的数据不能读取
+        store
+            .inner()
+            .set("t1", "k3".into(), "plain".into())
+            .unwrap();
+        assert!(store.get("t1", "k3").is_err());
+    }
+
+    #[test]
+    fn old_records_should_stay_readable_after_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.keys");
+        let hex = |b: u8| format!("{:02x}", b).repeat(KEY_LEN);
+
+        fs::write(&path, format!("# keys\n1:{}\n", hex(1))).unwrap();
+        let store = EncryptedStore::new(MemTable::new(), Keyring::from_file(&path).unwrap());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        let inner = store.inner;
+
+        // 新密钥的 id 更大，用于之后的写入
+        fs::write(&path, format!("2:{}\n1:{}\n", hex(2), hex(1))).unwrap();
+        let keyring = Keyring::from_file(&path).unwrap();
+        assert_eq!(keyring.current(), 2);
+
+        let store = EncryptedStore::new(inner, keyring);
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        assert_eq!(
+            store.get_range("t1", "", None, 0, false),
+            Ok(vec![
+                KvPair::new("k1", "v1".into()),
+                KvPair::new("k2", "v2".into())
+            ])
+        );
+
+        // 去掉旧密钥后，旧数据无法解密
+        let store = EncryptedStore::new(store.inner, Keyring::new(2, [2; KEY_LEN]));
+        assert_eq!(store.get("t1", "k2"), Ok(Some("v2".into())));
+        assert!(store.get("t1", "k1").is_err());
+    }
+
+    #[test]
+    fn invalid_key_file_should_be_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.keys");
+
+        for content in ["", "# empty\n", "1:abcd\n", "x:00\n"] {
+            fs::write(&path, content).unwrap();
+            assert!(matches!(
+                Keyring::from_file(&path),
+                Err(KVError::CryptoError(_))
+            ));
+        }
+    }
+}
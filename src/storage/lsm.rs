@@ -0,0 +1,758 @@
+mod bloom;
+mod manifest;
+mod sstable;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use manifest::Manifest;
+use sstable::{Key, SsTable, SsTableBuilder};
+use tracing::warn;
+
+use super::bitcask::record::{self, Record};
+use crate::{
+    KVError, KvPair, MemSnapshot, Snapshot, Storage, StorageIter, Value, WriteOp,
+    storage::take_ordered,
+};
+
+const WAL: &str = "wal.log";
+const SST_EXT: &str = "sst";
+
+/// LsmTree 的配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LsmConfig {
+    /// memtable 超过这个大小后写入 level 0
+    pub memtable_size: usize,
+    /// SSTable 中 data block 的大小
+    pub block_size: usize,
+    /// compaction 时单个 SSTable 的目标大小
+    pub table_size: usize,
+    /// level 0 的 SSTable 达到这个数量时合并到 level 1
+    pub level0_tables: usize,
+    /// level 1 的大小上限
+    pub level1_size: u64,
+    /// 每一层的大小上限是上一层的多少倍
+    pub level_multiplier: u64,
+    /// 每次写入 WAL 后是否 fsync
+    pub sync: bool,
+}
+
+impl Default for LsmConfig {
+    fn default() -> Self {
+        Self {
+            memtable_size: 4 * 1024 * 1024,
+            block_size: 4 * 1024,
+            table_size: 2 * 1024 * 1024,
+            level0_tables: 4,
+            level1_size: 10 * 1024 * 1024,
+            level_multiplier: 10,
+            sync: false,
+        }
+    }
+}
+
+impl LsmConfig {
+    fn max_level_size(&self, level: usize) -> u64 {
+        (1..level).fold(self.level1_size, |size, _| {
+            size.saturating_mul(self.level_multiplier)
+        })
+    }
+}
+
+/// 有序的 memtable，value 为 None 表示删除
+type Memtable = BTreeMap<Key, Option<Vec<u8>>>;
+
+struct Inner {
+    memtable: Memtable,
+    mem_bytes: usize,
+    wal: File,
+    /// levels[0] 中的 SSTable 按写入顺序排列，key 范围可能重叠；其它层按 key 排序，互不重叠
+    levels: Vec<Vec<SsTable>>,
+    next_id: u64,
+}
+
+/// LSM-tree 存储，实现了 Storage trait
+///
+/// 写入先追加到 WAL，再写入有序的 memtable；memtable 写满后落盘成 level 0 的 SSTable，
+/// 每一层超过上限后和下一层合并（leveled compaction）。所有数据按 (table, key) 排序，
+/// 所以 get_iter 按 key 的顺序返回，范围查询和前缀查询只需读取相关的 block。
+pub struct LsmTree {
+    dir: PathBuf,
+    config: LsmConfig,
+    inner: Mutex<Inner>,
+}
+
+impl LsmTree {
+    /// 使用缺省配置打开 path 目录下的 LsmTree，目录不存在则创建
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::open(path, LsmConfig::default()).unwrap()
+    }
+
+    /// 打开 path 目录下的 LsmTree：按 manifest 加载 SSTable，删除不在 manifest 中的文件，重放 WAL
+    pub fn open(path: impl AsRef<Path>, config: LsmConfig) -> Result<Self, KVError> {
+        let dir = path.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let manifest = Manifest::load(&dir)?;
+        let mut live = HashSet::new();
+        let mut levels = Vec::with_capacity(manifest.levels.len().max(1));
+
+        for ids in &manifest.levels {
+            let mut level = Vec::with_capacity(ids.len());
+            for id in ids {
+                level.push(SsTable::open(*id, sst_path(&dir, *id))?);
+                live.insert(*id);
+            }
+            levels.push(level);
+        }
+
+        if levels.is_empty() {
+            levels.push(Vec::new());
+        }
+
+        // flush 或 compaction 中途崩溃时，新的 SSTable 可能还没有写入 manifest
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let orphan = path.extension().is_some_and(|ext| ext == SST_EXT)
+                && path
+                    .file_stem()
+                    .and_then(|s| s.to_str()?.parse().ok())
+                    .is_none_or(|id| !live.contains(&id));
+
+            if orphan {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let wal_path = dir.join(WAL);
+        let (memtable, mem_bytes) = replay_wal(&wal_path)?;
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)?;
+
+        Ok(Self {
+            dir,
+            config,
+            inner: Mutex::new(Inner {
+                memtable,
+                mem_bytes,
+                wal,
+                levels,
+                next_id: manifest.next_id,
+            }),
+        })
+    }
+
+    /// 把 memtable 写入 level 0，必要时触发 compaction
+    pub fn flush(&self) -> Result<(), KVError> {
+        self.lock().flush(&self.dir, &self.config)
+    }
+
+    /// 每一层的 SSTable 数量
+    pub fn level_tables(&self) -> Vec<usize> {
+        self.lock().levels.iter().map(Vec::len).collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for LsmTree {
+    fn drop(&mut self) {
+        if let Err(e) = self.lock().wal.sync_all() {
+            warn!("Failed to sync lsm wal: {}", e);
+        }
+    }
+}
+
+impl Inner {
+    fn insert(&mut self, key: Key, value: Option<Vec<u8>>) {
+        self.mem_bytes += entry_size(&key, &value);
+
+        if let Some(old) = self.memtable.get(&key) {
+            self.mem_bytes -= entry_size(&key, old);
+        }
+
+        self.memtable.insert(key, value);
+    }
+
+    // 从新到旧查找 key：Some(None) 表示 key 已经被删除
+    fn lookup(&self, table: &str, key: &str) -> Result<Option<Option<Vec<u8>>>, KVError> {
+        if let Some(v) = self.memtable.get(&(table.to_owned(), key.to_owned())) {
+            return Ok(Some(v.clone()));
+        }
+
+        for sst in self.levels[0].iter().rev() {
+            if let Some(v) = sst.get(table, key)? {
+                return Ok(Some(v));
+            }
+        }
+
+        for level in &self.levels[1..] {
+            // 找到第一个 last >= (table, key) 的 SSTable，它是这一层唯一可能包含 key 的
+            let i =
+                level.partition_point(|t| (t.last.0.as_str(), t.last.1.as_str()) < (table, key));
+
+            if let Some(sst) = level.get(i)
+                && let Some(v) = sst.get(table, key)?
+            {
+                return Ok(Some(v));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        match self.lookup(table, key)? {
+            Some(Some(v)) => Ok(Some(v.as_slice().try_into()?)),
+            _ => Ok(None),
+        }
+    }
+
+    // 合并所有层的数据，新的覆盖旧的
+    fn scan(
+        &self,
+        table: &str,
+        lower: Bound<&str>,
+        within: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let mut merged = BTreeMap::new();
+
+        for level in self.levels[1..].iter().rev() {
+            for sst in level {
+                merged.extend(sst.scan(table, lower, within)?);
+            }
+        }
+
+        for sst in &self.levels[0] {
+            merged.extend(sst.scan(table, lower, within)?);
+        }
+
+        let start = match lower {
+            Bound::Included(k) => Bound::Included((table.to_owned(), k.to_owned())),
+            Bound::Excluded(k) => Bound::Excluded((table.to_owned(), k.to_owned())),
+            Bound::Unbounded => Bound::Included((table.to_owned(), String::new())),
+        };
+
+        for ((t, k), v) in self.memtable.range((start, Bound::Unbounded)) {
+            if t != table || !within(k) {
+                break;
+            }
+            merged.insert(k.clone(), v.clone());
+        }
+
+        merged
+            .into_iter()
+            .filter_map(|(k, v)| v.map(|v| Ok(KvPair::new(k, v.as_slice().try_into()?))))
+            .collect()
+    }
+
+    // 先找出所有出现过的 table，再去掉 key 已经全部被删除的
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let mut names: BTreeSet<String> = self.memtable.keys().map(|(t, _)| t.clone()).collect();
+
+        for sst in self.levels.iter().flatten() {
+            // 只包含一个 table 的 SSTable 不需要读取数据
+            if sst.first.0 == sst.last.0 {
+                names.insert(sst.first.0.clone());
+            } else {
+                names.extend(sst.entries()?.into_iter().map(|((t, _), _)| t));
+            }
+        }
+
+        let mut tables = Vec::with_capacity(names.len());
+        for name in names {
+            if !self.scan(&name, Bound::Unbounded, &|_| true)?.is_empty() {
+                tables.push(name);
+            }
+        }
+
+        Ok(tables)
+    }
+
+    fn flush(&mut self, dir: &Path, config: &LsmConfig) -> Result<(), KVError> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+
+        let mut builder = SsTableBuilder::new(config.block_size);
+        for (k, v) in &self.memtable {
+            builder.add(k, v.as_deref());
+        }
+
+        let id = self.alloc_id();
+        let sst = builder.finish(id, sst_path(dir, id))?;
+        self.levels[0].push(sst);
+        self.save_manifest(dir)?;
+
+        // memtable 已经落盘，WAL 可以清空
+        self.wal.set_len(0)?;
+        self.memtable.clear();
+        self.mem_bytes = 0;
+
+        self.compact(dir, config)
+    }
+
+    fn compact(&mut self, dir: &Path, config: &LsmConfig) -> Result<(), KVError> {
+        loop {
+            if self.levels[0].len() >= config.level0_tables {
+                let inputs = (0..self.levels[0].len()).collect();
+                self.merge_into(dir, config, 1, inputs)?;
+                continue;
+            }
+
+            let over = (1..self.levels.len()).find(|n| {
+                let size: u64 = self.levels[*n].iter().map(|t| t.size).sum();
+                size > config.max_level_size(*n)
+            });
+
+            match over {
+                // 每次从超限的层中取出 key 最小的 SSTable 合并到下一层
+                Some(n) => self.merge_into(dir, config, n + 1, vec![0])?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // 把 level - 1 层中的 inputs 和 level 层中与之重叠的 SSTable 合并，写入 level 层
+    fn merge_into(
+        &mut self,
+        dir: &Path,
+        config: &LsmConfig,
+        level: usize,
+        inputs: Vec<usize>,
+    ) -> Result<(), KVError> {
+        let src = level - 1;
+
+        if self.levels.len() <= level {
+            self.levels.push(Vec::new());
+        }
+
+        let first = inputs
+            .iter()
+            .map(|i| &self.levels[src][*i].first)
+            .min()
+            .cloned()
+            .unwrap_or_default();
+        let last = inputs
+            .iter()
+            .map(|i| &self.levels[src][*i].last)
+            .max()
+            .cloned()
+            .unwrap_or_default();
+        let overlaps: Vec<usize> = (0..self.levels[level].len())
+            .filter(|i| self.levels[level][*i].overlaps(&first, &last))
+            .collect();
+
+        // 更下层没有数据时，删除记录已经没有需要屏蔽的旧数据了
+        let bottom = self.levels[level + 1..].iter().all(Vec::is_empty);
+
+        // 旧数据先写入，新数据覆盖；inputs 按从旧到新的顺序排列
+        let mut merged = BTreeMap::new();
+        for i in &overlaps {
+            merged.extend(self.levels[level][*i].entries()?);
+        }
+        for i in &inputs {
+            merged.extend(self.levels[src][*i].entries()?);
+        }
+
+        let mut outputs = Vec::new();
+        let mut builder = SsTableBuilder::new(config.block_size);
+
+        for (k, v) in &merged {
+            if bottom && v.is_none() {
+                continue;
+            }
+
+            builder.add(k, v.as_deref());
+
+            if builder.estimated_size() >= config.table_size {
+                let full = std::mem::replace(&mut builder, SsTableBuilder::new(config.block_size));
+                let id = self.alloc_id();
+                outputs.push(full.finish(id, sst_path(dir, id))?);
+            }
+        }
+
+        if !builder.is_empty() {
+            let id = self.alloc_id();
+            outputs.push(builder.finish(id, sst_path(dir, id))?);
+        }
+
+        let mut removed = take_indices(&mut self.levels[src], &inputs);
+        removed.append(&mut take_indices(&mut self.levels[level], &overlaps));
+
+        self.levels[level].append(&mut outputs);
+        self.levels[level].sort_by(|a, b| a.first.cmp(&b.first));
+        self.save_manifest(dir)?;
+
+        for sst in removed {
+            if let Err(e) = sst.remove() {
+                warn!("Failed to remove sstable {:?}: {}", sst.path, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn alloc_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn save_manifest(&self, dir: &Path) -> Result<(), KVError> {
+        let manifest = Manifest {
+            next_id: self.next_id,
+            levels: self
+                .levels
+                .iter()
+                .map(|level| level.iter().map(|t| t.id).collect())
+                .collect(),
+        };
+
+        Ok(manifest.save(dir)?)
+    }
+}
+
+impl Storage for LsmTree {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        self.lock().get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+        Ok(self
+            .write_batch(vec![WriteOp::set(table, key, value)])?
+            .pop()
+            .flatten())
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        Ok(matches!(self.lock().lookup(table, key)?, Some(Some(_))))
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        Ok(self
+            .write_batch(vec![WriteOp::del(table, key)])?
+            .pop()
+            .flatten())
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        self.lock().scan(table, Bound::Unbounded, &|_| true)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+        Ok(StorageIter::new(self.get_all(table)?.into_iter()))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        self.lock().tables()
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        // compaction 会删除旧的 SSTable，所以在锁内把数据合并出来
+        let inner = self.lock();
+        let tables = inner
+            .tables()?
+            .into_iter()
+            .map(|t| {
+                let pairs = inner.scan(&t, Bound::Unbounded, &|_| true)?;
+                Ok((t, pairs))
+            })
+            .collect::<Result<Vec<_>, KVError>>()?;
+
+        Ok(Arc::new(MemSnapshot::new(tables)))
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let within = |k: &str| end.is_none_or(|e| k < e);
+        let pairs = self.lock().scan(table, Bound::Included(start), &within)?;
+
+        Ok(take_ordered(pairs.into_iter(), limit, reverse))
+    }
+
+    fn get_prefix(&self, table: &str, prefix: &str) -> Result<Vec<KvPair>, KVError> {
+        self.lock()
+            .scan(table, Bound::Included(prefix), &|k| k.starts_with(prefix))
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        let mut inner = self.lock();
+        let mut staged: HashMap<Key, Option<Value>> = HashMap::new();
+        let mut old_values = Vec::with_capacity(ops.len());
+        let mut writes = Vec::with_capacity(ops.len());
+
+        // 按顺序计算旧的 value 并编码，全部成功后才写入 WAL
+        for op in ops {
+            let k = (op.table().to_owned(), op.key().to_owned());
+            let old = match staged.get(&k) {
+                Some(v) => v.clone(),
+                None => inner.get(&k.0, &k.1)?,
+            };
+
+            match op {
+                WriteOp::Set { value, .. } => {
+                    staged.insert(k.clone(), Some(value.clone()));
+                    writes.push((k, Some(value.try_into()?)));
+                }
+                WriteOp::Del { .. } if old.is_some() => {
+                    staged.insert(k.clone(), None);
+                    writes.push((k, None));
+                }
+                WriteOp::Del { .. } => {}
+            }
+
+            old_values.push(old);
+        }
+
+        if writes.is_empty() {
+            return Ok(old_values);
+        }
+
+        // WAL 按顺序重放，不需要 seq
+        let mut buf = Vec::new();
+        for (i, ((table, key), value)) in writes.iter().enumerate() {
+            let record = Record {
+                seq: 0,
+                table: table.clone(),
+                key: key.clone(),
+                value: value.clone(),
+                chained: i + 1 < writes.len(),
+            };
+            buf.extend_from_slice(&record.encode());
+        }
+
+        let wal_len = inner.wal.metadata()?.len();
+
+        if let Err(e) = inner.wal.write_all(&buf) {
+            // 去掉写了一半的 batch，否则之后写入的数据在重放时会被一起丢弃
+            let _ = inner.wal.set_len(wal_len);
+            return Err(e.into());
+        }
+
+        if self.config.sync {
+            inner.wal.sync_data()?;
+        }
+
+        for (k, v) in writes {
+            inner.insert(k, v);
+        }
+
+        if inner.mem_bytes >= self.config.memtable_size {
+            inner.flush(&self.dir, &self.config)?;
+        }
+
+        Ok(old_values)
+    }
+}
+
+fn sst_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", id, SST_EXT))
+}
+
+fn entry_size(key: &Key, value: &Option<Vec<u8>>) -> usize {
+    key.0.len() + key.1.len() + value.as_ref().map_or(0, Vec::len)
+}
+
+// 按下标取出元素，其余元素保持原来的顺序
+fn take_indices(level: &mut Vec<SsTable>, indices: &[usize]) -> Vec<SsTable> {
+    let mut i = 0;
+
+    level
+        .extract_if(.., |_| {
+            i += 1;
+            indices.contains(&(i - 1))
+        })
+        .collect()
+}
+
+// 重放 WAL，末尾不完整的 batch 会被截掉
+fn replay_wal(path: &Path) -> Result<(Memtable, usize), KVError> {
+    let mut memtable = Memtable::new();
+    let mut mem_bytes = 0;
+
+    if path.exists() {
+        record::replay(path, |r, _, _| {
+            let key = (r.table, r.key);
+            mem_bytes += entry_size(&key, &r.value);
+            if let Some(old) = memtable.insert(key.clone(), r.value) {
+                mem_bytes -= entry_size(&key, &old);
+            }
+        })?;
+    }
+
+    Ok((memtable, mem_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_config() -> LsmConfig {
+        LsmConfig {
+            memtable_size: 256,
+            block_size: 64,
+            table_size: 512,
+            level0_tables: 2,
+            level1_size: 1024,
+            level_multiplier: 2,
+            sync: false,
+        }
+    }
+
+    fn keys(pairs: Vec<KvPair>) -> Vec<String> {
+        pairs.into_iter().map(|p| p.key).collect()
+    }
+
+    #[test]
+    fn memtable_should_be_recovered_from_wal() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = LsmTree::new(dir.path());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        store.del("t1", "k1").unwrap();
+        drop(store);
+
+        // 模拟写入过程中崩溃，WAL 末尾有半条 record
+        let wal = dir.path().join(WAL);
+        let partial = Record::put(0, "t1", "k3", b"v3".to_vec()).encode();
+        OpenOptions::new()
+            .append(true)
+            .open(&wal)
+            .unwrap()
+            .write_all(&partial[..partial.len() - 1])
+            .unwrap();
+
+        let store = LsmTree::new(dir.path());
+        assert_eq!(store.level_tables(), vec![0]);
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t1", "k2"), Ok(Some("v2".into())));
+        assert_eq!(store.get("t1", "k3"), Ok(None));
+    }
+
+    #[test]
+    fn incomplete_batch_should_be_dropped_from_wal() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = LsmTree::new(dir.path());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store
+            .write_batch(vec![
+                WriteOp::del("t1", "k1"),
+                WriteOp::set("t1", "k2", "v2".into()),
+            ])
+            .unwrap();
+        drop(store);
+
+        let wal = dir.path().join(WAL);
+        let len = fs::metadata(&wal).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&wal)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let store = LsmTree::new(dir.path());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn compaction_should_keep_latest_values() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = LsmTree::open(dir.path(), small_config()).unwrap();
+        for round in 0..10 {
+            for i in 0..50 {
+                store
+                    .set("t1", format!("k{:02}", i), (round * 100 + i).into())
+                    .unwrap();
+            }
+        }
+        for i in (0..50).step_by(5) {
+            store.del("t1", &format!("k{:02}", i)).unwrap();
+        }
+
+        // 数据应该已经被合并到多层
+        let levels = store.level_tables();
+        assert!(levels.len() > 2, "{:?}", levels);
+        assert!(levels[0] < small_config().level0_tables);
+
+        let check = |store: &LsmTree| {
+            for i in 0..50 {
+                let expected = (i % 5 != 0).then(|| Value::from(900 + i));
+                assert_eq!(store.get("t1", &format!("k{:02}", i)), Ok(expected));
+            }
+            assert_eq!(store.get_all("t1").unwrap().len(), 40);
+        };
+
+        check(&store);
+        drop(store);
+
+        let store = LsmTree::open(dir.path(), small_config()).unwrap();
+        check(&store);
+
+        // 不在 manifest 中的 SSTable 都已经被删除
+        let ssts = fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().unwrap_or_default() == SST_EXT)
+            .count();
+        assert_eq!(ssts, store.level_tables().iter().sum::<usize>());
+    }
+
+    #[test]
+    fn scan_should_return_sorted_keys() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = LsmTree::open(dir.path(), small_config()).unwrap();
+        for i in (0..30).rev() {
+            store.set("t1", format!("k{:02}", i), i.into()).unwrap();
+            store.set("t2", format!("k{:02}", i), i.into()).unwrap();
+        }
+        store.flush().unwrap();
+
+        // 一部分数据只在 memtable 中
+        store.set("t1", "k05".into(), "new".into()).unwrap();
+        store.del("t1", "k06").unwrap();
+        store.set("t1", "k30".into(), 30.into()).unwrap();
+
+        let all = keys(store.get_iter("t1").unwrap().collect());
+        let mut sorted = all.clone();
+        sorted.sort();
+        assert_eq!(all, sorted);
+        assert_eq!(all.len(), 30);
+
+        assert_eq!(
+            keys(store.get_range("t1", "k04", Some("k08"), 0, false).unwrap()),
+            vec!["k04", "k05", "k07"]
+        );
+        assert_eq!(
+            store.get_range("t1", "k05", Some("k06"), 0, true).unwrap(),
+            vec![KvPair::new("k05", "new".into())]
+        );
+        assert_eq!(
+            keys(store.get_range("t1", "k25", None, 2, true).unwrap()),
+            vec!["k30", "k29"]
+        );
+        assert_eq!(keys(store.get_prefix("t1", "k3").unwrap()), vec!["k30"]);
+        assert_eq!(store.get_prefix("t1", "k0").unwrap().len(), 9);
+        assert!(store.get_prefix("t3", "k").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+//! SSTable 的 bloom filter
+//!
+//! 编码格式为 `| k: u8 | bits |`，使用 double hashing 从一个 64 位 hash 派生出 k 个位置。
+
+/// 每个 key 占用的 bit 数，假阳性率约为 1%
+const BITS_PER_KEY: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Bloom {
+    bits: Vec<u8>,
+    k: u8,
+}
+
+impl Bloom {
+    /// 用所有 key 的 hash 创建 bloom filter
+    pub fn build(hashes: &[u64]) -> Self {
+        let bytes = (hashes.len() * BITS_PER_KEY).div_ceil(8).max(8);
+        // k = bits_per_key * ln2
+        let k = ((BITS_PER_KEY as f64) * 0.69).round() as u8;
+        let mut bloom = Self {
+            bits: vec![0; bytes],
+            k: k.clamp(1, 30),
+        };
+
+        for h in hashes {
+            for pos in bloom.positions(*h).collect::<Vec<_>>() {
+                bloom.bits[pos / 8] |= 1 << (pos % 8);
+            }
+        }
+
+        bloom
+    }
+
+    /// 返回 false 时 key 一定不存在
+    pub fn may_contain(&self, table: &str, key: &str) -> bool {
+        self.positions(hash(table, key))
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.bits.len() + 1);
+        buf.push(self.k);
+        buf.extend_from_slice(&self.bits);
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (&k, bits) = data.split_first()?;
+
+        (k > 0 && !bits.is_empty()).then(|| Self {
+            bits: bits.to_vec(),
+            k,
+        })
+    }
+
+    fn positions(&self, h: u64) -> impl Iterator<Item = usize> {
+        let (h1, h2) = (h as u32, (h >> 32) as u32 | 1);
+        let m = self.bits.len() as u64 * 8;
+
+        (0..self.k as u32).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) as u64 % m) as usize)
+    }
+}
+
+/// FNV-1a，table 和 key 之间插入 0 分隔，避免 ("ab", "c") 和 ("a", "bc") 冲突
+pub(crate) fn hash(table: &str, key: &str) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+
+    for b in table.bytes().chain([0]).chain(key.bytes()) {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+
+    // 打散低位，FNV 的低 32 位分布不够均匀
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_should_not_have_false_negatives() {
+        let hashes: Vec<_> = (0..1000).map(|i| hash("t1", &format!("k{}", i))).collect();
+        let bloom = Bloom::decode(&Bloom::build(&hashes).encode()).unwrap();
+        assert!((0..1000).all(|i| bloom.may_contain("t1", &format!("k{}", i))));
+
+        // 假阳性率应该在 1% 左右
+        let false_positives = (0..10000)
+            .filter(|i| bloom.may_contain("t2", &format!("k{}", i)))
+            .count();
+        assert!(false_positives < 300, "{}", false_positives);
+    }
+}
@@ -0,0 +1,90 @@
+//! manifest 记录每一层有哪些 SSTable
+//!
+//! 文本格式，第一行是下一个可用的文件 id，之后每行是一个 SSTable 的 `层 id`，同一层内按顺序排列。
+//! 每次修改都重写整个文件：先写临时文件再 rename，保证 manifest 要么是旧的要么是新的。
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+const MANIFEST: &str = "MANIFEST";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    pub next_id: u64,
+    /// levels[n] 是第 n 层的 SSTable id
+    pub levels: Vec<Vec<u64>>,
+}
+
+impl Manifest {
+    /// 读取 dir 下的 manifest，不存在时返回空的 manifest
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let content = match fs::read_to_string(dir.join(MANIFEST)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        Self::parse(&content)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Corrupted lsm manifest"))
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let mut content = format!("{}\n", self.next_id);
+        for (level, ids) in self.levels.iter().enumerate() {
+            for id in ids {
+                content.push_str(&format!("{} {}\n", level, id));
+            }
+        }
+
+        let tmp = dir.join(format!("{}.tmp", MANIFEST));
+        let mut file = File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(tmp, dir.join(MANIFEST))
+    }
+
+    fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        let mut manifest = Self {
+            next_id: lines.next()?.parse().ok()?,
+            levels: Vec::new(),
+        };
+
+        for line in lines {
+            let (level, id) = line.split_once(' ')?;
+            let (level, id): (usize, u64) = (level.parse().ok()?, id.parse().ok()?);
+
+            if manifest.levels.len() <= level {
+                manifest.levels.resize(level + 1, Vec::new());
+            }
+            manifest.levels[level].push(id);
+        }
+
+        Some(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_should_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Manifest::load(dir.path()).unwrap(), Manifest::default());
+
+        let manifest = Manifest {
+            next_id: 9,
+            levels: vec![vec![7, 8], vec![], vec![3, 1]],
+        };
+        manifest.save(dir.path()).unwrap();
+
+        assert_eq!(Manifest::load(dir.path()).unwrap(), manifest);
+
+        fs::write(dir.path().join(MANIFEST), "9\nbad line\n").unwrap();
+        assert!(Manifest::load(dir.path()).is_err());
+    }
+}
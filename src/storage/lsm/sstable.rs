@@ -0,0 +1,513 @@
+//! SSTable 文件格式
+//!
+//! ```text
+//! | data block | ... | data block | index block | bloom | footer |
+//! ```
+//!
+//! data block 由按 (table, key) 排序的 entry 组成，末尾是整个 block 的 crc32：
+//!
+//! ```text
+//! | table_len: u32 | key_len: u32 | value_len: u32 | table | key | value |
+//! ```
+//!
+//! value_len 为 TOMBSTONE 时表示删除。index block 记录每个 data block 的第一个 key 和位置，以及整个文件的最后一个 key：
+//!
+//! ```text
+//! | count: u32 | (offset: u64 | len: u32 | table_len: u32 | key_len: u32 | table | key) * count |
+//! | table_len: u32 | key_len: u32 | table | key | crc: u32 |
+//! ```
+//!
+//! footer 固定 40 字节：`| index_offset: u64 | index_len: u64 | bloom_offset: u64 | bloom_len: u64 | magic: u64 |`。
+
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use super::bloom::{self, Bloom};
+use crate::storage::bitcask::record::TOMBSTONE;
+
+const MAGIC: u64 = 0x4b56_5353_5441_424c;
+const FOOTER_LEN: usize = 40;
+
+/// SSTable 中的 key：(table, key)，按 table 排序后再按 key 排序
+pub(crate) type Key = (String, String);
+
+/// SSTable 中的一条数据，value 为 None 表示删除
+pub(crate) type Entry = (Key, Option<Vec<u8>>);
+
+#[derive(Debug, Clone)]
+struct BlockHandle {
+    first: Key,
+    offset: u64,
+    len: u32,
+}
+
+/// 按顺序写入 entry，生成 SSTable 文件
+pub(crate) struct SsTableBuilder {
+    block_size: usize,
+    data: Vec<u8>,
+    block: Vec<u8>,
+    block_first: Option<Key>,
+    index: Vec<BlockHandle>,
+    hashes: Vec<u64>,
+    last: Option<Key>,
+}
+
+/// 打开的 SSTable，index 和 bloom filter 常驻内存
+#[derive(Debug)]
+pub(crate) struct SsTable {
+    pub id: u64,
+    pub path: PathBuf,
+    pub size: u64,
+    pub first: Key,
+    pub last: Key,
+    file: File,
+    index: Vec<BlockHandle>,
+    bloom: Bloom,
+}
+
+impl SsTableBuilder {
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            data: Vec::new(),
+            block: Vec::new(),
+            block_first: None,
+            index: Vec::new(),
+            hashes: Vec::new(),
+            last: None,
+        }
+    }
+
+    /// 添加一条 entry，key 必须比之前添加的都大
+    pub fn add(&mut self, key: &Key, value: Option<&[u8]>) {
+        debug_assert!(self.last.as_ref().is_none_or(|last| last < key));
+
+        let value_len = value.map_or(TOMBSTONE, |v| v.len() as u32);
+
+        self.block
+            .extend_from_slice(&(key.0.len() as u32).to_le_bytes());
+        self.block
+            .extend_from_slice(&(key.1.len() as u32).to_le_bytes());
+        self.block.extend_from_slice(&value_len.to_le_bytes());
+        self.block.extend_from_slice(key.0.as_bytes());
+        self.block.extend_from_slice(key.1.as_bytes());
+        self.block.extend_from_slice(value.unwrap_or_default());
+
+        self.hashes.push(bloom::hash(&key.0, &key.1));
+        self.block_first.get_or_insert_with(|| key.clone());
+        self.last = Some(key.clone());
+
+        if self.block.len() >= self.block_size {
+            self.finish_block();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last.is_none()
+    }
+
+    /// 当前文件大小的估计值
+    pub fn estimated_size(&self) -> usize {
+        self.data.len() + self.block.len()
+    }
+
+    /// 写入文件并打开，builder 不能为空
+    pub fn finish(mut self, id: u64, path: impl Into<PathBuf>) -> io::Result<SsTable> {
+        self.finish_block();
+
+        let last = self.last.take().expect("SsTableBuilder is empty");
+        let mut buf = std::mem::take(&mut self.data);
+
+        let index_offset = buf.len() as u64;
+        let mut index = Vec::new();
+        index.extend_from_slice(&(self.index.len() as u32).to_le_bytes());
+        for h in &self.index {
+            index.extend_from_slice(&h.offset.to_le_bytes());
+            index.extend_from_slice(&h.len.to_le_bytes());
+            encode_key(&mut index, &h.first);
+        }
+        encode_key(&mut index, &last);
+        let crc = crc32fast::hash(&index);
+        index.extend_from_slice(&crc.to_le_bytes());
+        buf.extend_from_slice(&index);
+
+        let bloom_offset = buf.len() as u64;
+        let bloom = Bloom::build(&self.hashes).encode();
+        buf.extend_from_slice(&bloom);
+
+        buf.extend_from_slice(&index_offset.to_le_bytes());
+        buf.extend_from_slice(&(index.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&bloom_offset.to_le_bytes());
+        buf.extend_from_slice(&(bloom.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&MAGIC.to_le_bytes());
+
+        let path = path.into();
+        let mut file = File::create(&path)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+
+        SsTable::open(id, path)
+    }
+
+    fn finish_block(&mut self) {
+        let Some(first) = self.block_first.take() else {
+            return;
+        };
+
+        let crc = crc32fast::hash(&self.block);
+        self.block.extend_from_slice(&crc.to_le_bytes());
+        self.index.push(BlockHandle {
+            first,
+            offset: self.data.len() as u64,
+            len: self.block.len() as u32,
+        });
+        self.data.append(&mut self.block);
+    }
+}
+
+impl SsTable {
+    /// 打开 SSTable 文件，读取 index 和 bloom filter
+    pub fn open(id: u64, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+
+        if size < FOOTER_LEN as u64 {
+            return Err(corrupted(&path));
+        }
+
+        let footer = read_at(&file, size - FOOTER_LEN as u64, FOOTER_LEN)?;
+        let [index_offset, index_len, bloom_offset, bloom_len, magic] =
+            std::array::from_fn(|i| u64_at(&footer, i * 8));
+
+        if magic != MAGIC || bloom_offset + bloom_len + FOOTER_LEN as u64 != size {
+            return Err(corrupted(&path));
+        }
+
+        let index = read_at(&file, index_offset, index_len as usize)?;
+        let (index, last) = decode_index(&index).ok_or_else(|| corrupted(&path))?;
+        let bloom = Bloom::decode(&read_at(&file, bloom_offset, bloom_len as usize)?)
+            .ok_or_else(|| corrupted(&path))?;
+        let first = index.first().ok_or_else(|| corrupted(&path))?.first.clone();
+
+        Ok(Self {
+            id,
+            path,
+            size,
+            first,
+            last,
+            file,
+            index,
+            bloom,
+        })
+    }
+
+    /// 查找 key：Some(None) 表示 key 在这个 SSTable 中被删除
+    pub fn get(&self, table: &str, key: &str) -> io::Result<Option<Option<Vec<u8>>>> {
+        if !self.bloom.may_contain(table, key)
+            || cmp_key(&self.first, table, key) == Ordering::Greater
+            || cmp_key(&self.last, table, key) == Ordering::Less
+        {
+            return Ok(None);
+        }
+
+        let block = self.block_for(table, key);
+
+        Ok(self
+            .read_block(block)?
+            .into_iter()
+            .find(|(k, _)| k.0 == table && k.1 == key)
+            .map(|(_, v)| v))
+    }
+
+    /// 按顺序返回 table 中从 lower 开始、满足 within 的 entry；within 第一次返回 false 时停止
+    pub fn scan(
+        &self,
+        table: &str,
+        lower: Bound<&str>,
+        within: &dyn Fn(&str) -> bool,
+    ) -> io::Result<Vec<(String, Option<Vec<u8>>)>> {
+        let mut res = Vec::new();
+
+        if self.last.0.as_str() < table || self.first.0.as_str() > table {
+            return Ok(res);
+        }
+
+        let start = match lower {
+            Bound::Included(k) | Bound::Excluded(k) => k,
+            Bound::Unbounded => "",
+        };
+
+        for block in self.block_for(table, start)..self.index.len() {
+            for ((t, k), v) in self.read_block(block)? {
+                match t.as_str().cmp(table) {
+                    Ordering::Less => continue,
+                    Ordering::Greater => return Ok(res),
+                    Ordering::Equal => {}
+                }
+
+                let after_lower = match lower {
+                    Bound::Included(l) => k.as_str() >= l,
+                    Bound::Excluded(l) => k.as_str() > l,
+                    Bound::Unbounded => true,
+                };
+
+                if !after_lower {
+                    continue;
+                }
+
+                if !within(&k) {
+                    return Ok(res);
+                }
+
+                res.push((k, v));
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// 读取所有 entry，用于 compaction
+    pub fn entries(&self) -> io::Result<Vec<Entry>> {
+        let mut res = Vec::new();
+
+        for block in 0..self.index.len() {
+            res.append(&mut self.read_block(block)?);
+        }
+
+        Ok(res)
+    }
+
+    /// key 范围是否和 [first, last] 有交集
+    pub fn overlaps(&self, first: &Key, last: &Key) -> bool {
+        &self.first <= last && &self.last >= first
+    }
+
+    /// 删除文件
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+
+    // 可能包含 (table, key) 的 block：第一个 key 不大于 (table, key) 的最后一个 block
+    fn block_for(&self, table: &str, key: &str) -> usize {
+        self.index
+            .partition_point(|h| cmp_key(&h.first, table, key) != Ordering::Greater)
+            .saturating_sub(1)
+    }
+
+    fn read_block(&self, block: usize) -> io::Result<Vec<Entry>> {
+        let h = &self.index[block];
+        let data = read_at(&self.file, h.offset, h.len as usize)?;
+
+        decode_block(&data).ok_or_else(|| corrupted(&self.path))
+    }
+}
+
+fn cmp_key(k: &Key, table: &str, key: &str) -> Ordering {
+    k.0.as_str().cmp(table).then(k.1.as_str().cmp(key))
+}
+
+fn encode_key(buf: &mut Vec<u8>, key: &Key) {
+    buf.extend_from_slice(&(key.0.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(key.1.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key.0.as_bytes());
+    buf.extend_from_slice(key.1.as_bytes());
+}
+
+fn decode_block(data: &[u8]) -> Option<Vec<Entry>> {
+    let (data, crc) = data.split_at_checked(data.len().checked_sub(4)?)?;
+
+    if crc32fast::hash(data) != u32::from_le_bytes(crc.try_into().ok()?) {
+        return None;
+    }
+
+    let mut reader = Reader(data);
+    let mut entries = Vec::new();
+
+    while !reader.0.is_empty() {
+        let table_len = reader.u32()? as usize;
+        let key_len = reader.u32()? as usize;
+        let value_len = reader.u32()?;
+        let key = (reader.string(table_len)?, reader.string(key_len)?);
+        let value = match value_len {
+            TOMBSTONE => None,
+            n => Some(reader.bytes(n as usize)?.to_vec()),
+        };
+
+        entries.push((key, value));
+    }
+
+    Some(entries)
+}
+
+fn decode_index(data: &[u8]) -> Option<(Vec<BlockHandle>, Key)> {
+    let (data, crc) = data.split_at_checked(data.len().checked_sub(4)?)?;
+
+    if crc32fast::hash(data) != u32::from_le_bytes(crc.try_into().ok()?) {
+        return None;
+    }
+
+    let mut reader = Reader(data);
+    let count = reader.u32()? as usize;
+    let mut index = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let offset = reader.u64()?;
+        let len = reader.u32()?;
+        let first = reader.key()?;
+        index.push(BlockHandle { first, offset, len });
+    }
+
+    Some((index, reader.key()?))
+}
+
+// 从字节流中依次读取字段，长度不够时返回 None
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (head, tail) = self.0.split_at_checked(n)?;
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn string(&mut self, n: usize) -> Option<String> {
+        String::from_utf8(self.bytes(n)?.to_vec()).ok()
+    }
+
+    fn key(&mut self) -> Option<Key> {
+        let table_len = self.u32()? as usize;
+        let key_len = self.u32()? as usize;
+        Some((self.string(table_len)?, self.string(key_len)?))
+    }
+}
+
+fn read_at(mut file: &File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+
+    Ok(buf)
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+fn corrupted(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Corrupted sstable {:?}", path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(table: &str, key: &str) -> Key {
+        (table.into(), key.into())
+    }
+
+    fn build(dir: &Path, entries: &[Entry]) -> SsTable {
+        // block 很小，保证测试中有多个 block
+        let mut builder = SsTableBuilder::new(64);
+        for (k, v) in entries {
+            builder.add(k, v.as_deref());
+        }
+        builder.finish(1, dir.join("1.sst")).unwrap()
+    }
+
+    #[test]
+    fn sstable_get_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries: Vec<Entry> = (0..100)
+            .map(|i| (key("t1", &format!("k{:03}", i)), Some(vec![i as u8])))
+            .chain([(key("t2", "deleted"), None)])
+            .collect();
+        let sst = build(dir.path(), &entries);
+
+        assert!(sst.index.len() > 1);
+        assert_eq!(sst.first, key("t1", "k000"));
+        assert_eq!(sst.last, key("t2", "deleted"));
+
+        for i in 0..100 {
+            assert_eq!(
+                sst.get("t1", &format!("k{:03}", i)).unwrap(),
+                Some(Some(vec![i as u8]))
+            );
+        }
+        assert_eq!(sst.get("t2", "deleted").unwrap(), Some(None));
+        assert_eq!(sst.get("t1", "k100").unwrap(), None);
+        assert_eq!(sst.get("t0", "k000").unwrap(), None);
+
+        let sst = SsTable::open(1, dir.path().join("1.sst")).unwrap();
+        assert_eq!(sst.entries().unwrap(), entries);
+    }
+
+    #[test]
+    fn sstable_scan_should_respect_bounds() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries: Vec<Entry> = ["t0", "t1", "t2"]
+            .iter()
+            .flat_map(|t| (0..30).map(move |i| (key(t, &format!("k{:02}", i)), Some(vec![]))))
+            .collect();
+        let sst = build(dir.path(), &entries);
+
+        let keys = |lower, within: &dyn Fn(&str) -> bool| -> Vec<String> {
+            sst.scan("t1", lower, within)
+                .unwrap()
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect()
+        };
+
+        assert_eq!(keys(Bound::Unbounded, &|_| true).len(), 30);
+        assert_eq!(
+            keys(Bound::Included("k10"), &|k| k < "k13"),
+            vec!["k10", "k11", "k12"]
+        );
+        assert_eq!(
+            keys(Bound::Excluded("k10"), &|k| k <= "k12"),
+            vec!["k11", "k12"]
+        );
+        assert_eq!(
+            keys(Bound::Included("k2"), &|k| k.starts_with("k2")).len(),
+            10
+        );
+        assert!(
+            sst.scan("t3", Bound::Unbounded, &|_| true)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn corrupted_sstable_should_be_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let sst = build(dir.path(), &[(key("t1", "k1"), Some(b"v1".to_vec()))]);
+
+        let mut data = fs::read(&sst.path).unwrap();
+        data[0] ^= 0xff;
+        fs::write(&sst.path, &data).unwrap();
+        assert!(sst.get("t1", "k1").is_err());
+
+        data.truncate(data.len() - 1);
+        fs::write(&sst.path, &data).unwrap();
+        assert!(SsTable::open(1, &sst.path).is_err());
+    }
+}
@@ -1,10 +1,61 @@
-use crate::{KVError, KvPair, Storage, StorageIter, Value};
+mod eviction;
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{Evicted, KVError, KvPair, Snapshot, Storage, Value, WriteOp, storage::Capture};
 use dashmap::{DashMap, mapref::one::Ref};
+use eviction::Evictor;
+pub use eviction::{EvictionConfig, EvictionPolicy};
+
+/// get_iter 每次读取和修正的 key 的数量
+const ITER_PAGE_SIZE: usize = 1024;
+/// 按 table 分段的 gate 的数量
+const GATE_STRIPES: usize = 64;
 
 /// 使用 DashMap 构建的 MemTable，实现了 Storage trait
-#[derive(Debug, Clone, Default)]
+#[derive(Debug)]
 pub struct MemTable {
     tables: DashMap<String, DashMap<String, Value>>,
+    evictor: Option<Mutex<Evictor>>,
+    /// 是否调用过 expire，没有 TTL 时读操作不需要获取 evictor 的锁来检查过期
+    ttl_used: AtomicBool,
+    /// 按 table 分段的锁：单个操作持有 table 对应的读锁，write_batch 持有它涉及的 table 的写锁，
+    /// 保证其它操作看不到执行了一半的 batch，写入不同 table 的 batch 之间互不影响
+    gates: Vec<RwLock<()>>,
+    /// snapshot 复制数据期间被修改的 key 原来的 value
+    capture: Capture,
+}
+
+impl Clone for MemTable {
+    fn clone(&self) -> Self {
+        Self {
+            tables: self.tables.clone(),
+            evictor: self.evictor.as_ref().map(|e| Mutex::new(lock(e).clone())),
+            ttl_used: AtomicBool::new(self.ttl_used.load(Ordering::Acquire)),
+            gates: new_gates(),
+            capture: Capture::default(),
+        }
+    }
+}
+
+impl Default for MemTable {
+    fn default() -> Self {
+        Self {
+            tables: DashMap::new(),
+            evictor: None,
+            ttl_used: AtomicBool::new(false),
+            gates: new_gates(),
+            capture: Capture::default(),
+        }
+    }
 }
 
 impl MemTable {
@@ -13,6 +64,43 @@ impl MemTable {
         Self::default()
     }
 
+    /// 创建一个有内存限制的 MemTable，超出预算时按 config.policy 淘汰数据
+    pub fn with_eviction(config: EvictionConfig) -> Self {
+        Self {
+            evictor: Some(Mutex::new(Evictor::new(config))),
+            ..Self::default()
+        }
+    }
+
+    /// 内存限制的配置，没有限制时返回 None
+    pub fn eviction_config(&self) -> Option<EvictionConfig> {
+        self.evictor.as_ref().map(|e| lock(e).config())
+    }
+
+    /// 近似的内存占用（key 和 Value 编码后的字节数），没有内存限制时不做统计，返回 None
+    pub fn used_bytes(&self) -> Option<usize> {
+        self.evictor.as_ref().map(|e| lock(e).used())
+    }
+
+    /// 为 key 设置过期时间，过期后的 key 视为不存在；再次 set 会清除 TTL
+    ///
+    /// TTL 由内存限制模块记录，所以只有通过 with_eviction 创建的 MemTable 才支持。
+    /// key 不存在时返回 false。
+    pub fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KVError> {
+        let Some(evictor) = &self.evictor else {
+            return Err(KVError::InvalidCommand(
+                "TTL is only supported on MemTable with eviction".into(),
+            ));
+        };
+
+        let _gate = self.read_gate(table);
+        let mut evictor = lock(evictor);
+        self.ttl_used.store(true, Ordering::Release);
+        let exists = self.get_or_create_table(table).contains_key(key);
+
+        Ok(exists && evictor.set_expire(table, key, Instant::now() + ttl))
+    }
+
     /// 如果名为 name 的 hash table 不存在，则创建，否则返回
     fn get_or_create_table(&'_ self, name: &str) -> Ref<'_, String, DashMap<String, Value>> {
         match self.tables.get(name) {
@@ -20,34 +108,206 @@ impl MemTable {
             None => self.tables.entry(name.into()).or_default().downgrade(),
         }
     }
+
+    fn read_gate(&self, table: &str) -> RwLockReadGuard<'_, ()> {
+        read(&self.gates[gate(table)])
+    }
+
+    fn write_gate(&self, table: &str) -> RwLockWriteGuard<'_, ()> {
+        write(&self.gates[gate(table)])
+    }
+
+    // 按固定的顺序获取多个 gate，避免死锁
+    fn write_gates<'a>(
+        &self,
+        tables: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<RwLockWriteGuard<'_, ()>> {
+        let mut gates: Vec<_> = tables.into_iter().map(gate).collect();
+        gates.sort_unstable();
+        gates.dedup();
+
+        gates.into_iter().map(|i| write(&self.gates[i])).collect()
+    }
+
+    // 需要看到所有 table 一致的状态时（tables、snapshot）使用
+    fn read_all_gates(&self) -> Vec<RwLockReadGuard<'_, ()>> {
+        self.gates.iter().map(read).collect()
+    }
+
+    fn write_all_gates(&self) -> Vec<RwLockWriteGuard<'_, ()>> {
+        self.gates.iter().map(write).collect()
+    }
+
+    // 所有对数据的修改都通过 put/take，让正在复制的快照记下原来的 value
+    fn put(&self, table: &str, key: String, value: Value) -> Option<Value> {
+        let old = self.get_or_create_table(table).insert(key.clone(), value);
+        self.capture.record(table, &key, old.as_ref());
+        old
+    }
+
+    fn take(&self, table: &str, key: &str) -> Option<Value> {
+        let old = self.tables.get(table)?.remove(key).map(|(_, v)| v);
+        self.capture.record(table, key, old.as_ref());
+        old
+    }
+
+    // 写入 key，返回旧的 value
+    fn insert(&self, table: &str, key: String, value: Value) -> Option<Value> {
+        let Some(evictor) = &self.evictor else {
+            return self.put(table, key, value);
+        };
+
+        let mut evictor = lock(evictor);
+        let expired = self.purge_if_expired(&mut evictor, table, &key);
+
+        evictor.insert(table, &key, &value);
+        let old = self.put(table, key.clone(), value);
+        self.evict(&mut evictor, table, &key);
+
+        if expired { None } else { old }
+    }
+
+    // 删除 key，返回之前的 value
+    fn remove(&self, table: &str, key: &str) -> Option<Value> {
+        let Some(evictor) = &self.evictor else {
+            return self.take(table, key);
+        };
+
+        let mut evictor = lock(evictor);
+
+        if self.purge_if_expired(&mut evictor, table, key) {
+            return None;
+        }
+
+        evictor.remove(table, key);
+
+        self.take(table, key)
+    }
+
+    // 淘汰时只持有写入的 table 的 gate，可能正在删除其它 table 的 key；
+    // 持有 evictor 的锁可以等这些删除记录到 capture 中
+    fn settled(&self) -> Option<MutexGuard<'_, Evictor>> {
+        self.evictor.as_ref().map(lock)
+    }
+
+    // 可能有 key 设置了 TTL 时，返回 evictor 的锁，用来清除过期的 key
+    fn expiring(&self) -> Option<MutexGuard<'_, Evictor>> {
+        let evictor = self.evictor.as_ref()?;
+
+        self.ttl_used.load(Ordering::Acquire).then(|| lock(evictor))
+    }
+
+    // 如果 key 已经过期，就删除它并返回 true
+    fn purge_if_expired(&self, evictor: &mut Evictor, table: &str, key: &str) -> bool {
+        if !evictor.expired(table, key) {
+            return false;
+        }
+
+        self.take(table, key);
+        evictor.remove(table, key);
+        true
+    }
+
+    // 删除 table 中所有已经过期的 key
+    fn purge_expired(&self, evictor: &mut Evictor, table: &str) {
+        for key in evictor.expired_keys(table) {
+            self.purge_if_expired(evictor, table, &key);
+        }
+    }
+
+    // 清除所有 table 中过期的 key，返回所有 table 的名字
+    fn purge_all_expired(&self) -> Vec<String> {
+        let names: Vec<_> = self.tables.iter().map(|t| t.key().clone()).collect();
+
+        if let Some(mut evictor) = self.expiring() {
+            for name in &names {
+                self.purge_expired(&mut evictor, name);
+            }
+        }
+
+        names
+    }
+
+    // 内存超出预算时不断淘汰数据，刚写入的 (table, key) 不会被淘汰
+    fn evict(&self, evictor: &mut Evictor, table: &str, key: &str) {
+        while evictor.over_budget() {
+            let Some((t, k)) = evictor.victim(table, key) else {
+                break;
+            };
+
+            evictor.remove(&t, &k);
+
+            if let Some(v) = self.take(&t, &k) {
+                evictor.push_evicted(Evicted::new(t, k, v));
+            }
+        }
+    }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
-        let table = self.get_or_create_table(table);
+        let _gate = self.read_gate(table);
+
+        let Some(evictor) = &self.evictor else {
+            let table = self.get_or_create_table(table);
+            return Ok(table.get(key).map(|v| v.value().clone()));
+        };
 
-        Ok(table.get(key).map(|v| v.value().clone()))
+        let mut expiring = self.expiring();
+        if let Some(evictor) = expiring.as_mut()
+            && self.purge_if_expired(evictor, table, key)
+        {
+            return Ok(None);
+        }
+
+        let v = self
+            .get_or_create_table(table)
+            .get(key)
+            .map(|v| v.value().clone());
+
+        // 访问记录只是淘汰时的参考，锁被占用时跳过这次记录，读操作之间不必互相等待
+        if v.is_some()
+            && let Some(mut evictor) = expiring.or_else(|| try_lock(evictor))
+        {
+            evictor.touch(table, key);
+        }
+
+        Ok(v)
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
-        let table = self.get_or_create_table(table);
+        let _gate = self.read_gate(table);
 
-        Ok(table.insert(key, value))
+        Ok(self.insert(table, key, value))
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        let _gate = self.read_gate(table);
+
+        if let Some(mut evictor) = self.expiring()
+            && self.purge_if_expired(&mut evictor, table, key)
+        {
+            return Ok(false);
+        }
+
         let table = self.get_or_create_table(table);
 
         Ok(table.contains_key(key))
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
-        let table = self.get_or_create_table(table);
+        let _gate = self.read_gate(table);
 
-        Ok(table.remove(key).map(|(_, v)| v))
+        Ok(self.remove(table, key))
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        let _gate = self.read_gate(table);
+
+        if let Some(mut evictor) = self.expiring() {
+            self.purge_expired(&mut evictor, table);
+        }
+
         let table = self.get_or_create_table(table);
 
         Ok(table
@@ -57,9 +317,180 @@ impl Storage for MemTable {
     }
 
     fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
-        let table = self.get_or_create_table(table).clone();
+        Ok(TableIter::new(self, table))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let _gates = self.read_all_gates();
+        let mut names = self.purge_all_expired();
+
+        // get 等操作会创建空的 table，这里只返回有数据的
+        names.retain(|name| self.tables.get(name).is_some_and(|t| !t.is_empty()));
+        names.sort();
+
+        Ok(names)
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        // 只在开始和结束时持有写锁，复制期间的写操作由 capture 记录，见 Capture
+        let id = {
+            let _gates = self.write_all_gates();
+            self.purge_all_expired();
+            self.capture.start()
+        };
+
+        let tables: Vec<_> = self
+            .tables
+            .iter()
+            .map(|t| {
+                let pairs = t
+                    .iter()
+                    .map(|v| KvPair::new(v.key(), v.value().clone()))
+                    .collect();
+                (t.key().clone(), pairs)
+            })
+            .collect();
+
+        let _gates = self.write_all_gates();
+        let snapshot = self.capture.finish(id, Ok(tables))?;
 
-        Ok(StorageIter::new(table.into_iter()))
+        Ok(Arc::new(snapshot))
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        // 内存中的写操作不会失败，持有涉及的 table 的写锁依次执行即可
+        let _gates = self.write_gates(ops.iter().map(WriteOp::table));
+
+        Ok(ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Set { table, key, value } => self.insert(&table, key, value),
+                WriteOp::Del { table, key } => self.remove(&table, &key),
+            })
+            .collect())
+    }
+
+    fn drain_evicted(&self) -> Vec<Evicted> {
+        self.evictor
+            .as_ref()
+            .map(|e| lock(e).drain_evicted())
+            .unwrap_or_default()
+    }
+}
+
+/// MemTable::get_iter 返回的迭代器，看到的是创建时 table 中的数据
+///
+/// 创建时只复制 key，value 按页读取；每读完一页，在写锁内用 capture 记录的旧 value 修正，
+/// 这时在读取之前开始的写操作都已经记录完毕。最后补上创建之后被删除的 key。
+struct TableIter<'a> {
+    store: &'a MemTable,
+    table: String,
+    id: u64,
+    keys: Vec<String>,
+    pos: usize,
+    page: std::vec::IntoIter<KvPair>,
+    finished: bool,
+}
+
+impl<'a> TableIter<'a> {
+    fn new(store: &'a MemTable, table: &str) -> Self {
+        let mut iter = Self {
+            store,
+            table: table.to_owned(),
+            id: 0,
+            keys: Vec::new(),
+            pos: 0,
+            page: Vec::new().into_iter(),
+            finished: true,
+        };
+
+        {
+            let _gate = store.write_gate(table);
+            let mut evictor = store.settled();
+            if !store.tables.contains_key(table) {
+                return iter;
+            }
+            if let Some(evictor) = evictor.as_mut()
+                && store.ttl_used.load(Ordering::Acquire)
+            {
+                store.purge_expired(evictor, table);
+            }
+            iter.id = store.capture.start_table(table);
+            iter.finished = false;
+        }
+
+        // 这里可能读到开始之后才写入的 key，修正时会去掉
+        if let Some(t) = store.tables.get(table) {
+            iter.keys = t.iter().map(|v| v.key().clone()).collect();
+        }
+        iter.keys.sort_unstable();
+
+        iter
+    }
+
+    // 读取下一页的 value，修正之后放到 page 中
+    fn fill(&mut self) {
+        let end = (self.pos + ITER_PAGE_SIZE).min(self.keys.len());
+        let keys = &self.keys[self.pos..end];
+        let values: Vec<_> = match self.store.tables.get(&self.table) {
+            Some(t) => keys
+                .iter()
+                .map(|k| t.get(k).map(|v| v.value().clone()))
+                .collect(),
+            None => vec![None; keys.len()],
+        };
+        self.pos = end;
+
+        let _gate = self.store.write_gate(&self.table);
+        let _evictor = self.store.settled();
+        let capture = &self.store.capture;
+        let mut pairs: Vec<_> = keys
+            .iter()
+            .zip(values)
+            .filter_map(|(k, v)| {
+                let v = capture.original(self.id, &self.table, k).unwrap_or(v)?;
+                Some(KvPair::new(k, v))
+            })
+            .collect();
+
+        if self.pos == self.keys.len() {
+            for (k, v) in capture.finish_table(self.id, &self.table) {
+                if let Some(v) = v
+                    && self.keys.binary_search(&k).is_err()
+                {
+                    pairs.push(KvPair::new(k, v));
+                }
+            }
+            self.finished = true;
+        }
+
+        self.page = pairs.into_iter();
+    }
+}
+
+impl Iterator for TableIter<'_> {
+    type Item = KvPair;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(pair) = self.page.next() {
+                return Some(pair);
+            }
+            if self.finished {
+                return None;
+            }
+
+            self.fill();
+        }
+    }
+}
+
+impl Drop for TableIter<'_> {
+    fn drop(&mut self) {
+        // 没有读完就被丢弃时停止记录
+        if !self.finished {
+            self.store.capture.cancel(self.id);
+        }
     }
 }
 
@@ -68,3 +499,341 @@ impl From<(String, Value)> for KvPair {
         KvPair::new(data.0, data.1)
     }
 }
+
+fn new_gates() -> Vec<RwLock<()>> {
+    (0..GATE_STRIPES).map(|_| RwLock::new(())).collect()
+}
+
+// table 对应的 gate
+fn gate(table: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    table.hash(&mut hasher);
+
+    (hasher.finish() % GATE_STRIPES as u64) as usize
+}
+
+// gate 不保护任何数据，直接忽略 poison
+fn read(gate: &RwLock<()>) -> RwLockReadGuard<'_, ()> {
+    gate.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write(gate: &RwLock<()>) -> RwLockWriteGuard<'_, ()> {
+    gate.write().unwrap_or_else(|e| e.into_inner())
+}
+
+// 持有锁的线程 panic 不会破坏 Evictor 的数据，所以直接忽略 poison
+fn lock(evictor: &Mutex<Evictor>) -> MutexGuard<'_, Evictor> {
+    evictor.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn try_lock(evictor: &Mutex<Evictor>) -> Option<MutexGuard<'_, Evictor>> {
+    match evictor.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    // 每个 entry 的大小："t1" + "kN" + 编码后的 i64 value
+    fn entry_size(key: &str, v: i64) -> usize {
+        use prost::Message;
+        "t1".len() + key.len() + Value::from(v).encoded_len()
+    }
+
+    fn store_with(policy: EvictionPolicy, entries: usize) -> MemTable {
+        let budget = entry_size("k0", 0) * entries;
+        MemTable::with_eviction(EvictionConfig::new(budget, policy))
+    }
+
+    fn keys(store: &MemTable) -> Vec<String> {
+        let mut keys: Vec<_> = store
+            .get_all("t1")
+            .unwrap()
+            .into_iter()
+            .map(|p| p.key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn memtable_should_track_used_bytes() {
+        let store = store_with(EvictionPolicy::Lru, 10);
+        assert_eq!(store.used_bytes(), Some(0));
+
+        store.set("t1", "k0".into(), 0.into()).unwrap();
+        assert_eq!(store.used_bytes(), Some(entry_size("k0", 0)));
+
+        store.del("t1", "k0").unwrap();
+        assert_eq!(store.used_bytes(), Some(0));
+        assert_eq!(MemTable::new().used_bytes(), None);
+    }
+
+    #[test]
+    fn lru_should_evict_least_recently_used() {
+        let store = store_with(EvictionPolicy::Lru, 3);
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), 0.into()).unwrap();
+        }
+
+        // 访问 k0 之后，k1 变成最久没有被访问的
+        store.get("t1", "k0").unwrap();
+        store.set("t1", "k3".into(), 0.into()).unwrap();
+
+        assert_eq!(keys(&store), vec!["k0", "k2", "k3"]);
+
+        let evicted = store.drain_evicted();
+        assert_eq!(evicted, vec![Evicted::new("t1", "k1", 0.into())]);
+        assert!(store.drain_evicted().is_empty());
+    }
+
+    #[test]
+    fn lfu_should_evict_least_frequently_used() {
+        let store = store_with(EvictionPolicy::Lfu, 3);
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), 0.into()).unwrap();
+        }
+
+        store.get("t1", "k0").unwrap();
+        store.get("t1", "k0").unwrap();
+        store.get("t1", "k1").unwrap();
+        store.set("t1", "k3".into(), 0.into()).unwrap();
+
+        assert_eq!(keys(&store), vec!["k0", "k1", "k3"]);
+    }
+
+    #[test]
+    fn random_should_stay_within_budget() {
+        let store = store_with(EvictionPolicy::Random, 5);
+        for i in 0..50 {
+            store.set("t1", format!("k{}", i), 0.into()).unwrap();
+        }
+
+        // key 的长度不同，所以剩下的 entry 数不固定
+        let remaining = keys(&store).len();
+        assert!(remaining > 0 && remaining <= 5);
+        assert!(store.used_bytes().unwrap() <= entry_size("k0", 0) * 5);
+        assert_eq!(store.drain_evicted().len(), 50 - remaining);
+    }
+
+    #[test]
+    fn ttl_first_should_evict_keys_with_ttl_first() {
+        let store = store_with(EvictionPolicy::TtlFirst, 3);
+        for i in 0..3 {
+            store.set("t1", format!("k{}", i), 0.into()).unwrap();
+        }
+
+        assert_eq!(store.expire("t1", "k2", Duration::from_secs(60)), Ok(true));
+        assert_eq!(store.expire("t1", "k1", Duration::from_secs(30)), Ok(true));
+        store.set("t1", "k3".into(), 0.into()).unwrap();
+        assert_eq!(keys(&store), vec!["k0", "k2", "k3"]);
+
+        store.set("t1", "k4".into(), 0.into()).unwrap();
+        assert_eq!(keys(&store), vec!["k0", "k3", "k4"]);
+
+        // 没有 TTL 的 key 按 LRU 淘汰
+        store.set("t1", "k5".into(), 0.into()).unwrap();
+        assert_eq!(keys(&store), vec!["k3", "k4", "k5"]);
+    }
+
+    #[test]
+    fn expired_keys_should_be_invisible() {
+        let store = store_with(EvictionPolicy::Lru, 10);
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store.set("t1", "k2".into(), 2.into()).unwrap();
+
+        assert_eq!(store.expire("t1", "k1", Duration::ZERO), Ok(true));
+        assert_eq!(store.expire("t1", "nope", Duration::ZERO), Ok(false));
+
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.contains("t1", "k1"), Ok(false));
+        assert_eq!(keys(&store), vec!["k2"]);
+        assert_eq!(store.used_bytes(), Some(entry_size("k2", 2)));
+
+        // 过期不算淘汰
+        assert!(store.drain_evicted().is_empty());
+        assert!(MemTable::new().expire("t1", "k2", Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn get_should_not_wait_for_evictor_without_ttl() {
+        let store = Arc::new(store_with(EvictionPolicy::Lru, 10));
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+
+        // 其它线程持有 evictor 的锁时，get 和 contains 仍然可以完成
+        let _evictor = lock(store.evictor.as_ref().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        let reader = store.clone();
+        thread::spawn(move || {
+            let v = reader.get("t1", "k1").unwrap();
+            let exists = reader.contains("t1", "k1").unwrap();
+            tx.send((v, exists)).unwrap();
+        });
+
+        let res = rx.recv_timeout(Duration::from_secs(1));
+        assert_eq!(res, Ok((Some(1.into()), true)));
+    }
+
+    #[test]
+    fn get_iter_should_see_table_at_creation() {
+        // 超过一页，转账时会删除和创建 key
+        const ACCOUNTS: i64 = 3000;
+        let store = Arc::new(MemTable::new());
+        for i in 0..ACCOUNTS {
+            store.set("bank", format!("a{:04}", i), 100.into()).unwrap();
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (store, done) = (store.clone(), done.clone());
+            thread::spawn(move || {
+                let balance = |k: &str| {
+                    store
+                        .get("bank", k)
+                        .unwrap()
+                        .map_or(0, |v| i64::try_from(v).unwrap())
+                };
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let from = format!("a{:04}", i % ACCOUNTS);
+                    let to = format!("a{:04}", (i * 7 + 3) % ACCOUNTS);
+                    if from != to {
+                        let total = balance(&from) + balance(&to);
+                        let ops = vec![
+                            WriteOp::del("bank", from),
+                            WriteOp::set("bank", to, total.into()),
+                        ];
+                        store.write_batch(ops).unwrap();
+                    }
+                    i += 1;
+                }
+            })
+        };
+
+        for _ in 0..20 {
+            let pairs: Vec<_> = store.get_iter("bank").unwrap().collect();
+            let keys: std::collections::HashSet<_> = pairs.iter().map(|p| &p.key).collect();
+            assert_eq!(keys.len(), pairs.len());
+
+            let total: i64 = pairs
+                .into_iter()
+                .map(|p| i64::try_from(p.value.unwrap()).unwrap())
+                .sum();
+            assert_eq!(total, 100 * ACCOUNTS);
+        }
+
+        done.store(true, Ordering::Relaxed);
+        writer.join().unwrap();
+
+        // 读取不存在的 table 不会创建它
+        assert_eq!(store.get_iter("nope").unwrap().count(), 0);
+        assert!(!store.tables.contains_key("nope"));
+    }
+
+    #[test]
+    fn eviction_should_work_across_threads() {
+        let store = Arc::new(store_with(EvictionPolicy::Lru, 20));
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        store
+                            .set("t1", format!("k{}", t * 100 + i), 0.into())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let all = store.get_all("t1").unwrap();
+        let used: usize = all.iter().map(|p| entry_size(&p.key, 0)).sum();
+        assert_eq!(store.used_bytes(), Some(used));
+        assert!(used <= store.eviction_config().unwrap().max_bytes);
+    }
+
+    #[test]
+    fn write_batch_should_be_isolated_from_readers() {
+        let store = Arc::new(MemTable::new());
+        let writer = {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..1000i64 {
+                    let ops = vec![
+                        WriteOp::set("t1", "a", i.into()),
+                        WriteOp::set("t1", "b", i.into()),
+                    ];
+                    store.write_batch(ops).unwrap();
+                }
+            })
+        };
+
+        // 读者不会看到只执行了一半的 batch
+        while !writer.is_finished() {
+            let mut values: Vec<_> = store.get_all("t1").unwrap();
+            values.sort_by(|a, b| a.key.cmp(&b.key));
+            if let [a, b] = values.as_slice() {
+                assert_eq!(a.value, b.value);
+            }
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn write_batch_should_only_block_its_tables() {
+        use std::sync::mpsc;
+
+        let store = MemTable::new();
+        let other = (0..)
+            .map(|i| format!("t{}", i))
+            .find(|t| gate(t) != gate("t1"))
+            .unwrap();
+
+        // 模拟一个正在执行的 t1 的 batch
+        let gates = store.write_gates(["t1"]);
+        let store = &store;
+        thread::scope(|s| {
+            let (tx, rx) = mpsc::channel();
+            s.spawn(|| store.set(&other, "k1".into(), 1.into()).unwrap())
+                .join()
+                .unwrap();
+            s.spawn(move || {
+                store.set("t1", "k1".into(), 1.into()).unwrap();
+                tx.send(()).unwrap();
+            });
+
+            assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+            drop(gates);
+            rx.recv().unwrap();
+        });
+
+        assert_eq!(store.get(&other, "k1"), Ok(Some(1.into())));
+    }
+
+    #[test]
+    fn expired_keys_should_follow_ttl_changes() {
+        let mut evictor = Evictor::new(EvictionConfig::new(1 << 20, EvictionPolicy::Lru));
+        let now = Instant::now();
+        for key in ["k1", "k2", "k3"] {
+            evictor.insert("t1", key, &1.into());
+            evictor.set_expire("t1", key, now);
+        }
+        evictor.insert("t2", "k1", &1.into());
+        evictor.set_expire("t2", "k1", now + Duration::from_secs(60));
+
+        // 再次写入会清除 TTL，重新设置会替换原来的过期时间
+        evictor.insert("t1", "k2", &2.into());
+        evictor.set_expire("t1", "k3", now + Duration::from_secs(60));
+        assert_eq!(evictor.expired_keys("t1"), ["k1"]);
+        assert!(evictor.expired_keys("t2").is_empty());
+
+        evictor.remove("t1", "k1");
+        assert!(evictor.expired_keys("t1").is_empty());
+    }
+}
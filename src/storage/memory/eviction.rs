@@ -0,0 +1,279 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+
+use crate::{Evicted, Value};
+
+/// 最多缓存多少条尚未被取走的淘汰事件，超过后丢弃最早的事件
+const MAX_PENDING_EVICTIONS: usize = 1024;
+
+/// 内存超限时选择淘汰对象的策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// 淘汰最久没有被访问的 key
+    #[default]
+    Lru,
+    /// 淘汰访问次数最少的 key，次数相同时淘汰最久没有被访问的
+    Lfu,
+    /// 随机淘汰
+    Random,
+    /// 优先淘汰设置了 TTL 的 key（最先过期的先淘汰），没有 TTL 的 key 按 LRU 淘汰
+    TtlFirst,
+}
+
+/// MemTable 的内存限制配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvictionConfig {
+    /// 内存预算，按 key 和 Value 编码后的长度近似计算
+    pub max_bytes: usize,
+    /// 淘汰策略
+    pub policy: EvictionPolicy,
+}
+
+impl EvictionConfig {
+    pub fn new(max_bytes: usize, policy: EvictionPolicy) -> Self {
+        Self { max_bytes, policy }
+    }
+}
+
+/// 排序用的 rank，越小越先被淘汰
+type Rank = (u64, u64, u64);
+/// (table, key)
+type EntryKey = (String, String);
+
+#[derive(Debug, Clone)]
+struct EntryMeta {
+    size: usize,
+    last_access: u64,
+    hits: u64,
+    expire_at: Option<Instant>,
+    rank: Rank,
+}
+
+/// 记录每个 entry 的大小和访问信息，用于计算内存占用和挑选淘汰对象
+///
+/// 所有 entry 按 rank 排序保存在 BTreeSet 里，淘汰时取最小的即可，不需要遍历。
+/// 设置了 TTL 的 entry 另外按 table 和过期时间排序，查找过期的 key 时只访问已经过期的部分。
+#[derive(Debug, Clone)]
+pub(crate) struct Evictor {
+    config: EvictionConfig,
+    epoch: Instant,
+    used: usize,
+    clock: u64,
+    seed: u64,
+    entries: HashMap<EntryKey, EntryMeta>,
+    ranks: BTreeSet<(Rank, EntryKey)>,
+    /// table -> (过期时间, key)
+    expiries: HashMap<String, BTreeSet<(Instant, String)>>,
+    evicted: VecDeque<Evicted>,
+}
+
+impl Evictor {
+    pub(crate) fn new(config: EvictionConfig) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0x2545f4914f6cdd1d, |d| d.as_nanos() as u64)
+            | 1;
+
+        Self {
+            config,
+            epoch: Instant::now(),
+            used: 0,
+            clock: 0,
+            seed,
+            entries: HashMap::new(),
+            ranks: BTreeSet::new(),
+            expiries: HashMap::new(),
+            evicted: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn config(&self) -> EvictionConfig {
+        self.config
+    }
+
+    /// 当前占用的字节数
+    pub(crate) fn used(&self) -> usize {
+        self.used
+    }
+
+    /// 内存占用是否超出预算
+    pub(crate) fn over_budget(&self) -> bool {
+        self.used > self.config.max_bytes
+    }
+
+    /// entry 是否已经过期
+    pub(crate) fn expired(&self, table: &str, key: &str) -> bool {
+        self.entries
+            .get(&entry_key(table, key))
+            .and_then(|m| m.expire_at)
+            .is_some_and(|at| at <= Instant::now())
+    }
+
+    /// table 里所有已经过期的 key
+    pub(crate) fn expired_keys(&self, table: &str) -> Vec<String> {
+        let now = Instant::now();
+
+        self.expiries
+            .get(table)
+            .into_iter()
+            .flatten()
+            .take_while(|(at, _)| *at <= now)
+            .map(|(_, k)| k.clone())
+            .collect()
+    }
+
+    /// 记录一次访问
+    pub(crate) fn touch(&mut self, table: &str, key: &str) {
+        let clock = self.tick();
+
+        self.update(table, key, |m| {
+            m.last_access = clock;
+            m.hits += 1;
+        });
+    }
+
+    /// 记录一次写入，写入会清除之前设置的 TTL
+    pub(crate) fn insert(&mut self, table: &str, key: &str, value: &Value) {
+        let size = table.len() + key.len() + value.encoded_len();
+        let clock = self.tick();
+        let ek = entry_key(table, key);
+
+        let meta = match self.entries.remove(&ek) {
+            Some(mut m) => {
+                self.unindex(&ek, &m);
+                self.used -= m.size;
+                m.size = size;
+                m.last_access = clock;
+                m.hits += 1;
+                m.expire_at = None;
+                m
+            }
+            None => EntryMeta {
+                size,
+                last_access: clock,
+                hits: 1,
+                expire_at: None,
+                rank: (0, 0, 0),
+            },
+        };
+
+        self.used += size;
+        self.put(ek, meta);
+    }
+
+    /// 删除一个 entry 的记录
+    pub(crate) fn remove(&mut self, table: &str, key: &str) {
+        let ek = entry_key(table, key);
+
+        if let Some(m) = self.entries.remove(&ek) {
+            self.unindex(&ek, &m);
+            self.used -= m.size;
+        }
+    }
+
+    /// 设置过期时间，entry 不存在时返回 false
+    pub(crate) fn set_expire(&mut self, table: &str, key: &str, at: Instant) -> bool {
+        self.update(table, key, |m| m.expire_at = Some(at))
+    }
+
+    /// 按策略挑选下一个要淘汰的 entry，跳过 (table, key) 本身
+    pub(crate) fn victim(&self, table: &str, key: &str) -> Option<EntryKey> {
+        self.ranks
+            .iter()
+            .map(|(_, ek)| ek)
+            .find(|(t, k)| t != table || k != key)
+            .cloned()
+    }
+
+    /// 记录一次淘汰事件
+    pub(crate) fn push_evicted(&mut self, evicted: Evicted) {
+        if self.evicted.len() == MAX_PENDING_EVICTIONS {
+            self.evicted.pop_front();
+        }
+
+        self.evicted.push_back(evicted);
+    }
+
+    /// 取走所有尚未处理的淘汰事件
+    pub(crate) fn drain_evicted(&mut self) -> Vec<Evicted> {
+        self.evicted.drain(..).collect()
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    // 修改 entry 的元数据，并重新计算它的 rank
+    fn update(&mut self, table: &str, key: &str, f: impl FnOnce(&mut EntryMeta)) -> bool {
+        let ek = entry_key(table, key);
+
+        match self.entries.remove(&ek) {
+            Some(mut m) => {
+                self.unindex(&ek, &m);
+                f(&mut m);
+                self.put(ek, m);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn put(&mut self, ek: EntryKey, mut meta: EntryMeta) {
+        meta.rank = self.rank(&meta);
+        self.ranks.insert((meta.rank, ek.clone()));
+        if let Some(at) = meta.expire_at {
+            let (table, key) = &ek;
+            let expiries = self.expiries.entry(table.clone()).or_default();
+            expiries.insert((at, key.clone()));
+        }
+        self.entries.insert(ek, meta);
+    }
+
+    // 从排序用的索引中删除 entry，之后可以通过 put 重新加入
+    fn unindex(&mut self, ek: &EntryKey, meta: &EntryMeta) {
+        self.ranks.remove(&(meta.rank, ek.clone()));
+
+        let (table, key) = ek;
+        if let Some(at) = meta.expire_at
+            && let Some(expiries) = self.expiries.get_mut(table)
+        {
+            expiries.remove(&(at, key.clone()));
+            if expiries.is_empty() {
+                self.expiries.remove(table);
+            }
+        }
+    }
+
+    fn rank(&mut self, meta: &EntryMeta) -> Rank {
+        match self.config.policy {
+            EvictionPolicy::Lru => (0, meta.last_access, 0),
+            EvictionPolicy::Lfu => (meta.hits, meta.last_access, 0),
+            EvictionPolicy::Random => match meta.rank {
+                // random 的 rank 只在第一次插入时生成
+                (0, 0, 0) => (self.next_random(), 0, 0),
+                rank => rank,
+            },
+            EvictionPolicy::TtlFirst => match meta.expire_at {
+                Some(at) => (0, at.duration_since(self.epoch).as_nanos() as u64, 0),
+                None => (1, meta.last_access, 0),
+            },
+        }
+    }
+
+    // xorshift64*
+    fn next_random(&mut self) -> u64 {
+        self.seed ^= self.seed >> 12;
+        self.seed ^= self.seed << 25;
+        self.seed ^= self.seed >> 27;
+        self.seed.wrapping_mul(0x2545f4914f6cdd1d).max(1)
+    }
+}
+
+fn entry_key(table: &str, key: &str) -> EntryKey {
+    (table.to_owned(), key.to_owned())
+}
@@ -0,0 +1,362 @@
+use std::{path::Path, sync::Arc};
+
+use redb::{
+    Database, ReadTransaction, ReadableDatabase, ReadableTableMetadata, TableDefinition,
+    TableError, TableHandle, WriteTransaction,
+};
+
+use crate::{
+    CompressionConfig, CompressionStats, KVError, KvPair, Snapshot, Storage, StorageIter, Value,
+    WriteOp,
+    storage::{Compressor, skip_errors, take_ordered},
+};
+
+/// 使用 redb 构建的持久化存储，实现了 Storage trait
+///
+/// 每个 kv table 对应一个 redb table，key 为字符串，value 为 protobuf 编码后的 Value。
+pub struct RedbDb {
+    db: Database,
+    compressor: Arc<Compressor>,
+}
+
+/// 读事务上的快照，redb 的 MVCC 保证之后的写入对它不可见，创建时不需要复制数据
+struct RedbSnapshot {
+    txn: ReadTransaction,
+    compressor: Arc<Compressor>,
+}
+
+type ReadTable = redb::ReadOnlyTable<&'static str, &'static [u8]>;
+
+/// 一个写事务中的批量操作，RedbDb::batch 返回 Ok 时所有修改一起提交，否则全部回滚
+pub struct RedbBatch<'a> {
+    txn: &'a WriteTransaction,
+    compressor: &'a Compressor,
+}
+
+impl RedbDb {
+    /// 打开 path 指定的数据库文件，不存在则创建
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::with_compression(path, CompressionConfig::default())
+    }
+
+    /// 按 config 压缩写入的 value，已有的未压缩数据仍然可以读取
+    pub fn with_compression(path: impl AsRef<Path>, config: CompressionConfig) -> Self {
+        Self {
+            db: Database::create(path).unwrap(),
+            compressor: Arc::new(Compressor::new(config)),
+        }
+    }
+
+    /// 打开以来写入的 value 的压缩统计
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.compressor.stats()
+    }
+
+    /// 在一个 ACID 写事务中执行 f，f 返回错误时事务回滚
+    pub fn batch<T>(&self, f: impl FnOnce(&RedbBatch) -> Result<T, KVError>) -> Result<T, KVError> {
+        let txn = self.db.begin_write()?;
+        let res = f(&RedbBatch {
+            txn: &txn,
+            compressor: &self.compressor,
+        });
+
+        match res {
+            Ok(v) => {
+                txn.commit()?;
+                Ok(v)
+            }
+            Err(e) => {
+                txn.abort()?;
+                Err(e)
+            }
+        }
+    }
+
+    // 在只读事务中打开 table，table 不存在时返回 None
+    fn read_table(&self, table: &str) -> Result<Option<ReadTable>, KVError> {
+        self.read()?.open_table(table)
+    }
+
+    fn read(&self) -> Result<RedbSnapshot, KVError> {
+        Ok(RedbSnapshot {
+            txn: self.db.begin_read()?,
+            compressor: self.compressor.clone(),
+        })
+    }
+}
+
+impl RedbSnapshot {
+    fn open_table(&self, table: &str) -> Result<Option<ReadTable>, KVError> {
+        match self.txn.open_table(table_definition(table)) {
+            Ok(t) => Ok(Some(t)),
+            Err(TableError::TableDoesNotExist(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Snapshot for RedbSnapshot {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let Some(t) = self.open_table(table)? else {
+            return Ok(None);
+        };
+
+        t.get(key)?
+            .map(|v| self.compressor.decode(v.value()))
+            .transpose()
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let Some(t) = self.open_table(table)? else {
+            return Ok(Vec::new());
+        };
+
+        let iter = match end {
+            Some(end) if end <= start => return Ok(Vec::new()),
+            Some(end) => t.range::<&str>(start..end)?,
+            None => t.range::<&str>(start..)?,
+        };
+        let limit = if limit == 0 { usize::MAX } else { limit };
+
+        iter.take(limit)
+            .map(|v| decode_pair(&self.compressor, v))
+            .collect()
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let mut names = Vec::new();
+
+        // 删除所有 key 后 redb table 仍然存在，需要跳过空的 table
+        for handle in self.txn.list_tables()? {
+            let t = self.txn.open_table(table_definition(handle.name()))?;
+            if !t.is_empty()? {
+                names.push(handle.name().to_owned());
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+}
+
+impl RedbBatch<'_> {
+    /// 设置 key 的 value，返回旧的 value
+    pub fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KVError> {
+        let data = self.compressor.encode(table, value)?;
+        let mut t = self.txn.open_table(table_definition(table))?;
+        let old = t.insert(key, data.as_slice())?;
+
+        old.map(|v| self.compressor.decode(v.value())).transpose()
+    }
+
+    /// 删除 key，返回之前的 value
+    pub fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let mut t = self.txn.open_table(table_definition(table))?;
+        let old = t.remove(key)?;
+
+        old.map(|v| self.compressor.decode(v.value())).transpose()
+    }
+}
+
+impl Storage for RedbDb {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let Some(t) = self.read_table(table)? else {
+            return Ok(None);
+        };
+
+        t.get(key)?
+            .map(|v| self.compressor.decode(v.value()))
+            .transpose()
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+        self.batch(|b| b.set(table, &key, value))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        let Some(t) = self.read_table(table)? else {
+            return Ok(false);
+        };
+
+        Ok(t.get(key)?.is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        self.batch(|b| b.del(table, key))
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        let Some(t) = self.read_table(table)? else {
+            return Ok(Vec::new());
+        };
+
+        t.range::<&str>(..)?
+            .map(|v| decode_pair(&self.compressor, v))
+            .collect()
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+        // ReadOnlyTable::range 返回的迭代器持有事务，不借用 self
+        let iter = match self.read_table(table)? {
+            Some(t) => Some(t.range::<&str>(..)?),
+            None => None,
+        };
+
+        let compressor = self.compressor.clone();
+
+        Ok(StorageIter::new(skip_errors(
+            iter.into_iter()
+                .flatten()
+                .map(move |v| decode_pair(&compressor, v)),
+        )))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        self.read()?.tables()
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        Ok(Arc::new(self.read()?))
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let Some(t) = self.read_table(table)? else {
+            return Ok(Vec::new());
+        };
+
+        let iter = match end {
+            Some(end) if end <= start => return Ok(Vec::new()),
+            Some(end) => t.range::<&str>(start..end)?,
+            None => t.range::<&str>(start..)?,
+        };
+
+        take_ordered(iter, limit, reverse)
+            .into_iter()
+            .map(|v| decode_pair(&self.compressor, v))
+            .collect()
+    }
+
+    fn get_prefix(&self, table: &str, prefix: &str) -> Result<Vec<KvPair>, KVError> {
+        let Some(t) = self.read_table(table)? else {
+            return Ok(Vec::new());
+        };
+
+        t.range::<&str>(prefix..)?
+            .take_while(|v| {
+                v.as_ref().is_ok_and(|(k, _)| k.value().starts_with(prefix)) || v.is_err()
+            })
+            .map(|v| decode_pair(&self.compressor, v))
+            .collect()
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        self.batch(|b| {
+            ops.into_iter()
+                .map(|op| match op {
+                    WriteOp::Set { table, key, value } => b.set(&table, &key, value),
+                    WriteOp::Del { table, key } => b.del(&table, &key),
+                })
+                .collect()
+        })
+    }
+}
+
+type RedbEntry = (
+    redb::AccessGuard<'static, &'static str>,
+    redb::AccessGuard<'static, &'static [u8]>,
+);
+
+fn decode_pair(
+    compressor: &Compressor,
+    v: Result<RedbEntry, redb::StorageError>,
+) -> Result<KvPair, KVError> {
+    let (k, v) = v?;
+
+    Ok(KvPair::new(k.value(), compressor.decode(v.value())?))
+}
+
+fn table_definition(table: &str) -> TableDefinition<'_, &'static str, &'static [u8]> {
+    TableDefinition::new(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_should_commit_all_or_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+
+        store
+            .batch(|b| {
+                b.set("t1", "k1", "v1".into())?;
+                b.set("t2", "k2", "v2".into())
+            })
+            .unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t2", "k2"), Ok(Some("v2".into())));
+
+        let res: Result<(), _> = store.batch(|b| {
+            b.set("t1", "k1", "changed".into())?;
+            b.del("t2", "k2")?;
+            Err(KVError::InternalError("abort".into()))
+        });
+        assert!(res.is_err());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t2", "k2"), Ok(Some("v2".into())));
+    }
+
+    #[test]
+    fn data_should_persist_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.redb");
+
+        let store = RedbDb::new(&path);
+        store
+            .set_many(
+                "t1",
+                vec![KvPair::new("k1", 1.into()), KvPair::new("k2", 2.into())],
+            )
+            .unwrap();
+        drop(store);
+
+        let store = RedbDb::new(&path);
+        assert_eq!(
+            store.get_many("t1", vec!["k2".into(), "k1".into()]),
+            Ok(vec![Some(2.into()), Some(1.into())])
+        );
+    }
+
+    #[test]
+    fn corrupted_values_should_not_become_empty_pairs() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RedbDb::new(dir.path().join("kv.redb"));
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+
+        // 直接写入无法解码的 value
+        let txn = store.db.begin_write().unwrap();
+        txn.open_table(table_definition("t1"))
+            .unwrap()
+            .insert("k2", [0xffu8, 0xff].as_slice())
+            .unwrap();
+        txn.commit().unwrap();
+
+        assert!(store.get_all("t1").is_err());
+        let pairs: Vec<_> = store.get_iter("t1").unwrap().collect();
+        assert_eq!(pairs, vec![KvPair::new("k1", 1.into())]);
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{KVError, KvPair, MemSnapshot, Snapshot, Storage, StorageIter, Value, WriteOp};
+
+/// 每个 shard 私有的数据：table -> (key -> value)
+type ShardData = HashMap<String, HashMap<String, Value>>;
+
+/// 发送给 shard 线程执行的任务
+type Job = Box<dyn FnOnce(&mut ShardData) + Send>;
+
+/// shard 返回的 (在请求中的位置, 结果)
+type Replies<T> = Receiver<Vec<(usize, T)>>;
+
+/// thread-per-core 的分片存储，实现了 Storage trait
+///
+/// 每个 shard 由一个线程独占一个 HashMap，命令按 (table, key) 的哈希分派到对应 shard 的 channel，
+/// 因此处理过程中无需加锁。需要访问多个 shard 的操作（get_all、get_many 等）会先把请求发给所有相关的 shard，
+/// 再统一收集结果。
+///
+/// 每个 shard 按 channel 中的顺序执行任务，所以只要访问多个 shard 的操作持有 order 锁把请求发完，
+/// 所有 shard 看到的这些操作的先后顺序都相同，write_batch 对其它多 shard 操作来说就是原子的。
+pub struct ShardedTable {
+    senders: Vec<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    order: Mutex<()>,
+}
+
+impl Default for ShardedTable {
+    /// 缺省按 CPU 核数创建 shard
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+impl ShardedTable {
+    /// 创建一个有 shards 个 shard 的 ShardedTable
+    pub fn new(shards: usize) -> Self {
+        let (senders, workers) = (0..shards.max(1))
+            .map(|i| {
+                let (tx, rx) = mpsc::channel::<Job>();
+                let handle = thread::Builder::new()
+                    .name(format!("kv-shard-{}", i))
+                    .spawn(move || {
+                        let mut data = ShardData::new();
+                        // 所有 Sender 被 drop 后，recv 返回错误，线程退出
+                        while let Ok(job) = rx.recv() {
+                            job(&mut data);
+                        }
+                    })
+                    .expect("Failed to spawn shard thread");
+
+                (tx, handle)
+            })
+            .unzip();
+
+        Self {
+            senders,
+            workers,
+            order: Mutex::new(()),
+        }
+    }
+
+    /// shard 的数量
+    pub fn shards(&self) -> usize {
+        self.senders.len()
+    }
+
+    // 计算 (table, key) 所属的 shard
+    fn shard_of(&self, table: &str, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        table.hash(&mut hasher);
+        key.hash(&mut hasher);
+
+        (hasher.finish() % self.senders.len() as u64) as usize
+    }
+
+    // 访问多个 shard 的操作在发送请求期间持有这个锁
+    fn order(&self) -> MutexGuard<'_, ()> {
+        self.order.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 把任务发到指定的 shard，返回用于接收结果的 channel
+    fn send<T, F>(&self, shard: usize, f: F) -> Result<Receiver<T>, KVError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ShardData) -> T + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move |data| {
+            let _ = tx.send(f(data));
+        });
+
+        self.senders[shard]
+            .send(job)
+            .map_err(|_| shard_gone(shard))?;
+
+        Ok(rx)
+    }
+
+    // 在指定的 shard 上执行任务并等待结果
+    fn call<T, F>(&self, shard: usize, f: F) -> Result<T, KVError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ShardData) -> T + Send + 'static,
+    {
+        self.send(shard, f)?.recv().map_err(|_| shard_gone(shard))
+    }
+
+    // 把 keys 按 shard 分组并发读取，结果按 keys 的原始顺序返回；表不存在时传入 None，不会创建空表
+    fn fan_out<I, T, F>(
+        &self,
+        table: &str,
+        items: Vec<I>,
+        key_of: fn(&I) -> &str,
+        f: F,
+    ) -> Result<Vec<T>, KVError>
+    where
+        I: Send + 'static,
+        T: Send + 'static,
+        F: Fn(Option<&HashMap<String, Value>>, I) -> T + Send + Clone + 'static,
+    {
+        let total = items.len();
+        let mut groups: HashMap<usize, Vec<(usize, I)>> = HashMap::new();
+
+        for (i, item) in items.into_iter().enumerate() {
+            let shard = self.shard_of(table, key_of(&item));
+            groups.entry(shard).or_default().push((i, item));
+        }
+
+        // 先把请求发给所有相关的 shard，再统一等待，这样各 shard 可以并行处理
+        let order = self.order();
+        let receivers = groups
+            .into_iter()
+            .map(|(shard, items)| {
+                let name = table.to_owned();
+                let f = f.clone();
+                let rx = self.send(shard, move |data| {
+                    let table = data.get(&name);
+                    items
+                        .into_iter()
+                        .map(|(i, item)| (i, f(table, item)))
+                        .collect::<Vec<_>>()
+                })?;
+
+                Ok((shard, rx))
+            })
+            .collect::<Result<Vec<_>, KVError>>()?;
+        drop(order);
+
+        gather(receivers, total)
+    }
+
+    // 从所有 shard 中收集某个 table 的全部数据
+    fn collect_table(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        let name = table.to_owned();
+
+        self.collect(move |data| {
+            data.get(&name)
+                .map(|t| {
+                    t.iter()
+                        .map(|(k, v)| KvPair::new(k, v.clone()))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        })
+    }
+
+    // 在所有 shard 上执行 f，合并返回的结果
+    fn collect<T, F>(&self, f: F) -> Result<Vec<T>, KVError>
+    where
+        T: Send + 'static,
+        F: Fn(&mut ShardData) -> Vec<T> + Send + Clone + 'static,
+    {
+        let order = self.order();
+        let receivers = (0..self.shards())
+            .map(|shard| self.send(shard, f.clone()).map(|rx| (shard, rx)))
+            .collect::<Result<Vec<_>, KVError>>()?;
+        drop(order);
+
+        let mut items = Vec::new();
+
+        for (shard, rx) in receivers {
+            items.extend(rx.recv().map_err(|_| shard_gone(shard))?);
+        }
+
+        Ok(items)
+    }
+}
+
+impl Drop for ShardedTable {
+    fn drop(&mut self) {
+        // 先关闭所有 channel，让 shard 线程退出，再等待它们结束
+        self.senders.clear();
+
+        for handle in self.workers.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Storage for ShardedTable {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let (name, key) = (table.to_owned(), key.to_owned());
+
+        self.call(self.shard_of(table, &key), move |data| {
+            data.get(&name).and_then(|t| t.get(&key)).cloned()
+        })
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+        let name = table.to_owned();
+
+        self.call(self.shard_of(table, &key), move |data| {
+            data.entry(name).or_default().insert(key, value)
+        })
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        let (name, key) = (table.to_owned(), key.to_owned());
+
+        self.call(self.shard_of(table, &key), move |data| {
+            data.get(&name).is_some_and(|t| t.contains_key(&key))
+        })
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let (name, key) = (table.to_owned(), key.to_owned());
+
+        self.call(self.shard_of(table, &key), move |data| {
+            data.get_mut(&name).and_then(|t| t.remove(&key))
+        })
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        self.collect_table(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+        Ok(StorageIter::new(self.collect_table(table)?.into_iter()))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let mut names = self.collect(|data| {
+            data.iter()
+                .filter(|(_, t)| !t.is_empty())
+                .map(|(name, _)| name.clone())
+                .collect()
+        })?;
+        names.sort();
+        names.dedup();
+
+        Ok(names)
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        // collect 在 order 锁内把请求发给所有 shard，各 shard 看到的是同一时刻的数据
+        let tables = self.collect(|data| {
+            data.iter()
+                .map(|(name, t)| {
+                    let pairs = t.iter().map(|(k, v)| KvPair::new(k, v.clone())).collect();
+                    (name.clone(), pairs)
+                })
+                .collect()
+        })?;
+
+        Ok(Arc::new(MemSnapshot::new(tables)))
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        let total = ops.len();
+        let mut groups: HashMap<usize, Vec<(usize, WriteOp)>> = HashMap::new();
+
+        for (i, op) in ops.into_iter().enumerate() {
+            let shard = self.shard_of(op.table(), op.key());
+            groups.entry(shard).or_default().push((i, op));
+        }
+
+        let order = self.order();
+        let receivers = groups
+            .into_iter()
+            .map(|(shard, ops)| {
+                let rx = self.send(shard, move |data| {
+                    ops.into_iter()
+                        .map(|(i, op)| {
+                            let old = match op {
+                                WriteOp::Set { table, key, value } => {
+                                    data.entry(table).or_default().insert(key, value)
+                                }
+                                WriteOp::Del { table, key } => {
+                                    data.get_mut(&table).and_then(|t| t.remove(&key))
+                                }
+                            };
+                            (i, old)
+                        })
+                        .collect::<Vec<_>>()
+                })?;
+
+                Ok((shard, rx))
+            })
+            .collect::<Result<Vec<_>, KVError>>()?;
+        drop(order);
+
+        gather(receivers, total)
+    }
+
+    fn get_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KVError> {
+        self.fan_out(
+            table,
+            keys,
+            |k| k,
+            |t, k| t.and_then(|t| t.get(&k)).cloned(),
+        )
+    }
+
+    fn contains_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<bool>, KVError> {
+        self.fan_out(
+            table,
+            keys,
+            |k| k,
+            |t, k| t.is_some_and(|t| t.contains_key(&k)),
+        )
+    }
+}
+
+// 收集各个 shard 返回的 (原始位置, 结果)，按原始顺序排列
+fn gather<T>(receivers: Vec<(usize, Replies<T>)>, total: usize) -> Result<Vec<T>, KVError> {
+    let mut results: Vec<Option<T>> = (0..total).map(|_| None).collect();
+
+    for (shard, rx) in receivers {
+        for (i, v) in rx.recv().map_err(|_| shard_gone(shard))? {
+            results[i] = Some(v);
+        }
+    }
+
+    Ok(results.into_iter().flatten().collect())
+}
+
+fn shard_gone(shard: usize) -> KVError {
+    KVError::InternalError(format!("Shard {} is not running", shard))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CommandRequest, Service, ServiceInner, assert_res_ok};
+
+    #[test]
+    fn sharded_table_should_spread_keys_across_shards() {
+        let store = ShardedTable::new(4);
+        let shards: std::collections::HashSet<_> = (0..100)
+            .map(|i| store.shard_of("t1", &format!("k{}", i)))
+            .collect();
+
+        assert_eq!(store.shards(), 4);
+        assert_eq!(shards.len(), 4);
+    }
+
+    #[test]
+    fn sharded_table_multi_key_ops_should_keep_order() {
+        let store = ShardedTable::new(4);
+        let pairs: Vec<_> = (0..50)
+            .map(|i| KvPair::new(format!("k{}", i), (i as i64).into()))
+            .collect();
+
+        let old = store.set_many("t1", pairs).unwrap();
+        assert!(old.iter().all(|v| v.is_none()));
+
+        let keys: Vec<_> = (0..50).rev().map(|i| format!("k{}", i)).collect();
+        let values = store.get_many("t1", keys.clone()).unwrap();
+        let expected: Vec<_> = (0..50).rev().map(|i| Some((i as i64).into())).collect();
+        assert_eq!(values, expected);
+
+        let exists = store
+            .contains_many("t1", vec!["k1".into(), "nope".into()])
+            .unwrap();
+        assert_eq!(exists, vec![true, false]);
+
+        let removed = store.del_many("t1", keys).unwrap();
+        assert_eq!(removed, expected);
+        assert!(store.get_all("t1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn multi_key_reads_should_not_create_tables() {
+        let store = ShardedTable::new(4);
+        let keys: Vec<_> = (0..20).map(|i| format!("k{}", i)).collect();
+
+        assert!(
+            store
+                .get_many("t1", keys.clone())
+                .unwrap()
+                .iter()
+                .all(|v| v.is_none())
+        );
+        assert!(store.contains_many("t1", keys).unwrap().iter().all(|v| !v));
+
+        // tables() 会过滤空表，这里直接检查各 shard 的数据
+        let names = store
+            .collect(|data| data.keys().cloned().collect())
+            .unwrap();
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn sharded_table_should_work_with_service() {
+        let service: Service<ShardedTable> = ServiceInner::new(ShardedTable::new(2)).into();
+        let cloned = service.clone();
+
+        let handle = thread::spawn(move || {
+            let pairs = vec![
+                KvPair::new("k1", "v1".into()),
+                KvPair::new("k2", "v2".into()),
+            ];
+            cloned.execute(CommandRequest::new_hmset("t1", pairs))
+        });
+        assert_res_ok(
+            handle.join().unwrap(),
+            &[Value::default(), Value::default()],
+            &[],
+        );
+
+        let res = service.execute(CommandRequest::new_hmget(
+            "t1",
+            vec!["k2".into(), "k1".into()],
+        ));
+        assert_res_ok(res, &["v2".into(), "v1".into()], &[]);
+    }
+
+    #[test]
+    fn write_batch_should_be_atomic_for_multi_shard_reads() {
+        let store = std::sync::Arc::new(ShardedTable::new(4));
+        // 找两个落在不同 shard 上的 key
+        let a = "k0".to_string();
+        let b = (1..)
+            .map(|i| format!("k{}", i))
+            .find(|k| store.shard_of("t1", k) != store.shard_of("t1", &a))
+            .unwrap();
+
+        let writer = {
+            let (store, a, b) = (store.clone(), a.clone(), b.clone());
+            thread::spawn(move || {
+                for i in 0..1000i64 {
+                    let ops = vec![
+                        WriteOp::set("t1", a.clone(), i.into()),
+                        WriteOp::set("t1", b.clone(), i.into()),
+                    ];
+                    store.write_batch(ops).unwrap();
+                }
+            })
+        };
+
+        while !writer.is_finished() {
+            let values = store.get_many("t1", vec![a.clone(), b.clone()]).unwrap();
+            assert_eq!(values[0], values[1]);
+        }
+        writer.join().unwrap();
+    }
+}
@@ -1,15 +1,52 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
-use sled::{Db, IVec};
+use sled::{Db, IVec, transaction::TransactionError};
 
-use crate::{KVError, KvPair, Storage, StorageIter, Value};
+use crate::{
+    CompressionConfig, CompressionStats, KVError, KvPair, Snapshot, Storage, StorageIter, Value,
+    WriteOp,
+    storage::{Capture, Compressor, skip_errors, take_ordered},
+};
 
 #[derive(Debug)]
-pub struct SledDb(Db);
+pub struct SledDb {
+    db: Db,
+    /// sled 没有快照，写操作持有读锁，snapshot 在开始和结束复制数据时持有写锁
+    gate: RwLock<()>,
+    /// snapshot 复制数据期间被修改的 key 原来的 value
+    capture: Capture,
+    compressor: Compressor,
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self::with_compression(path, CompressionConfig::default())
+    }
+
+    /// 按 config 压缩写入的 value，已有的未压缩数据仍然可以读取
+    pub fn with_compression(path: impl AsRef<Path>, config: CompressionConfig) -> Self {
+        Self {
+            db: sled::open(path).unwrap(),
+            gate: RwLock::new(()),
+            capture: Capture::default(),
+            compressor: Compressor::new(config),
+        }
+    }
+
+    /// 打开以来写入的 value 的压缩统计
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.compressor.stats()
+    }
+
+    fn read_gate(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_gate(&self) -> RwLockWriteGuard<'_, ()> {
+        self.gate.write().unwrap_or_else(|e| e.into_inner())
     }
 
     // 使用 prefix 模拟 table
@@ -21,69 +58,224 @@ impl SledDb {
     fn get_table_prefix(table: &str) -> String {
         format!("{}:", table)
     }
+
+    // 按 table 复制所有数据，key 按 "table:key" 排序，同一个 table 的 key 是连续的
+    fn copy_all(&self) -> Result<Vec<(String, Vec<KvPair>)>, KVError> {
+        let mut tables: Vec<(String, Vec<KvPair>)> = Vec::new();
+
+        for item in self.db.iter() {
+            let (k, v) = item?;
+            let full = str::from_utf8(&k).unwrap();
+            let (table, key) = full.split_once(':').unwrap_or(("", full));
+            let pair = KvPair::new(key, self.compressor.decode(&v)?);
+
+            match tables.last_mut() {
+                Some((t, pairs)) if t == table => pairs.push(pair),
+                _ => tables.push((table.to_owned(), vec![pair])),
+            }
+        }
+
+        Ok(tables)
+    }
+
+    fn decode(&self, v: Option<IVec>) -> Result<Option<Value>, KVError> {
+        v.map(|v| self.compressor.decode(&v)).transpose()
+    }
+
+    fn decode_pair(&self, v: Result<(IVec, IVec), sled::Error>) -> Result<KvPair, KVError> {
+        let (k, v) = v?;
+
+        Ok(KvPair::new(
+            ivec_to_key(k.as_ref()),
+            self.compressor.decode(&v)?,
+        ))
+    }
 }
 
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
         let name = SledDb::get_full_key(table, key);
 
-        self.0.get(name)?.map(|v| v.as_ref().try_into()).transpose()
+        self.decode(self.db.get(name)?)
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
         let name = SledDb::get_full_key(table, &key);
-        let data: Vec<u8> = value.try_into()?;
+        let data = self.compressor.encode(table, value)?;
+        let _gate = self.read_gate();
 
-        self.0
-            .insert(name, data)?
-            .map(|v| v.as_ref().try_into())
-            .transpose()
+        let old = self.decode(self.db.insert(name, data)?)?;
+        self.capture.record(table, &key, old.as_ref());
+        Ok(old)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
         let name = SledDb::get_full_key(table, key);
 
-        Ok(self.0.contains_key(name)?)
+        Ok(self.db.contains_key(name)?)
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
         let name = SledDb::get_full_key(table, key);
+        let _gate = self.read_gate();
 
-        self.0
-            .remove(name)?
-            .map(|v| v.as_ref().try_into())
-            .transpose()
+        let old = self.decode(self.db.remove(name)?)?;
+        self.capture.record(table, key, old.as_ref());
+        Ok(old)
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
         let prefix = SledDb::get_table_prefix(table);
 
-        Ok(self.0.scan_prefix(prefix).map(|v| v.into()).collect())
+        self.db
+            .scan_prefix(prefix)
+            .map(|v| self.decode_pair(v))
+            .collect()
     }
 
     fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
         let prefix = SledDb::get_table_prefix(table);
 
-        Ok(StorageIter::new(self.0.scan_prefix(prefix)))
+        Ok(StorageIter::new(skip_errors(
+            self.db.scan_prefix(prefix).map(|v| self.decode_pair(v)),
+        )))
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let mut names = Vec::new();
+        let mut from = String::new();
+
+        // 每找到一个 table，就从 "table;" 开始继续找，跳过这个 table 剩下的 key
+        while let Some((k, _)) = self.db.range(from.as_str()..).next().transpose()? {
+            let key = str::from_utf8(&k).unwrap();
+            let name = key.split_once(':').map_or(key, |(t, _)| t);
+
+            from = format!("{};", name);
+            names.push(name.to_owned());
+        }
+
+        Ok(names)
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        // 只在开始和结束时持有写锁，复制期间的写操作由 capture 记录，见 Capture
+        let id = {
+            let _gate = self.write_gate();
+            self.capture.start()
+        };
+        let copied = self.copy_all();
+
+        let _gate = self.write_gate();
+        let snapshot = self.capture.finish(id, copied)?;
+
+        Ok(Arc::new(snapshot))
     }
-}
 
-impl From<Result<(IVec, IVec), sled::Error>> for KvPair {
-    fn from(v: Result<(IVec, IVec), sled::Error>) -> Self {
-        match v {
-            Ok((k, v)) => match v.as_ref().try_into() {
-                Ok(v) => KvPair::new(ivec_to_key(k.as_ref()), v),
-                Err(_) => KvPair::default(),
-            },
-            _ => KvPair::default(),
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let lower = SledDb::get_full_key(table, start);
+        // ';' 是 ':' 的下一个字符，"table;" 比 table 中所有的 key 都大
+        let upper = match end {
+            Some(end) => SledDb::get_full_key(table, end),
+            None => format!("{};", table),
+        };
+
+        if lower >= upper {
+            return Ok(Vec::new());
         }
+
+        take_ordered(self.db.range(lower..upper), limit, reverse)
+            .into_iter()
+            .map(|v| self.decode_pair(v))
+            .collect()
+    }
+
+    fn get_prefix(&self, table: &str, prefix: &str) -> Result<Vec<KvPair>, KVError> {
+        self.db
+            .scan_prefix(SledDb::get_full_key(table, prefix))
+            .map(|v| self.decode_pair(v))
+            .collect()
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        // 先完成所有 Value 的编码，事务中只做读写
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Set { table, key, value } => Ok((
+                    SledDb::get_full_key(&table, &key),
+                    Some(self.compressor.encode(&table, value)?),
+                )),
+                WriteOp::Del { table, key } => Ok((SledDb::get_full_key(&table, &key), None)),
+            })
+            .collect::<Result<Vec<(String, Option<Vec<u8>>)>, KVError>>()?;
+        let _gate = self.read_gate();
+
+        let old = self
+            .db
+            .transaction(|tx| {
+                let mut old = Vec::with_capacity(ops.len());
+
+                for (key, value) in &ops {
+                    old.push(match value {
+                        Some(v) => tx.insert(key.as_str(), v.as_slice())?,
+                        None => tx.remove(key.as_str())?,
+                    });
+                }
+
+                Ok(old)
+            })
+            .map_err(|e: TransactionError<()>| match e {
+                TransactionError::Storage(e) => KVError::from(e),
+                TransactionError::Abort(_) => KVError::InternalError("Transaction aborted".into()),
+            })?;
+
+        let old = old
+            .into_iter()
+            .map(|v| self.decode(v))
+            .collect::<Result<Vec<_>, _>>()?;
+        for ((key, _), v) in ops.iter().zip(&old) {
+            let (table, key) = key.split_once(':').unwrap_or(("", key));
+            self.capture.record(table, key, v.as_ref());
+        }
+
+        Ok(old)
     }
 }
 
+// 去掉 "table:" 前缀，key 中的 ':' 保留
 fn ivec_to_key(ivec: &[u8]) -> &str {
     let s = str::from_utf8(ivec).unwrap();
-    let mut iter = s.split(":");
 
-    iter.next();
-    iter.next().unwrap()
+    s.split_once(':').map_or(s, |(_, key)| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Codec;
+
+    #[test]
+    fn compression_stats_should_be_tracked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = CompressionConfig::new(Codec::Lz4);
+        config.tables.insert("raw".into(), None);
+        let store = SledDb::with_compression(&dir, config);
+
+        let large: Value = "compress me ".repeat(100).into();
+        store.set("t1", "k1".into(), large.clone()).unwrap();
+        store.set("t1", "k2".into(), "small".into()).unwrap();
+        store.set("raw", "k1".into(), large).unwrap();
+
+        let stats = store.compression_stats();
+        assert_eq!((stats.values, stats.compressed), (3, 1));
+        assert!(stats.raw_bytes > stats.stored_bytes);
+        assert!(stats.ratio() > 1.0);
+    }
 }
@@ -0,0 +1,217 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use crate::{KVError, KvPair, Value};
+
+/// 某个时间点的只读视图，创建之后的写入对它不可见
+pub trait Snapshot: Send + Sync {
+    /// 获取快照中 key 的 value
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError>;
+    /// 按 key 的顺序返回 table 中 [start, end) 范围内最多 limit 个 kv pair，limit 为 0 表示不限数量
+    fn scan(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KvPair>, KVError>;
+    /// 快照中所有有数据的 table，按名字排序
+    fn tables(&self) -> Result<Vec<String>, KVError>;
+}
+
+/// 把数据完整复制一份的快照，没有 MVCC 的存储配合 Capture 用它冻结数据
+#[derive(Debug, Clone, Default)]
+pub struct MemSnapshot {
+    tables: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+impl MemSnapshot {
+    /// 用 (table, kv pairs) 创建快照，同一个 table 可以出现多次
+    pub fn new(tables: impl IntoIterator<Item = (String, Vec<KvPair>)>) -> Self {
+        let mut snapshot = Self::default();
+
+        for (table, pairs) in tables {
+            if pairs.is_empty() {
+                continue;
+            }
+
+            snapshot.tables.entry(table).or_default().extend(
+                pairs
+                    .into_iter()
+                    .map(|p| (p.key, p.value.unwrap_or_default())),
+            );
+        }
+
+        snapshot
+    }
+
+    // 用修改之前的 value 覆盖快照中的数据，None 表示当时 key 不存在
+    fn restore(&mut self, changes: Changes) {
+        for ((table, key), old) in changes {
+            match old {
+                Some(v) => {
+                    self.tables.entry(table).or_default().insert(key, v);
+                }
+                None => {
+                    if let Some(t) = self.tables.get_mut(&table) {
+                        t.remove(&key);
+                        if t.is_empty() {
+                            self.tables.remove(&table);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// (table, key) 在第一次被修改之前的 value
+type Changes = HashMap<(String, String), Option<Value>>;
+
+/// 一次复制期间的修改记录，table 不为 None 时只记录这个 table
+#[derive(Debug, Default)]
+struct Log {
+    table: Option<String>,
+    changes: Changes,
+}
+
+/// 让没有 MVCC 的存储不阻塞写操作地创建一致的快照
+///
+/// 快照在写锁内 start，然后不持锁复制数据；期间的写操作（在读锁内）用 record 记下被修改的 key 原来的 value，
+/// 复制完成后 finish（同样在写锁内）用这些 value 覆盖复制到的数据，得到的就是 start 时的状态。
+/// 写锁只在开始和结束时持有很短的时间，写操作只在有快照正在复制时才需要记录。
+#[derive(Debug, Default)]
+pub(crate) struct Capture {
+    active: AtomicUsize,
+    next_id: AtomicU64,
+    logs: Mutex<HashMap<u64, Log>>,
+}
+
+impl Capture {
+    /// 开始记录，需要在存储的写锁内调用
+    pub(crate) fn start(&self) -> u64 {
+        self.start_log(Log::default())
+    }
+
+    /// 和 start 一样，但只记录 table 中的修改
+    pub(crate) fn start_table(&self, table: &str) -> u64 {
+        self.start_log(Log {
+            table: Some(table.to_owned()),
+            changes: Changes::new(),
+        })
+    }
+
+    fn start_log(&self, log: Log) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.logs().insert(id, log);
+        self.active.fetch_add(1, Ordering::SeqCst);
+        id
+    }
+
+    /// 记录 key 被修改之前的 value，只有每个快照开始后的第一次修改有意义
+    pub(crate) fn record(&self, table: &str, key: &str, old: Option<&Value>) {
+        if self.active.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        for log in self.logs().values_mut() {
+            if log.table.as_deref().is_some_and(|t| t != table) {
+                continue;
+            }
+
+            log.changes
+                .entry((table.to_owned(), key.to_owned()))
+                .or_insert_with(|| old.cloned());
+        }
+    }
+
+    /// key 在开始记录之后第一次被修改之前的 value，没有被修改过时返回 None；需要在存储的写锁内调用
+    pub(crate) fn original(&self, id: u64, table: &str, key: &str) -> Option<Option<Value>> {
+        self.logs()
+            .get(&id)?
+            .changes
+            .get(&(table.to_owned(), key.to_owned()))
+            .cloned()
+    }
+
+    /// 停止记录，返回 table 中所有被修改过的 key 原来的 value；需要在存储的写锁内调用
+    pub(crate) fn finish_table(&self, id: u64, table: &str) -> Vec<(String, Option<Value>)> {
+        self.stop(id)
+            .into_iter()
+            .filter(|((t, _), _)| t == table)
+            .map(|((_, k), v)| (k, v))
+            .collect()
+    }
+
+    /// 停止记录，丢弃记录的修改
+    pub(crate) fn cancel(&self, id: u64) {
+        self.stop(id);
+    }
+
+    fn stop(&self, id: u64) -> Changes {
+        let Some(log) = self.logs().remove(&id) else {
+            return Changes::new();
+        };
+        self.active.fetch_sub(1, Ordering::SeqCst);
+
+        log.changes
+    }
+
+    /// 停止记录，用记录的 value 修正复制到的数据；需要在存储的写锁内调用，复制失败时也要调用
+    pub(crate) fn finish(
+        &self,
+        id: u64,
+        copied: Result<Vec<(String, Vec<KvPair>)>, KVError>,
+    ) -> Result<MemSnapshot, KVError> {
+        let changes = self.stop(id);
+
+        let mut snapshot = MemSnapshot::new(copied?);
+        snapshot.restore(changes);
+        Ok(snapshot)
+    }
+
+    fn logs(&self) -> MutexGuard<'_, HashMap<u64, Log>> {
+        self.logs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Snapshot for MemSnapshot {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        Ok(self.tables.get(table).and_then(|t| t.get(key)).cloned())
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KvPair>, KVError> {
+        let Some(t) = self.tables.get(table) else {
+            return Ok(Vec::new());
+        };
+
+        // BTreeMap::range 在 start > end 时会 panic
+        let iter = match end {
+            Some(end) if end <= start => return Ok(Vec::new()),
+            Some(end) => t.range::<str, _>((Bound::Included(start), Bound::Excluded(end))),
+            None => t.range::<str, _>((Bound::Included(start), Bound::Unbounded)),
+        };
+        let limit = if limit == 0 { usize::MAX } else { limit };
+
+        Ok(iter
+            .take(limit)
+            .map(|(k, v)| KvPair::new(k, v.clone()))
+            .collect())
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        Ok(self.tables.keys().cloned().collect())
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use prost::Message;
+
+use crate::{Evicted, KVError, KvPair, Snapshot, Storage, Value, Version, VersionHistory, WriteOp};
+
+/// table 的历史版本保存在 "__history__table" 中，key 不变，value 是编码后的 VersionHistory
+const HISTORY_PREFIX: &str = "__history__";
+
+/// 在任意 Storage 之上为每个 key 保留最近 max_versions 个版本的存储
+///
+/// 最新的 value 仍然保存在原来的 table 中，读操作直接访问 inner；
+/// 写操作和对应的历史记录放在同一个 write_batch 中，保证两者一起生效。
+pub struct VersionedStore<S> {
+    inner: S,
+    max_versions: usize,
+    /// 写操作需要先读出历史再写回，持有锁避免并发写丢失版本
+    lock: Mutex<()>,
+}
+
+/// 一个 key 在 batch 执行过程中的历史版本
+struct Staged {
+    versions: Vec<Version>,
+    live: bool,
+    changed: bool,
+}
+
+impl<S: Storage> VersionedStore<S> {
+    /// max_versions 至少为 1
+    pub fn new(inner: S, max_versions: usize) -> Self {
+        Self {
+            inner,
+            max_versions: max_versions.max(1),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn max_versions(&self) -> usize {
+        self.max_versions
+    }
+
+    /// key 当前的版本号，key 不存在（或者已经被删除）时为 0
+    pub fn version(&self, table: &str, key: &str) -> Result<u64, KVError> {
+        Ok(current_version(&self.load(table, key)?))
+    }
+
+    /// 只有 key 当前的版本号等于 expected 时才写入，返回新的版本号；expected 为 0 表示 key 必须不存在
+    pub fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        expected: u64,
+    ) -> Result<u64, KVError> {
+        let _lock = self.lock();
+
+        let actual = current_version(&self.load(table, &key)?);
+        if actual != expected {
+            return Err(KVError::VersionMismatch(
+                table.into(),
+                key,
+                expected,
+                actual,
+            ));
+        }
+
+        self.write_locked(vec![WriteOp::set(table, key.clone(), value)])?;
+
+        Ok(current_version(&self.load(table, &key)?))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn load(&self, table: &str, key: &str) -> Result<Vec<Version>, KVError> {
+        match self.inner.get(&history_table(table), key)? {
+            Some(v) => Ok(VersionHistory::decode(Bytes::try_from(v)?)?.versions),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn write_locked(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        if let Some(op) = ops.iter().find(|op| is_history_table(op.table())) {
+            return Err(KVError::InvalidCommand(format!(
+                "Table {} is reserved",
+                op.table()
+            )));
+        }
+
+        let now = now_ms();
+        let mut staged: HashMap<(String, String), Staged> = HashMap::new();
+
+        for op in &ops {
+            let k = (op.table().to_owned(), op.key().to_owned());
+            let entry = match staged.entry(k) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let versions = self.load(op.table(), op.key())?;
+                    // 包装之前写入的 key 没有历史，以 inner 中是否存在为准
+                    let live = match versions.first() {
+                        Some(v) => !v.deleted,
+                        None => self.inner.contains(op.table(), op.key())?,
+                    };
+                    e.insert(Staged {
+                        versions,
+                        live,
+                        changed: false,
+                    })
+                }
+            };
+
+            let version = entry.versions.first().map_or(1, |v| v.version + 1);
+            let new = match op {
+                WriteOp::Set { value, .. } => Version {
+                    version,
+                    timestamp: now,
+                    value: Some(value.clone()),
+                    deleted: false,
+                },
+                // 删除不存在的 key 不产生新版本
+                WriteOp::Del { .. } if !entry.live => continue,
+                WriteOp::Del { .. } => Version {
+                    version,
+                    timestamp: now,
+                    value: None,
+                    deleted: true,
+                },
+            };
+
+            entry.live = !new.deleted;
+            entry.changed = true;
+            entry.versions.insert(0, new);
+            entry.versions.truncate(self.max_versions);
+        }
+
+        let n = ops.len();
+        let mut all = ops;
+        all.extend(
+            staged
+                .into_iter()
+                .filter(|(_, s)| s.changed)
+                .map(|((table, key), s)| {
+                    let data = VersionHistory {
+                        versions: s.versions,
+                    }
+                    .encode_to_vec();
+                    WriteOp::set(history_table(&table), key, Bytes::from(data).into())
+                }),
+        );
+
+        let mut old = self.inner.write_batch(all)?;
+        old.truncate(n);
+
+        Ok(old)
+    }
+}
+
+impl<S: Storage> Storage for VersionedStore<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        self.inner.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KVError> {
+        let mut old = self.write_batch(vec![WriteOp::set(table, key, value)])?;
+        Ok(old.remove(0))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KVError> {
+        self.inner.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        let mut old = self.write_batch(vec![WriteOp::del(table, key)])?;
+        Ok(old.remove(0))
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<KvPair>, KVError> {
+        self.inner.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<impl Iterator<Item = KvPair>, KVError> {
+        self.inner.get_iter(table)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let mut tables = self.inner.tables()?;
+        tables.retain(|t| !is_history_table(t));
+        Ok(tables)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KVError> {
+        let _lock = self.lock();
+        self.write_locked(ops)
+    }
+
+    fn get_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KVError> {
+        self.inner.get_many(table, keys)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<KvPair>, KVError> {
+        self.inner.get_range(table, start, end, limit, reverse)
+    }
+
+    fn get_prefix(&self, table: &str, prefix: &str) -> Result<Vec<KvPair>, KVError> {
+        self.inner.get_prefix(table, prefix)
+    }
+
+    fn contains_many(&self, table: &str, keys: Vec<String>) -> Result<Vec<bool>, KVError> {
+        self.inner.contains_many(table, keys)
+    }
+
+    fn snapshot(&self) -> Result<Arc<dyn Snapshot>, KVError> {
+        Ok(Arc::new(VersionedSnapshot(self.inner.snapshot()?)))
+    }
+
+    fn history(&self, table: &str, key: &str) -> Result<Vec<Version>, KVError> {
+        self.load(table, key)
+    }
+
+    fn drop_tables(&self, prefix: &str) -> Result<usize, KVError> {
+        let _lock = self.lock();
+        // 所有 key 都被删除的 table 不出现在 tables() 中，但它的历史版本还在，需要单独删除
+        let deleted = self.inner.drop_tables(prefix)?;
+        self.inner.drop_tables(&history_table(prefix))?;
+
+        Ok(deleted)
+    }
+
+    fn drain_evicted(&self) -> Vec<Evicted> {
+        self.inner.drain_evicted()
+    }
+}
+
+/// 隐藏历史版本 table 的快照
+struct VersionedSnapshot(Arc<dyn Snapshot>);
+
+impl Snapshot for VersionedSnapshot {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KVError> {
+        self.0.get(table, key)
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KvPair>, KVError> {
+        self.0.scan(table, start, end, limit)
+    }
+
+    fn tables(&self) -> Result<Vec<String>, KVError> {
+        let mut tables = self.0.tables()?;
+        tables.retain(|t| !is_history_table(t));
+        Ok(tables)
+    }
+}
+
+fn current_version(versions: &[Version]) -> u64 {
+    versions
+        .first()
+        .filter(|v| !v.deleted)
+        .map_or(0, |v| v.version)
+}
+
+fn history_table(table: &str) -> String {
+    format!("{}{}", HISTORY_PREFIX, table)
+}
+
+fn is_history_table(table: &str) -> bool {
+    table.starts_with(HISTORY_PREFIX)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, SledDb};
+    use std::{thread, time::Duration};
+    use tempfile::tempdir;
+
+    #[test]
+    fn memtable_versions_should_work() {
+        test_versions(VersionedStore::new(MemTable::new(), 3));
+    }
+
+    #[test]
+    fn sled_db_versions_should_work() {
+        let dir = tempdir().unwrap();
+        test_versions(VersionedStore::new(SledDb::new(dir), 3));
+    }
+
+    #[test]
+    fn memtable_as_of_should_work() {
+        test_as_of(VersionedStore::new(MemTable::new(), 3));
+    }
+
+    #[test]
+    fn sled_db_as_of_should_work() {
+        let dir = tempdir().unwrap();
+        test_as_of(VersionedStore::new(SledDb::new(dir), 3));
+    }
+
+    #[test]
+    fn set_if_version_should_detect_conflicts() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+
+        assert_eq!(
+            store.set_if_version("t1", "k1".into(), "v1".into(), 0),
+            Ok(1)
+        );
+        assert_eq!(
+            store.set_if_version("t1", "k1".into(), "v2".into(), 0),
+            Err(KVError::VersionMismatch("t1".into(), "k1".into(), 0, 1))
+        );
+        assert_eq!(
+            store.set_if_version("t1", "k1".into(), "v2".into(), 1),
+            Ok(2)
+        );
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v2".into())));
+
+        // 删除之后 key 的版本号为 0，新的写入继续递增
+        store.del("t1", "k1").unwrap();
+        assert_eq!(store.version("t1", "k1"), Ok(0));
+        assert_eq!(
+            store.set_if_version("t1", "k1".into(), "v3".into(), 0),
+            Ok(4)
+        );
+    }
+
+    #[test]
+    fn history_tables_should_be_hidden() {
+        let store = VersionedStore::new(MemTable::new(), 3);
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        assert_eq!(store.tables(), Ok(vec!["t1".into()]));
+        assert_eq!(store.snapshot().unwrap().tables(), Ok(vec!["t1".into()]));
+        assert!(matches!(
+            store.set("__history__t1", "k1".into(), "v1".into()),
+            Err(KVError::InvalidCommand(_))
+        ));
+    }
+
+    #[test]
+    fn unversioned_storage_should_reject_history() {
+        let store = MemTable::new();
+        assert!(matches!(
+            store.history("t1", "k1"),
+            Err(KVError::InvalidCommand(_))
+        ));
+    }
+
+    fn test_versions(store: impl Storage) {
+        assert_eq!(store.history("t1", "k1"), Ok(vec![]));
+
+        for i in 1..=4 {
+            store.set("t1", "k1".into(), i.into()).unwrap();
+        }
+        assert_eq!(store.get("t1", "k1"), Ok(Some(4.into())));
+
+        // 只保留最近的 3 个版本，从新到旧
+        let history = store.history("t1", "k1").unwrap();
+        let versions: Vec<_> = history.iter().map(|v| v.version).collect();
+        assert_eq!(versions, vec![4, 3, 2]);
+        assert_eq!(history[0].value, Some(4.into()));
+
+        assert_eq!(
+            store.get_version("t1", "k1", 3).unwrap().unwrap().value,
+            Some(3.into())
+        );
+        assert_eq!(store.get_version("t1", "k1", 1), Ok(None));
+
+        // 删除产生一个没有 value 的版本，删除不存在的 key 不产生版本
+        assert_eq!(store.del("t1", "k1"), Ok(Some(4.into())));
+        assert_eq!(store.del("t1", "k1"), Ok(None));
+        let history = store.history("t1", "k1").unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history[0].deleted);
+        assert_eq!(history[0].version, 5);
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+
+        // batch 中对同一个 key 的多次写入各自产生版本
+        store
+            .write_batch(vec![
+                WriteOp::set("t1", "k2", "a".into()),
+                WriteOp::set("t1", "k2", "b".into()),
+                WriteOp::set("t2", "k1", "c".into()),
+            ])
+            .unwrap();
+        let versions: Vec<_> = store
+            .history("t1", "k2")
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.version, v.value))
+            .collect();
+        assert_eq!(versions, vec![(2, Some("b".into())), (1, Some("a".into()))]);
+        assert_eq!(store.tables(), Ok(vec!["t1".into(), "t2".into()]));
+    }
+
+    fn test_as_of(store: impl Storage) {
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        let t1 = now_ms();
+        thread::sleep(Duration::from_millis(5));
+        store.set("t1", "k1".into(), "v2".into()).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        let t2 = now_ms();
+        thread::sleep(Duration::from_millis(5));
+        store.del("t1", "k1").unwrap();
+
+        assert_eq!(store.get_as_of("t1", "k1", t1), Ok(Some("v1".into())));
+        assert_eq!(store.get_as_of("t1", "k1", t2), Ok(Some("v2".into())));
+        assert_eq!(store.get_as_of("t1", "k1", now_ms()), Ok(None));
+        // 第一次写入之前 key 不存在
+        assert_eq!(store.get_as_of("t1", "k1", 1), Ok(None));
+    }
+}